@@ -68,3 +68,228 @@ pub fn out32(port: IOPort, value: u32) {
         );
     }
 }
+
+pub fn read_cr0() -> u32 {
+    let result;
+    unsafe {
+        core::arch::asm!("mov {}, cr0", out(reg) result);
+    }
+    result
+}
+
+/// # Safety
+/// Changing cr0 alters protection and paging for all the following instructions
+pub unsafe fn write_cr0(value: u32) {
+    unsafe {
+        core::arch::asm!("mov cr0, {}", in(reg) value);
+    }
+}
+
+/// Address that caused the last page fault
+pub fn read_cr2() -> u32 {
+    let result;
+    unsafe {
+        core::arch::asm!("mov {}, cr2", out(reg) result);
+    }
+    result
+}
+
+pub fn read_cr3() -> u32 {
+    let result;
+    unsafe {
+        core::arch::asm!("mov {}, cr3", out(reg) result);
+    }
+    result
+}
+
+/// # Safety
+/// `value` must point to a valid paging structure that maps the executing code
+pub unsafe fn write_cr3(value: u32) {
+    unsafe {
+        core::arch::asm!("mov cr3, {}", in(reg) value);
+    }
+}
+
+pub fn read_cr4() -> u32 {
+    let result;
+    unsafe {
+        core::arch::asm!("mov {}, cr4", out(reg) result);
+    }
+    result
+}
+
+/// # Safety
+/// Changing cr4 alters how paging structures are interpreted
+pub unsafe fn write_cr4(value: u32) {
+    unsafe {
+        core::arch::asm!("mov cr4, {}", in(reg) value);
+    }
+}
+
+/// Drop the TLB entry of the page containing `address`
+pub fn invlpg(address: usize) {
+    unsafe {
+        core::arch::asm!("invlpg [{}]", in(reg) address, options(nostack));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+pub fn cpuid(leaf: u32) -> CpuidResult {
+    // ebx is reserved by LLVM, the intrinsic takes care of saving it
+    #[allow(unused_unsafe)]
+    let result = unsafe { core::arch::x86::__cpuid(leaf) };
+    CpuidResult {
+        eax: result.eax,
+        ebx: result.ebx,
+        ecx: result.ecx,
+        edx: result.edx,
+    }
+}
+
+/// Operand of `lidt` and `lgdt`
+#[repr(C, packed)]
+pub struct DescriptorTablePointer {
+    /// Size of the table minus one
+    pub limit: u16,
+    pub base: u32,
+}
+
+/// # Safety
+/// `pointer` must describe a valid interrupt descriptor table that lives as
+/// long as it is loaded
+pub unsafe fn lidt(pointer: &DescriptorTablePointer) {
+    unsafe {
+        core::arch::asm!("lidt [{}]", in(reg) pointer, options(readonly, nostack));
+    }
+}
+
+/// # Safety
+/// `pointer` must describe a valid global descriptor table that lives as
+/// long as it is loaded
+pub unsafe fn lgdt(pointer: &DescriptorTablePointer) {
+    unsafe {
+        core::arch::asm!("lgdt [{}]", in(reg) pointer, options(readonly, nostack));
+    }
+}
+
+/// Reload every segment register from the loaded global descriptor table
+///
+/// # Safety
+/// `code` and `data` must select valid code and data segments
+pub unsafe fn load_segments(code: u16, data: u16) {
+    unsafe {
+        core::arch::asm!(
+            // cs can only be changed by a far jump, call or return
+            "push {code:e}",
+            "lea {scratch}, [2f]",
+            "push {scratch}",
+            "retf",
+            "2:",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "mov fs, {data:x}",
+            "mov gs, {data:x}",
+            "mov ss, {data:x}",
+            code = in(reg) code as u32,
+            data = in(reg) data,
+            scratch = out(reg) _,
+        );
+    }
+}
+
+/// Load the task register
+///
+/// # Safety
+/// `selector` must select an available task state segment descriptor
+pub unsafe fn ltr(selector: u16) {
+    unsafe {
+        core::arch::asm!("ltr {:x}", in(reg) selector, options(nostack));
+    }
+}
+
+pub fn read_cs() -> u16 {
+    let result;
+    unsafe {
+        core::arch::asm!("mov {:x}, cs", out(reg) result, options(nomem, nostack));
+    }
+    result
+}
+
+/// Model specific register
+pub type Msr = u32;
+
+pub fn read_msr(msr: Msr) -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        core::arch::asm!(
+            "rdmsr",
+            in("ecx") msr,
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack),
+        );
+    }
+    (high as u64) << 32 | low as u64
+}
+
+/// # Safety
+/// Model specific registers control core features of the CPU
+pub unsafe fn write_msr(msr: Msr, value: u64) {
+    unsafe {
+        core::arch::asm!(
+            "wrmsr",
+            in("ecx") msr,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+            options(nostack),
+        );
+    }
+}
+
+pub fn read_eflags() -> u32 {
+    let result;
+    unsafe {
+        core::arch::asm!("pushfd", "pop {}", out(reg) result, options(nomem));
+    }
+    result
+}
+
+/// Interrupt flag of EFLAGS
+pub const EFLAGS_INTERRUPTS: u32 = 1 << 9;
+
+/// Also a compiler barrier, memory accesses are not moved across it
+pub fn enable_interrupts() {
+    unsafe {
+        core::arch::asm!("sti", options(nostack));
+    }
+}
+
+/// Also a compiler barrier, memory accesses are not moved across it
+pub fn disable_interrupts() {
+    unsafe {
+        core::arch::asm!("cli", options(nostack));
+    }
+}
+
+/// Enable interrupts and wait for the next one, which cannot slip in
+/// between since `sti` only takes effect after the next instruction
+pub fn enable_interrupts_and_halt() {
+    unsafe {
+        core::arch::asm!("sti", "hlt", options(nostack));
+    }
+}
+
+pub fn read_timestamp_counter() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        core::arch::asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack));
+    }
+    (high as u64) << 32 | low as u64
+}
@@ -49,7 +49,7 @@ impl<const CAPACITY: usize, T> ArrayRing<CAPACITY, T> {
             false => None,
             true => unsafe {
                 let value = self.get_unchecked_mut(0).assume_init_read();
-                self.start = (self.start + 1) % self.len;
+                self.start = (self.start + 1) % CAPACITY;
                 self.len -= 1;
                 Some(value)
             },
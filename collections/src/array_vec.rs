@@ -9,8 +9,15 @@ pub struct ArrayVec<const CAPACITY: usize, T> {
 }
 
 impl<const CAPACITY: usize, T> ArrayVec<CAPACITY, T> {
-    pub fn new() -> Self {
-        Self::default()
+    pub const fn new() -> Self {
+        Self {
+            len: 0,
+            values: [const { MaybeUninit::uninit() }; CAPACITY],
+        }
+    }
+
+    pub const fn is_full(&self) -> bool {
+        self.len == CAPACITY
     }
 
     pub unsafe fn push_unchecked(&mut self, value: T) {
@@ -55,6 +62,22 @@ impl<const CAPACITY: usize, T> ArrayVec<CAPACITY, T> {
         }
     }
 
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        match index < self.len {
+            false => None,
+            true => unsafe {
+                let value = self.values.get_unchecked(index).assume_init_read();
+
+                // Fill the hole
+                let start = &mut self.values[index] as *mut MaybeUninit<T>;
+                core::ptr::copy(start.add(1), start, self.len - index - 1);
+
+                self.len -= 1;
+                Some(value)
+            },
+        }
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &T> {
         self.as_ref().iter()
     }
@@ -120,10 +143,7 @@ impl<const CAPACITY: usize, T> DerefMut for ArrayVec<CAPACITY, T> {
 
 impl<const CAPACITY: usize, T> Default for ArrayVec<CAPACITY, T> {
     fn default() -> Self {
-        Self {
-            len: 0,
-            values: [const { MaybeUninit::uninit() }; CAPACITY],
-        }
+        Self::new()
     }
 }
 
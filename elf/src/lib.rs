@@ -0,0 +1,161 @@
+#![no_std]
+
+//! Parser of ELF32 executables for i386
+//!
+//! Based of the [specification](https://refspecs.linuxfoundation.org/elf/elf.pdf)
+
+mod program_header;
+
+pub use program_header::{ProgramHeader, ProgramHeaders, SegmentFlags, SegmentKind};
+
+const MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const CLASS_32: u8 = 1;
+const LITTLE_ENDIAN: u8 = 1;
+const CURRENT_VERSION: u8 = 1;
+const KIND_EXECUTABLE: u16 = 2;
+const MACHINE_I386: u16 = 3;
+
+const HEADER_SIZE: usize = 52;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The file ends before a structure it describes
+    Truncated,
+    NotElf,
+    NotElf32,
+    NotLittleEndian,
+    UnsupportedVersion,
+    /// Relocatable, shared and core files cannot be run
+    NotExecutable,
+    NotI386,
+    BadProgramHeaderSize,
+    /// A segment needs a dynamic linker
+    Dynamic,
+    /// A loadable segment has more bytes in the file than in memory, or
+    /// wraps around the address space
+    BadSegment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub entry: u32,
+    pub program_header_offset: u32,
+    pub program_header_size: u16,
+    pub program_header_count: u16,
+}
+
+/// A validated executable
+#[derive(Clone, Copy)]
+pub struct Elf<'a> {
+    bytes: &'a [u8],
+    header: Header,
+}
+
+impl<'a> Elf<'a> {
+    /// Check that `bytes` is an i386 executable whose loadable segments can
+    /// be mapped as they are described
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, Error> {
+        let identification = bytes.get(..16).ok_or(Error::Truncated)?;
+        if identification[..4] != MAGIC {
+            return Err(Error::NotElf);
+        }
+        if identification[4] != CLASS_32 {
+            return Err(Error::NotElf32);
+        }
+        if identification[5] != LITTLE_ENDIAN {
+            return Err(Error::NotLittleEndian);
+        }
+        if identification[6] != CURRENT_VERSION {
+            return Err(Error::UnsupportedVersion);
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(Error::Truncated);
+        }
+        if read_u16(bytes, 16)? != KIND_EXECUTABLE {
+            return Err(Error::NotExecutable);
+        }
+        if read_u16(bytes, 18)? != MACHINE_I386 {
+            return Err(Error::NotI386);
+        }
+        if read_u32(bytes, 20)? != CURRENT_VERSION as u32 {
+            return Err(Error::UnsupportedVersion);
+        }
+
+        let header = Header {
+            entry: read_u32(bytes, 24)?,
+            program_header_offset: read_u32(bytes, 28)?,
+            program_header_size: read_u16(bytes, 42)?,
+            program_header_count: read_u16(bytes, 44)?,
+        };
+        if header.program_header_size as usize != ProgramHeader::SIZE {
+            return Err(Error::BadProgramHeaderSize);
+        }
+        let table_size = header.program_header_count as usize * ProgramHeader::SIZE;
+        let table_end = (header.program_header_offset as usize)
+            .checked_add(table_size)
+            .ok_or(Error::Truncated)?;
+        if bytes.len() < table_end {
+            return Err(Error::Truncated);
+        }
+
+        let elf = Self { bytes, header };
+        for program_header in elf.program_headers() {
+            match program_header.kind {
+                SegmentKind::Interpreter | SegmentKind::Dynamic => return Err(Error::Dynamic),
+                SegmentKind::Load => program_header.validate(bytes.len())?,
+                _ => (),
+            }
+        }
+        Ok(elf)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn entry(&self) -> u32 {
+        self.header.entry
+    }
+
+    pub fn program_headers(&self) -> ProgramHeaders<'a> {
+        let start = self.header.program_header_offset as usize;
+        let size = self.header.program_header_count as usize * ProgramHeader::SIZE;
+        ProgramHeaders::new(&self.bytes[start..start + size])
+    }
+
+    /// Segments to map in memory
+    pub fn segments(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        self.program_headers()
+            .filter(|program_header| program_header.kind == SegmentKind::Load)
+    }
+
+    /// Bytes of `segment` found in the file, the rest of its memory is zeroed
+    pub fn data(&self, segment: &ProgramHeader) -> &'a [u8] {
+        let start = segment.offset as usize;
+        &self.bytes[start..start + segment.file_size as usize]
+    }
+
+    /// Where the program headers are once the segments are mapped
+    pub fn program_headers_address(&self) -> Option<u32> {
+        if let Some(program_headers) = self
+            .program_headers()
+            .find(|program_header| program_header.kind == SegmentKind::ProgramHeaders)
+        {
+            return Some(program_headers.virtual_address);
+        }
+        let offset = self.header.program_header_offset;
+        self.segments()
+            .find(|segment| (segment.offset..segment.offset + segment.file_size).contains(&offset))
+            .map(|segment| segment.virtual_address + (offset - segment.offset))
+    }
+}
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> Result<u16, Error> {
+    let bytes = bytes.get(offset..offset + 2).ok_or(Error::Truncated)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, Error> {
+    let bytes = bytes.get(offset..offset + 4).ok_or(Error::Truncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
@@ -0,0 +1,113 @@
+use crate::{read_u32, Error};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    Null,
+    Load,
+    Dynamic,
+    Interpreter,
+    Note,
+    ProgramHeaders,
+    ThreadLocalStorage,
+    Other(u32),
+}
+
+impl From<u32> for SegmentKind {
+    fn from(value: u32) -> Self {
+        use SegmentKind::*;
+        match value {
+            0 => Null,
+            1 => Load,
+            2 => Dynamic,
+            3 => Interpreter,
+            4 => Note,
+            6 => ProgramHeaders,
+            7 => ThreadLocalStorage,
+            _ => Other(value),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentFlags(u32);
+
+impl SegmentFlags {
+    pub const EXECUTE: Self = Self(1 << 0);
+    pub const WRITE: Self = Self(1 << 1);
+    pub const READ: Self = Self(1 << 2);
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub kind: SegmentKind,
+    /// Position of the data of the segment in the file
+    pub offset: u32,
+    pub virtual_address: u32,
+    /// Bytes of the segment found in the file
+    pub file_size: u32,
+    /// Bytes of the segment in memory, past the file data they are zeroed
+    pub memory_size: u32,
+    pub flags: SegmentFlags,
+    pub align: u32,
+}
+
+impl ProgramHeader {
+    pub const SIZE: usize = 32;
+
+    fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            kind: SegmentKind::from(read_u32(bytes, 0)?),
+            offset: read_u32(bytes, 4)?,
+            virtual_address: read_u32(bytes, 8)?,
+            file_size: read_u32(bytes, 16)?,
+            memory_size: read_u32(bytes, 20)?,
+            flags: SegmentFlags(read_u32(bytes, 24)?),
+            align: read_u32(bytes, 28)?,
+        })
+    }
+
+    /// First address after the segment in memory
+    pub fn end(&self) -> u32 {
+        self.virtual_address + self.memory_size
+    }
+
+    pub(crate) fn validate(&self, file_size: usize) -> Result<(), Error> {
+        let file_end = self
+            .offset
+            .checked_add(self.file_size)
+            .ok_or(Error::Truncated)?;
+        if file_size < file_end as usize {
+            return Err(Error::Truncated);
+        }
+        if self.memory_size < self.file_size
+            || self.virtual_address.checked_add(self.memory_size).is_none()
+        {
+            return Err(Error::BadSegment);
+        }
+        Ok(())
+    }
+}
+
+pub struct ProgramHeaders<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ProgramHeaders<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+}
+
+impl Iterator for ProgramHeaders<'_> {
+    type Item = ProgramHeader;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let program_header = ProgramHeader::parse(self.bytes).ok()?;
+        self.bytes = &self.bytes[ProgramHeader::SIZE..];
+        Some(program_header)
+    }
+}
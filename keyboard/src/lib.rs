@@ -52,6 +52,10 @@ impl Keyboard {
         }
     }
 
+    pub fn is_pressed(&self, scan_code: ScanCode) -> bool {
+        self.key_statuses[scan_code as usize].is_pressed()
+    }
+
     pub fn feed(&mut self, event: Event) -> Option<&'static str> {
         let Event {
             scan_code,
@@ -49,6 +49,10 @@ _start:
   mov ebp, stack_bottom
   mov esp, stack_bottom
 
+  ; Hand the multiboot2 magic (eax) and boot information address (ebx) over
+  push ebx
+  push eax
+
   ; Jump to 
 extern entrypoint
   call entrypoint
@@ -0,0 +1,68 @@
+; Interrupt entry points
+  ; Every vector gets a stub that pushes the same frame layout (see
+  ; `interrupts::Frame` in kfs/src/interrupts.rs) before calling the Rust
+  ; dispatcher
+
+section .text
+
+extern interrupt_dispatch
+
+; Build one stub per vector
+%assign vector 0
+%rep 256
+interrupt_stub_ %+ vector:
+  ; The CPU only pushes an error code for some exceptions
+%if vector = 8 || (vector >= 10 && vector <= 14) || vector = 17 || vector = 21 || vector = 29 || vector = 30
+%else
+  push dword 0 ; Dummy error code
+%endif
+  push dword vector
+  jmp interrupt_common
+%assign vector vector + 1
+%endrep
+
+interrupt_common:
+  pushad
+  push ds
+  push es
+  push fs
+  push gs
+
+  ; Use the kernel data segment, the CPU already loaded it in ss
+  mov ax, ss
+  mov ds, ax
+  mov es, ax
+
+  cld ; The Rust ABI expects the direction flag to be clear
+  push esp ; Pointer to the frame
+  call interrupt_dispatch
+  add esp, 4
+
+; Restore the context described by the frame at the top of the stack
+global interrupt_return
+interrupt_return:
+  pop gs
+  pop fs
+  pop es
+  pop ds
+  popad
+  add esp, 8 ; Vector and error code
+  iretd
+
+; Switch to the context described by the frame given as argument, such as a
+; program entering ring 3 for the first time
+global interrupt_enter
+interrupt_enter:
+  mov esp, [esp + 4]
+  jmp interrupt_return
+
+section .rodata
+
+; Address of the stub of every vector
+global interrupt_stubs
+interrupt_stubs:
+%assign vector 0
+%rep 256
+  dd interrupt_stub_ %+ vector
+%assign vector vector + 1
+%endrep
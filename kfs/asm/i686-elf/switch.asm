@@ -0,0 +1,25 @@
+; Context switch between kernel threads
+  ; Only the registers the C calling convention asks the callee to preserve
+  ; are saved, on the stack of the thread being left (see kfs/src/task.rs)
+
+section .text
+
+; void switch_to(uint32_t *previous_esp, uint32_t next_esp)
+global switch_to
+switch_to:
+  mov eax, [esp + 4] ; Where to save the stack pointer of the current thread
+  mov edx, [esp + 8] ; Stack pointer of the next thread
+
+  push ebp
+  push ebx
+  push esi
+  push edi
+
+  mov [eax], esp
+  mov esp, edx
+
+  pop edi
+  pop esi
+  pop ebx
+  pop ebp
+  ret
@@ -14,18 +14,24 @@ fn compile_asm(input: impl AsRef<Path>, output: impl AsRef<Path>) {
         .expect("Could not wait for nasm");
 }
 
-const SOURCE_PATH: &str = "asm/i686-elf/boot.asm";
-const OBJECT_PATH: &str = "boot.o";
+/// Assembly sources and the object they compile to
+const SOURCES: &[(&str, &str)] = &[
+    ("asm/i686-elf/boot.asm", "boot.o"),
+    ("asm/i686-elf/interrupts.asm", "interrupts.o"),
+    ("asm/i686-elf/switch.asm", "switch.o"),
+];
 const STATIC_LIB_NAME: &str = "boot";
 
 fn main() {
-    compile_asm(SOURCE_PATH, OBJECT_PATH);
+    for (source_path, object_path) in SOURCES {
+        compile_asm(source_path, object_path);
+    }
 
     // Build static library
     Command::new("ar")
         .arg("rcsu") // TODO: MAYBE remove
         .arg(format!("lib{STATIC_LIB_NAME}.a"))
-        .arg(OBJECT_PATH)
+        .args(SOURCES.iter().map(|(_, object_path)| object_path))
         .spawn()
         .expect("Could not run ar")
         .wait()
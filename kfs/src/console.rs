@@ -0,0 +1,123 @@
+//! Terminal of the programs
+//!
+//! What programs write goes to the log, one entry per line. Lines submitted
+//! by the prompt go to the program the shell waits for, if any, and to the
+//! shell otherwise. Ctrl+C sends `SIGINT` to that program.
+
+use alloc::{collections::VecDeque, string::String};
+
+use sync::SpinLock;
+
+use crate::{
+    file::{self, File},
+    process::{self, ProcessId},
+    shell,
+    signal::{self, Info, Signal},
+    wait_queue::WaitQueue,
+};
+
+/// Rows left to programs, the prompt takes the last one
+pub const ROWS: usize = vga::TextBuffer::HEIGHT - 1;
+pub const COLUMNS: usize = vga::TextBuffer::WIDTH;
+
+/// Written line not terminated yet
+static OUTPUT: SpinLock<String> = SpinLock::new(String::new());
+/// Submitted bytes not read yet
+static INPUT: SpinLock<VecDeque<u8>> = SpinLock::new(VecDeque::new());
+/// Readers waiting for [INPUT]
+static INPUT_READERS: WaitQueue = WaitQueue::new();
+/// Program the shell waits for, which then gets the input
+static FOREGROUND: SpinLock<Option<ProcessId>> = SpinLock::new(None);
+
+/// The console as a file, what the standard streams of programs refer to
+pub struct Console;
+
+impl File for Console {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, file::Error> {
+        read(buffer).map_err(|()| file::Error::Interrupted)
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<usize, file::Error> {
+        write(bytes);
+        Ok(bytes.len())
+    }
+
+    fn is_terminal(&self) -> bool {
+        true
+    }
+}
+
+pub fn write(bytes: &[u8]) {
+    let mut line = OUTPUT.lock();
+    for chunk in bytes.split_inclusive(|&byte| byte == b'\n') {
+        line.push_str(&String::from_utf8_lossy(chunk));
+        if line.ends_with('\n') || log::Entry::MAX_CONTENT_LENGTH <= line.len() {
+            log::info!("{}", line.trim_end_matches('\n'));
+            line.clear();
+        }
+    }
+}
+
+/// Wait for input and copy at most one line of it to `buffer`
+///
+/// Fails when a signal interrupts the wait.
+pub fn read(buffer: &mut [u8]) -> Result<usize, ()> {
+    if buffer.is_empty() {
+        return Ok(0);
+    }
+    INPUT_READERS.wait_until(|| {
+        {
+            let mut input = INPUT.lock();
+            if !input.is_empty() {
+                let mut count = 0;
+                while count < buffer.len() {
+                    let Some(byte) = input.pop_front() else {
+                        break;
+                    };
+                    buffer[count] = byte;
+                    count += 1;
+                    if byte == b'\n' {
+                        break;
+                    }
+                }
+                return Some(Ok(count));
+            }
+        }
+        signal::is_interrupted().then_some(Err(()))
+    })
+}
+
+/// Hand `line` to the foreground program, or to the shell
+pub fn submit(line: &str) {
+    match FOREGROUND.lock().is_some() {
+        true => {
+            // Echo, the prompt is cleared once submitted
+            log::info!("{line}");
+            let mut input = INPUT.lock();
+            input.extend(line.bytes());
+            input.push_back(b'\n');
+            drop(input);
+            INPUT_READERS.wake_all();
+        }
+        false => shell::submit(line),
+    }
+}
+
+/// Interrupt the foreground program, with `SIGINT`
+pub fn interrupt() {
+    let Some(id) = *FOREGROUND.lock() else {
+        return;
+    };
+    log::info!("^C");
+    // The program may have exited already
+    let _ = process::signal(id, Some(Signal::INT), Info::kernel());
+}
+
+/// Give the input to the program `foreground`, the input it did not read
+/// is dropped once unset
+pub fn set_foreground(foreground: Option<ProcessId>) {
+    *FOREGROUND.lock() = foreground;
+    if foreground.is_none() {
+        INPUT.lock().clear();
+    }
+}
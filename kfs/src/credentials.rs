@@ -0,0 +1,134 @@
+//! Users and groups a process acts for, and what they may do
+//!
+//! Each process has a real, an effective and a saved user id, and the same
+//! three group ids. Permissions are checked against the effective ids, user
+//! [ROOT] being allowed everything. There are no supplementary groups.
+//!
+//! Based of [credentials(7)](https://man7.org/linux/man-pages/man7/credentials.7.html)
+
+pub type UserId = u32;
+pub type GroupId = u32;
+
+/// The superuser
+pub const ROOT: UserId = 0;
+
+/// Given to `setuid` and `setgid`, it means "unchanged" to other calls
+const INVALID_ID: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ids {
+    pub real: u32,
+    pub effective: u32,
+    pub saved: u32,
+}
+
+impl Ids {
+    const fn all(id: u32) -> Self {
+        Self {
+            real: id,
+            effective: id,
+            saved: id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Credentials {
+    pub user: Ids,
+    pub group: Ids,
+}
+
+impl Credentials {
+    /// Those of the kernel processes and of the programs they start
+    pub const ROOT: Self = Self::new(ROOT, 0);
+
+    pub const fn new(user: UserId, group: GroupId) -> Self {
+        Self {
+            user: Ids::all(user),
+            group: Ids::all(group),
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.user.effective == ROOT
+    }
+
+    /// `setuid`: root sets every user id, others may only set their
+    /// effective id to their real or saved one
+    pub fn set_user(&mut self, id: UserId) -> Result<(), Error> {
+        self.user = set(self.user, id, self.is_root())?;
+        Ok(())
+    }
+
+    /// `setgid`, root being the only one allowed to set every group id
+    pub fn set_group(&mut self, id: GroupId) -> Result<(), Error> {
+        self.group = set(self.group, id, self.is_root())?;
+        Ok(())
+    }
+
+    /// Whether a process with these credentials may send a signal to one
+    /// with the `target` ones
+    pub fn may_signal(&self, target: &Credentials) -> bool {
+        self.is_root()
+            || [self.user.real, self.user.effective]
+                .iter()
+                .any(|&id| id == target.user.real || id == target.user.saved)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Not an id
+    Invalid,
+    NotPermitted,
+}
+
+fn set(ids: Ids, id: u32, privileged: bool) -> Result<Ids, Error> {
+    if id == INVALID_ID {
+        return Err(Error::Invalid);
+    }
+    match privileged {
+        true => Ok(Ids::all(id)),
+        false if id == ids.real || id == ids.saved => Ok(Ids {
+            effective: id,
+            ..ids
+        }),
+        false => Err(Error::NotPermitted),
+    }
+}
+
+/// Owner, group and mode of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub owner: UserId,
+    pub group: GroupId,
+    /// The `rwx` bits of the owner, the group then the others
+    pub mode: u16,
+}
+
+impl Permissions {
+    pub const READ: u16 = 0o4;
+    pub const WRITE: u16 = 0o2;
+    pub const EXECUTE: u16 = 0o1;
+
+    /// Whether `credentials` grant every access in `wanted`, a combination
+    /// of [Permissions::READ], [Permissions::WRITE] and
+    /// [Permissions::EXECUTE]
+    ///
+    /// Root may read and write anything, but only execute files that
+    /// someone may execute.
+    pub fn allows(&self, credentials: &Credentials, wanted: u16) -> bool {
+        if credentials.is_root() {
+            let executable = self.mode & 0o111 != 0;
+            return wanted & Self::EXECUTE == 0 || executable;
+        }
+        let shift = if credentials.user.effective == self.owner {
+            6
+        } else if credentials.group.effective == self.group {
+            3
+        } else {
+            0
+        };
+        (self.mode >> shift) & wanted == wanted
+    }
+}
@@ -0,0 +1,235 @@
+//! Files programs read and write through descriptors
+//!
+//! A [File] is anything that moves bytes: the console, a pipe end, a socket,
+//! a boot module. Opening one gives an [OpenFile], the open file description of
+//! POSIX, which descriptor tables share between `dup` and `fork`. Files are
+//! closed when their last descriptor is.
+
+mod pipe;
+pub mod socket;
+mod table;
+
+use alloc::{boxed::Box, sync::Arc};
+
+use sync::SpinLock;
+
+use crate::{
+    console,
+    credentials::{self, Credentials, Permissions},
+    module,
+};
+
+pub use pipe::pipe;
+pub use socket::Socket;
+pub use table::{Table, MAX_DESCRIPTORS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Not an open descriptor, or not open for this access
+    BadDescriptor,
+    /// The descriptor table is full
+    TooManyFiles,
+    NotFound,
+    /// Boot modules can not be written
+    ReadOnly,
+    /// A signal arrived before any byte moved
+    Interrupted,
+    /// The credentials of the process do not allow the access
+    PermissionDenied,
+    /// The read end of the pipe is closed
+    BrokenPipe,
+    /// The bytes do not stay in place, such as those of a pipe
+    NotMappable,
+    /// Any other failure of a socket
+    Socket(socket::Error),
+}
+
+impl From<socket::Error> for Error {
+    fn from(error: socket::Error) -> Self {
+        match error {
+            socket::Error::Interrupted => Self::Interrupted,
+            socket::Error::BrokenPipe => Self::BrokenPipe,
+            error => Self::Socket(error),
+        }
+    }
+}
+
+pub trait File: Send + Sync {
+    /// Wait for bytes and copy them to `buffer`, 0 meaning the end of file
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize, Error> {
+        Err(Error::BadDescriptor)
+    }
+
+    /// Wait until some of `bytes` are taken, return how many
+    fn write(&self, _bytes: &[u8]) -> Result<usize, Error> {
+        Err(Error::BadDescriptor)
+    }
+
+    /// Copy the bytes at `offset` to `buffer` without waiting, for `mmap`
+    fn read_at(&self, _offset: usize, _buffer: &mut [u8]) -> Result<usize, Error> {
+        Err(Error::NotMappable)
+    }
+
+    fn is_terminal(&self) -> bool {
+        false
+    }
+
+    fn as_socket(&self) -> Option<&Socket> {
+        None
+    }
+}
+
+/// `O_RDONLY`, `O_WRONLY` or `O_RDWR`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Access {
+    pub const READ: Self = Self {
+        read: true,
+        write: false,
+    };
+    pub const WRITE: Self = Self {
+        read: false,
+        write: true,
+    };
+    pub const READ_WRITE: Self = Self {
+        read: true,
+        write: true,
+    };
+}
+
+/// A file with the access it was opened for
+pub struct OpenFile {
+    file: Box<dyn File>,
+    access: Access,
+}
+
+impl OpenFile {
+    pub fn new(file: impl File + 'static, access: Access) -> Self {
+        Self {
+            file: Box::new(file),
+            access,
+        }
+    }
+
+    pub fn access(&self) -> Access {
+        self.access
+    }
+
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        match self.access.read {
+            true => self.file.read(buffer),
+            false => Err(Error::BadDescriptor),
+        }
+    }
+
+    pub fn write(&self, bytes: &[u8]) -> Result<usize, Error> {
+        match self.access.write {
+            true => self.file.write(bytes),
+            false => Err(Error::BadDescriptor),
+        }
+    }
+
+    pub fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize, Error> {
+        match self.access.read {
+            true => self.file.read_at(offset, buffer),
+            false => Err(Error::BadDescriptor),
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        self.file.is_terminal()
+    }
+
+    pub fn socket(&self) -> Option<&Socket> {
+        self.file.as_socket()
+    }
+}
+
+/// `/dev/null`
+struct Null;
+
+impl File for Null {
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize, Error> {
+        Ok(0)
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<usize, Error> {
+        Ok(bytes.len())
+    }
+}
+
+/// Contents of a boot module, read from the start
+struct ModuleFile {
+    data: &'static [u8],
+    offset: SpinLock<usize>,
+}
+
+impl File for ModuleFile {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut offset = self.offset.lock();
+        let rest = &self.data[*offset..];
+        let count = rest.len().min(buffer.len());
+        buffer[..count].copy_from_slice(&rest[..count]);
+        *offset += count;
+        Ok(count)
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize, Error> {
+        let rest = self.data.get(offset..).unwrap_or_default();
+        let count = rest.len().min(buffer.len());
+        buffer[..count].copy_from_slice(&rest[..count]);
+        Ok(count)
+    }
+}
+
+/// Only root may open the console by its name, programs inherit it
+const CONSOLE_PERMISSIONS: Permissions = Permissions {
+    owner: credentials::ROOT,
+    group: 0,
+    mode: 0o600,
+};
+
+/// `/dev/tty` and `/dev/null`
+const DEVICE_PERMISSIONS: Permissions = Permissions {
+    owner: credentials::ROOT,
+    group: 0,
+    mode: 0o666,
+};
+
+/// Open the file at `path` for a process with `credentials`, there is no
+/// file system yet: the devices are under `/dev` and boot modules are found
+/// by the last component
+pub fn open(path: &str, access: Access, credentials: &Credentials) -> Result<Arc<OpenFile>, Error> {
+    let (file, permissions) = match path {
+        "/dev/console" => (OpenFile::new(console::Console, access), CONSOLE_PERMISSIONS),
+        "/dev/tty" => (OpenFile::new(console::Console, access), DEVICE_PERMISSIONS),
+        "/dev/null" => (OpenFile::new(Null, access), DEVICE_PERMISSIONS),
+        _ => {
+            let name = path.rsplit('/').next().unwrap_or_default();
+            let module = module::find(name).ok_or(Error::NotFound)?;
+            if access.write {
+                return Err(Error::ReadOnly);
+            }
+            let file = ModuleFile {
+                data: module.data,
+                offset: SpinLock::new(0),
+            };
+            (OpenFile::new(file, access), module::PERMISSIONS)
+        }
+    };
+    let mut wanted = 0;
+    if access.read {
+        wanted |= Permissions::READ;
+    }
+    if access.write {
+        wanted |= Permissions::WRITE;
+    }
+    match permissions.allows(credentials, wanted) {
+        true => Ok(Arc::new(file)),
+        false => Err(Error::PermissionDenied),
+    }
+}
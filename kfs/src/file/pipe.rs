@@ -0,0 +1,129 @@
+//! Anonymous pipes: a bounded buffer between a write end and a read end
+//!
+//! Based of [pipe(7)](https://man7.org/linux/man-pages/man7/pipe.7.html)
+
+use alloc::{collections::VecDeque, sync::Arc};
+
+use sync::SpinLock;
+
+use super::{Access, Error, File, OpenFile};
+use crate::{signal, wait_queue::WaitQueue};
+
+/// Bytes a pipe holds before writers wait
+const CAPACITY: usize = 4096;
+/// Writes of at most this many bytes are not interleaved with others
+const ATOMIC_SIZE: usize = CAPACITY;
+
+struct State {
+    buffer: VecDeque<u8>,
+    reader_open: bool,
+    writer_open: bool,
+}
+
+struct Pipe {
+    state: SpinLock<State>,
+    /// Woken when bytes come or the write end closes
+    readable: WaitQueue,
+    /// Woken when room frees up or the read end closes
+    writable: WaitQueue,
+}
+
+struct Reader(Arc<Pipe>);
+
+struct Writer(Arc<Pipe>);
+
+/// Create a pipe, return its read end then its write end
+pub fn pipe() -> (OpenFile, OpenFile) {
+    let pipe = Arc::new(Pipe {
+        state: SpinLock::new(State {
+            buffer: VecDeque::with_capacity(CAPACITY),
+            reader_open: true,
+            writer_open: true,
+        }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+    (
+        OpenFile::new(Reader(pipe.clone()), Access::READ),
+        OpenFile::new(Writer(pipe), Access::WRITE),
+    )
+}
+
+impl File for Reader {
+    /// Returns 0 once the buffer is empty and the write end closed
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        let pipe = &self.0;
+        pipe.readable.wait_until(|| {
+            {
+                let mut state = pipe.state.lock();
+                if !state.buffer.is_empty() {
+                    let count = state.buffer.len().min(buffer.len());
+                    for (byte, value) in buffer.iter_mut().zip(state.buffer.drain(..count)) {
+                        *byte = value;
+                    }
+                    drop(state);
+                    pipe.writable.wake_all();
+                    return Some(Ok(count));
+                }
+                if !state.writer_open {
+                    return Some(Ok(0));
+                }
+            }
+            signal::is_interrupted().then_some(Err(Error::Interrupted))
+        })
+    }
+}
+
+impl File for Writer {
+    /// Waits until every byte is taken, unless the read end closes or a
+    /// signal arrives
+    fn write(&self, bytes: &[u8]) -> Result<usize, Error> {
+        let pipe = &self.0;
+        let mut written = 0;
+        while written < bytes.len() {
+            let result = pipe.writable.wait_until(|| {
+                {
+                    let mut state = pipe.state.lock();
+                    if !state.reader_open {
+                        return Some(Err(Error::BrokenPipe));
+                    }
+                    let room = CAPACITY - state.buffer.len();
+                    let rest = &bytes[written..];
+                    // Small writes go in at once
+                    if room != 0 && (ATOMIC_SIZE < bytes.len() || rest.len() <= room) {
+                        let count = room.min(rest.len());
+                        state.buffer.extend(&rest[..count]);
+                        return Some(Ok(count));
+                    }
+                }
+                signal::is_interrupted().then_some(Err(Error::Interrupted))
+            });
+            match result {
+                Ok(count) => {
+                    written += count;
+                    pipe.readable.wake_all();
+                }
+                Err(error) if written == 0 => return Err(error),
+                Err(_) => break,
+            }
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.0.state.lock().reader_open = false;
+        self.0.writable.wake_all();
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.0.state.lock().writer_open = false;
+        self.0.readable.wake_all();
+    }
+}
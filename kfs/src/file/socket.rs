@@ -0,0 +1,623 @@
+//! UNIX domain sockets: connections and datagrams between local processes
+//!
+//! A socket is bound to a path or to a name of the abstract namespace. There
+//! is no file system, so paths live next to the abstract names and are
+//! released when their socket closes. Stream sockets listen for
+//! connections, each giving a pair of connected endpoints, while datagram
+//! sockets send to a name or to the socket they are connected to.
+//!
+//! Sent bytes are queued in the receiving endpoint, along with the open
+//! files of `SCM_RIGHTS` messages.
+//!
+//! Based of [unix(7)](https://man7.org/linux/man-pages/man7/unix.7.html)
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use sync::SpinLock;
+
+use super::{Access, File, OpenFile};
+use crate::{signal, wait_queue::WaitQueue};
+
+/// Bytes an endpoint queues before senders wait, the largest datagram too
+pub const CAPACITY: usize = 16 * 1024;
+/// Datagrams an endpoint queues before senders wait, whatever their size
+const MAX_DATAGRAMS: usize = 64;
+/// Connections waiting to be accepted, whatever the backlog asked for
+const MAX_BACKLOG: usize = 128;
+/// Open files a message carries at most
+pub const MAX_FILES: usize = 253;
+
+/// Sockets by the address they are bound to
+static NAMES: SpinLock<BTreeMap<Address, Weak<Endpoint>>> = SpinLock::new(BTreeMap::new());
+/// Abstract names of the sockets bound without a name
+static NEXT_AUTOMATIC_NAME: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Stream,
+    Datagram,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Address {
+    Path(String),
+    /// Name given after a null byte, which no path starts with
+    Abstract(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The socket is not in a state the operation applies to
+    Invalid,
+    /// Only stream sockets listen and accept
+    NotSupported,
+    AddressInUse,
+    /// No socket is bound to the path
+    NotFound,
+    /// No socket listens or receives at the address
+    ConnectionRefused,
+    /// The socket at the address is of another kind
+    WrongKind,
+    AlreadyConnected,
+    NotConnected,
+    /// Datagrams need an address unless the socket is connected
+    NoDestination,
+    /// The datagram is larger than any queue
+    TooLong,
+    /// The peer closed, or shut its reading down
+    BrokenPipe,
+    /// The operation would wait on a non-blocking socket
+    WouldBlock,
+    /// A signal arrived before anything happened
+    Interrupted,
+}
+
+/// What a receive gave
+#[derive(Default)]
+pub struct Received {
+    /// Bytes copied to the buffer
+    pub count: usize,
+    /// Size of the datagram, larger than `count` when it was truncated
+    pub size: usize,
+    pub files: Vec<Arc<OpenFile>>,
+    pub sender: Option<Address>,
+}
+
+struct Packet {
+    data: Vec<u8>,
+    files: Vec<Arc<OpenFile>>,
+    sender: Option<Address>,
+}
+
+enum State {
+    Unconnected,
+    Listening {
+        backlog: usize,
+        /// Server ends of the connections not accepted yet
+        pending: VecDeque<Arc<Endpoint>>,
+    },
+    /// For datagram sockets, the default destination
+    Connected {
+        peer: Weak<Endpoint>,
+    },
+}
+
+struct Inner {
+    address: Option<Address>,
+    state: State,
+    queue: VecDeque<Packet>,
+    /// Bytes in `queue`
+    queued: usize,
+    /// Nothing is received anymore, senders get a broken pipe
+    read_shut: bool,
+    /// Nothing is sent anymore
+    write_shut: bool,
+    /// The peer sends nothing anymore, receives end with the queue
+    end_of_stream: bool,
+}
+
+struct Endpoint {
+    kind: Kind,
+    inner: SpinLock<Inner>,
+    /// Woken when packets or connections come, or when the peer stops
+    /// sending
+    readable: WaitQueue,
+    /// Woken when room frees up in the queue or the backlog, or when the
+    /// endpoint closes
+    writable: WaitQueue,
+}
+
+/// Open socket, closed with its last descriptor
+pub struct Socket {
+    endpoint: Arc<Endpoint>,
+    nonblocking: bool,
+}
+
+/// Create an unbound and unconnected socket
+pub fn socket(kind: Kind, nonblocking: bool) -> OpenFile {
+    let endpoint = Endpoint::new(kind, None, State::Unconnected);
+    Socket::open(endpoint, nonblocking)
+}
+
+/// Create two sockets connected to each other
+pub fn pair(kind: Kind, nonblocking: bool) -> (OpenFile, OpenFile) {
+    let first = Endpoint::new(kind, None, State::Unconnected);
+    let peer = Arc::downgrade(&first);
+    let second = Endpoint::new(kind, None, State::Connected { peer });
+    first.inner.lock().state = State::Connected {
+        peer: Arc::downgrade(&second),
+    };
+    (
+        Socket::open(first, nonblocking),
+        Socket::open(second, nonblocking),
+    )
+}
+
+/// Socket bound to `address`
+fn lookup(address: &Address) -> Result<Arc<Endpoint>, Error> {
+    let endpoint = NAMES.lock().get(address).and_then(Weak::upgrade);
+    endpoint.ok_or(match address {
+        Address::Path(_) => Error::NotFound,
+        Address::Abstract(_) => Error::ConnectionRefused,
+    })
+}
+
+/// Wait on `queue` until `condition` gives a result, or fail at once if
+/// `nonblocking` is set
+fn wait<T>(
+    queue: &WaitQueue,
+    nonblocking: bool,
+    mut condition: impl FnMut() -> Option<Result<T, Error>>,
+) -> Result<T, Error> {
+    match nonblocking {
+        true => condition().unwrap_or(Err(Error::WouldBlock)),
+        false => queue.wait_until(|| {
+            condition().or_else(|| signal::is_interrupted().then_some(Err(Error::Interrupted)))
+        }),
+    }
+}
+
+impl Endpoint {
+    fn new(kind: Kind, address: Option<Address>, state: State) -> Arc<Self> {
+        Arc::new(Self {
+            kind,
+            inner: SpinLock::new(Inner {
+                address,
+                state,
+                queue: VecDeque::new(),
+                queued: 0,
+                read_shut: false,
+                write_shut: false,
+                end_of_stream: false,
+            }),
+            readable: WaitQueue::new(),
+            writable: WaitQueue::new(),
+        })
+    }
+
+    /// Stop sending and receiving, unbind and tell the peer
+    fn close(&self) {
+        let (address, state, queue) = {
+            let mut inner = self.inner.lock();
+            inner.read_shut = true;
+            inner.write_shut = true;
+            inner.queued = 0;
+            let state = core::mem::replace(&mut inner.state, State::Unconnected);
+            (
+                inner.address.take(),
+                state,
+                core::mem::take(&mut inner.queue),
+            )
+        };
+        if let Some(address) = address {
+            let mut names = NAMES.lock();
+            // Accepted sockets have the address of their listener
+            if names
+                .get(&address)
+                .is_some_and(|endpoint| core::ptr::eq(endpoint.as_ptr(), self))
+            {
+                names.remove(&address);
+            }
+        }
+        match state {
+            State::Listening { pending, .. } => pending.iter().for_each(|server| server.close()),
+            State::Connected { peer } if self.kind == Kind::Stream => {
+                if let Some(peer) = peer.upgrade() {
+                    peer.inner.lock().end_of_stream = true;
+                    peer.readable.wake_all();
+                }
+            }
+            _ => (),
+        }
+        self.readable.wake_all();
+        self.writable.wake_all();
+        // The files in flight are closed outside of the locks
+        drop(queue);
+    }
+
+    /// Queue the bytes of `data` that fit, the files coming with the first
+    /// ones
+    fn send_stream(
+        &self,
+        data: &[u8],
+        files: Vec<Arc<OpenFile>>,
+        nonblocking: bool,
+    ) -> Result<usize, Error> {
+        let mut files = Some(files);
+        let mut sent = 0;
+        while sent < data.len() {
+            let result = wait(&self.writable, nonblocking, || {
+                let mut inner = self.inner.lock();
+                if inner.read_shut {
+                    return Some(Err(Error::BrokenPipe));
+                }
+                let room = CAPACITY - inner.queued;
+                if room == 0 {
+                    return None;
+                }
+                let count = room.min(data.len() - sent);
+                inner.queued += count;
+                inner.queue.push_back(Packet {
+                    data: data[sent..sent + count].to_vec(),
+                    files: files.take().unwrap_or_default(),
+                    sender: None,
+                });
+                Some(Ok(count))
+            });
+            match result {
+                Ok(count) => {
+                    sent += count;
+                    self.readable.wake_all();
+                }
+                Err(error) if sent == 0 => return Err(error),
+                Err(_) => break,
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Queue `packet` as a whole
+    fn send_datagram(&self, packet: Packet, nonblocking: bool) -> Result<usize, Error> {
+        let size = packet.data.len();
+        if CAPACITY < size {
+            return Err(Error::TooLong);
+        }
+        let mut packet = Some(packet);
+        wait(&self.writable, nonblocking, || {
+            let mut inner = self.inner.lock();
+            if inner.read_shut {
+                return Some(Err(Error::ConnectionRefused));
+            }
+            if CAPACITY - inner.queued < size || MAX_DATAGRAMS <= inner.queue.len() {
+                return None;
+            }
+            inner.queued += size;
+            inner.queue.extend(packet.take());
+            Some(Ok(size))
+        })?;
+        self.readable.wake_all();
+        Ok(size)
+    }
+
+    /// Take bytes of the queue, several packets at once unless one brings
+    /// files
+    fn receive_stream(inner: &mut Inner, buffer: &mut [u8]) -> Received {
+        let mut received = Received::default();
+        while let Some(packet) = inner.queue.front_mut() {
+            if received.count != 0 && !packet.files.is_empty() {
+                break;
+            }
+            received.files.append(&mut packet.files);
+            let count = (buffer.len() - received.count).min(packet.data.len());
+            buffer[received.count..][..count].copy_from_slice(&packet.data[..count]);
+            packet.data.drain(..count);
+            received.count += count;
+            if packet.data.is_empty() {
+                inner.queue.pop_front();
+            }
+            if received.count == buffer.len() {
+                break;
+            }
+        }
+        inner.queued -= received.count;
+        received.size = received.count;
+        received
+    }
+
+    /// Take the first datagram, what does not fit in `buffer` is lost
+    fn receive_datagram(inner: &mut Inner, buffer: &mut [u8]) -> Option<Received> {
+        let packet = inner.queue.pop_front()?;
+        inner.queued -= packet.data.len();
+        let count = packet.data.len().min(buffer.len());
+        buffer[..count].copy_from_slice(&packet.data[..count]);
+        Some(Received {
+            count,
+            size: packet.data.len(),
+            files: packet.files,
+            sender: packet.sender,
+        })
+    }
+}
+
+impl Socket {
+    fn open(endpoint: Arc<Endpoint>, nonblocking: bool) -> OpenFile {
+        let socket = Self {
+            endpoint,
+            nonblocking,
+        };
+        OpenFile::new(socket, Access::READ_WRITE)
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.endpoint.kind
+    }
+
+    /// Bind to `address`, or to an unused abstract name without one
+    pub fn bind(&self, address: Option<Address>) -> Result<(), Error> {
+        let mut names = NAMES.lock();
+        let mut inner = self.endpoint.inner.lock();
+        if inner.address.is_some() || inner.read_shut {
+            return Err(Error::Invalid);
+        }
+        let is_free = |address: &Address| {
+            names
+                .get(address)
+                .is_none_or(|endpoint| endpoint.strong_count() == 0)
+        };
+        let address = match address {
+            Some(address) if is_free(&address) => address,
+            Some(_) => return Err(Error::AddressInUse),
+            // Five hexadecimal digits, as Linux does
+            None => (0..=0xF_FFFF)
+                .map(|_| {
+                    let number = NEXT_AUTOMATIC_NAME.fetch_add(1, Ordering::Relaxed) & 0xF_FFFF;
+                    Address::Abstract(format!("{number:05x}").into_bytes())
+                })
+                .find(is_free)
+                .ok_or(Error::AddressInUse)?,
+        };
+        names.insert(address.clone(), Arc::downgrade(&self.endpoint));
+        inner.address = Some(address);
+        Ok(())
+    }
+
+    pub fn listen(&self, backlog: usize) -> Result<(), Error> {
+        if self.kind() != Kind::Stream {
+            return Err(Error::NotSupported);
+        }
+        let mut inner = self.endpoint.inner.lock();
+        if inner.address.is_none() {
+            return Err(Error::Invalid);
+        }
+        let backlog = backlog.min(MAX_BACKLOG);
+        match &mut inner.state {
+            State::Unconnected => {
+                inner.state = State::Listening {
+                    backlog,
+                    pending: VecDeque::new(),
+                };
+            }
+            State::Listening {
+                backlog: current, ..
+            } => *current = backlog,
+            State::Connected { .. } => return Err(Error::Invalid),
+        }
+        Ok(())
+    }
+
+    /// Take a connection, waiting for one, return a socket connected to the
+    /// peer and its address
+    pub fn accept(&self, nonblocking: bool) -> Result<(OpenFile, Option<Address>), Error> {
+        if self.kind() != Kind::Stream {
+            return Err(Error::NotSupported);
+        }
+        let endpoint = &self.endpoint;
+        let server = wait(&endpoint.readable, self.nonblocking, || match &mut endpoint
+            .inner
+            .lock()
+            .state
+        {
+            State::Listening { pending, .. } => pending.pop_front().map(Ok),
+            _ => Some(Err(Error::Invalid)),
+        })?;
+        endpoint.writable.wake_all();
+        let socket = Socket {
+            endpoint: server,
+            nonblocking,
+        };
+        // The peer may be gone already, the connection then only gives its end
+        let address = socket.peer_address().unwrap_or_default();
+        Ok((OpenFile::new(socket, Access::READ_WRITE), address))
+    }
+
+    /// Connect to the socket at `address`
+    ///
+    /// Stream sockets wait for room in the backlog of the listener, the
+    /// connection is then established even before it is accepted.
+    pub fn connect(&self, address: &Address) -> Result<(), Error> {
+        let target = lookup(address)?;
+        if target.kind != self.kind() {
+            return Err(Error::WrongKind);
+        }
+        let connected = State::Connected {
+            peer: Arc::downgrade(&target),
+        };
+        if self.kind() == Kind::Datagram {
+            self.endpoint.inner.lock().state = connected;
+            return Ok(());
+        }
+
+        match self.endpoint.inner.lock().state {
+            State::Unconnected => (),
+            State::Listening { .. } => return Err(Error::Invalid),
+            State::Connected { .. } => return Err(Error::AlreadyConnected),
+        }
+        let client = Arc::downgrade(&self.endpoint);
+        let server = Endpoint::new(
+            Kind::Stream,
+            Some(address.clone()),
+            State::Connected { peer: client },
+        );
+        wait(&target.writable, self.nonblocking, || {
+            match &mut target.inner.lock().state {
+                // As by Linux, one more connection than the backlog waits
+                State::Listening { backlog, pending } if pending.len() <= *backlog => {
+                    pending.push_back(server.clone());
+                    Some(Ok(()))
+                }
+                State::Listening { .. } => None,
+                _ => Some(Err(Error::ConnectionRefused)),
+            }
+        })?;
+        self.endpoint.inner.lock().state = State::Connected {
+            peer: Arc::downgrade(&server),
+        };
+        target.readable.wake_all();
+        Ok(())
+    }
+
+    /// Send `data` with `files`, to `destination` or to the peer
+    ///
+    /// Stream sockets may send part of `data`, datagrams are sent whole.
+    pub fn send(
+        &self,
+        data: &[u8],
+        files: Vec<Arc<OpenFile>>,
+        destination: Option<&Address>,
+        dont_wait: bool,
+    ) -> Result<usize, Error> {
+        let nonblocking = self.nonblocking || dont_wait;
+        let (peer, sender) = {
+            let inner = self.endpoint.inner.lock();
+            if inner.write_shut {
+                return Err(Error::BrokenPipe);
+            }
+            let peer = match &inner.state {
+                State::Connected { peer } => Some(peer.upgrade()),
+                _ => None,
+            };
+            (peer, inner.address.clone())
+        };
+        match (self.kind(), peer, destination) {
+            (Kind::Stream, None, _) => Err(Error::NotConnected),
+            (Kind::Stream, Some(_), Some(_)) => Err(Error::AlreadyConnected),
+            (Kind::Stream, Some(peer), None) => match data.is_empty() {
+                true => Ok(0),
+                false => peer
+                    .ok_or(Error::BrokenPipe)?
+                    .send_stream(data, files, nonblocking),
+            },
+            (Kind::Datagram, peer, destination) => {
+                let target = match (destination, peer) {
+                    (Some(address), _) => lookup(address)?,
+                    (None, Some(peer)) => peer.ok_or(Error::ConnectionRefused)?,
+                    (None, None) => return Err(Error::NoDestination),
+                };
+                if target.kind != Kind::Datagram {
+                    return Err(Error::WrongKind);
+                }
+                let packet = Packet {
+                    data: data.to_vec(),
+                    files,
+                    sender,
+                };
+                target.send_datagram(packet, nonblocking)
+            }
+        }
+    }
+
+    /// Receive bytes in `buffer`, waiting for some, a count of 0 meaning
+    /// the end of the stream
+    pub fn receive(&self, buffer: &mut [u8], dont_wait: bool) -> Result<Received, Error> {
+        let endpoint = &self.endpoint;
+        let kind = self.kind();
+        let received = wait(&endpoint.readable, self.nonblocking || dont_wait, || {
+            let mut inner = endpoint.inner.lock();
+            let inner = &mut *inner;
+            let connected = matches!(inner.state, State::Connected { .. });
+            if kind == Kind::Stream && !connected && !inner.read_shut {
+                return Some(Err(Error::NotConnected));
+            }
+            if inner.read_shut {
+                return Some(Ok(Received::default()));
+            }
+            match kind {
+                Kind::Stream if buffer.is_empty() => Some(Ok(Received::default())),
+                Kind::Stream if !inner.queue.is_empty() => {
+                    Some(Ok(Endpoint::receive_stream(inner, buffer)))
+                }
+                Kind::Stream => inner.end_of_stream.then(|| Ok(Received::default())),
+                Kind::Datagram => Endpoint::receive_datagram(inner, buffer).map(Ok),
+            }
+        })?;
+        endpoint.writable.wake_all();
+        Ok(received)
+    }
+
+    /// Stop receiving, sending, or both
+    pub fn shutdown(&self, read: bool, write: bool) -> Result<(), Error> {
+        let peer = {
+            let mut inner = self.endpoint.inner.lock();
+            let State::Connected { peer } = &inner.state else {
+                return Err(Error::NotConnected);
+            };
+            let peer = peer.upgrade();
+            inner.read_shut |= read;
+            inner.write_shut |= write;
+            peer
+        };
+        if let (Some(peer), true, Kind::Stream) = (peer, write, self.kind()) {
+            peer.inner.lock().end_of_stream = true;
+            peer.readable.wake_all();
+        }
+        if read {
+            self.endpoint.readable.wake_all();
+            self.endpoint.writable.wake_all();
+        }
+        Ok(())
+    }
+
+    /// Address the socket is bound to
+    pub fn address(&self) -> Option<Address> {
+        self.endpoint.inner.lock().address.clone()
+    }
+
+    /// Address the peer is bound to
+    pub fn peer_address(&self) -> Result<Option<Address>, Error> {
+        let peer = match &self.endpoint.inner.lock().state {
+            State::Connected { peer } => peer.upgrade(),
+            _ => None,
+        };
+        let peer = peer.ok_or(Error::NotConnected)?;
+        let address = peer.inner.lock().address.clone();
+        Ok(address)
+    }
+}
+
+/// Reads and writes are receives and sends without files
+impl File for Socket {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, super::Error> {
+        Ok(self.receive(buffer, false)?.count)
+    }
+
+    fn write(&self, bytes: &[u8]) -> Result<usize, super::Error> {
+        Ok(self.send(bytes, Vec::new(), None, false)?)
+    }
+
+    fn as_socket(&self) -> Option<&Socket> {
+        Some(self)
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        self.endpoint.close();
+    }
+}
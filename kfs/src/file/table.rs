@@ -0,0 +1,116 @@
+//! Descriptor table of a process
+
+use alloc::{sync::Arc, vec::Vec};
+
+use super::{Error, OpenFile};
+
+/// Descriptors of a process are below this
+pub const MAX_DESCRIPTORS: usize = 256;
+
+#[derive(Clone)]
+struct Descriptor {
+    file: Arc<OpenFile>,
+    /// Closed when the process runs another program
+    close_on_exec: bool,
+}
+
+#[derive(Clone, Default)]
+pub struct Table {
+    descriptors: Vec<Option<Descriptor>>,
+}
+
+impl Table {
+    /// Standard input, output and error all on `file`
+    pub fn standard(file: Arc<OpenFile>) -> Self {
+        let descriptor = Descriptor {
+            file,
+            close_on_exec: false,
+        };
+        Self {
+            descriptors: alloc::vec![Some(descriptor); 3],
+        }
+    }
+
+    fn descriptor(&self, descriptor: usize) -> Result<&Descriptor, Error> {
+        self.descriptors
+            .get(descriptor)
+            .and_then(Option::as_ref)
+            .ok_or(Error::BadDescriptor)
+    }
+
+    pub fn get(&self, descriptor: usize) -> Result<Arc<OpenFile>, Error> {
+        self.descriptor(descriptor)
+            .map(|descriptor| descriptor.file.clone())
+    }
+
+    /// Give `file` the lowest free descriptor not below `minimum`
+    pub fn insert(
+        &mut self,
+        file: Arc<OpenFile>,
+        minimum: usize,
+        close_on_exec: bool,
+    ) -> Result<usize, Error> {
+        let free = (minimum..MAX_DESCRIPTORS)
+            .find(|&index| !matches!(self.descriptors.get(index), Some(Some(_))))
+            .ok_or(Error::TooManyFiles)?;
+        self.set(free, file, close_on_exec);
+        Ok(free)
+    }
+
+    /// Make `descriptor` refer to `file`, return the file it referred to
+    ///
+    /// The replaced file should be dropped once nothing is locked, closing
+    /// it may wake tasks.
+    pub fn set(
+        &mut self,
+        descriptor: usize,
+        file: Arc<OpenFile>,
+        close_on_exec: bool,
+    ) -> Option<Arc<OpenFile>> {
+        if self.descriptors.len() <= descriptor {
+            self.descriptors.resize(descriptor + 1, None);
+        }
+        let replaced = self.descriptors[descriptor].replace(Descriptor {
+            file,
+            close_on_exec,
+        });
+        replaced.map(|descriptor| descriptor.file)
+    }
+
+    /// Free `descriptor`, return its file to drop like for [Table::set]
+    pub fn close(&mut self, descriptor: usize) -> Result<Arc<OpenFile>, Error> {
+        self.descriptors
+            .get_mut(descriptor)
+            .and_then(Option::take)
+            .map(|descriptor| descriptor.file)
+            .ok_or(Error::BadDescriptor)
+    }
+
+    pub fn close_on_exec(&self, descriptor: usize) -> Result<bool, Error> {
+        self.descriptor(descriptor)
+            .map(|descriptor| descriptor.close_on_exec)
+    }
+
+    pub fn set_close_on_exec(&mut self, descriptor: usize, close: bool) -> Result<(), Error> {
+        let descriptor = self
+            .descriptors
+            .get_mut(descriptor)
+            .and_then(Option::as_mut)
+            .ok_or(Error::BadDescriptor)?;
+        descriptor.close_on_exec = close;
+        Ok(())
+    }
+
+    /// Close the descriptors marked close on exec, return their files to
+    /// drop like for [Table::set]
+    pub fn exec(&mut self) -> Vec<Arc<OpenFile>> {
+        self.descriptors
+            .iter_mut()
+            .filter(|slot| {
+                slot.as_ref()
+                    .is_some_and(|descriptor| descriptor.close_on_exec)
+            })
+            .filter_map(|slot| slot.take().map(|descriptor| descriptor.file))
+            .collect()
+    }
+}
@@ -0,0 +1,608 @@
+//! Physical frame allocator
+//!
+//! Frames reachable through the physical window are handed out by a buddy
+//! allocator: a block of order `n` is `2^n` contiguous frames aligned on its
+//! size. Free blocks are linked through their first frame, one list per order
+//! and per [Zone], and merge back with their buddy when freed.
+//!
+//! Frames above the window, up to 4 GiB or beyond with PAE, form the
+//! [Zone::High]. They are only handed out one at a time, to memory the kernel
+//! reaches through a mapping, and are linked through their first bytes by
+//! mapping them temporarily.
+//!
+//! Single frames are reference counted so that they can be mapped in several
+//! places, [free] only releases them once the last reference is dropped.
+//! Reserved frames, such as the boot modules, are never released.
+//!
+//! Based of [Wikipedia](https://en.wikipedia.org/wiki/Buddy_memory_allocation)
+
+use core::{mem::size_of, ops::Range, ptr};
+
+use sync::SpinLock;
+
+use crate::{
+    linker,
+    vmm::{self, PhysicalAddress, PAGE_SIZE},
+};
+
+pub const MAX_ORDER: usize = 10;
+pub const ORDER_COUNT: usize = MAX_ORDER + 1;
+
+/// First frame outside of the physical window
+const HIGH_START: PhysicalAddress = vmm::PHYSICAL_WINDOW_SIZE as PhysicalAddress;
+const FRAME_SIZE: PhysicalAddress = PAGE_SIZE as PhysicalAddress;
+
+const MAX_RESERVED_COUNT: usize = 32;
+
+pub static ALLOCATOR: SpinLock<Allocator> = SpinLock::new(Allocator::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// Below 16 MiB, reachable by ISA DMA
+    Dma,
+    Normal,
+    /// Above the physical window, only reachable through temporary mappings
+    High,
+}
+
+impl Zone {
+    pub const COUNT: usize = 3;
+    pub const ALL: [Self; Self::COUNT] = [Self::Dma, Self::Normal, Self::High];
+
+    const DMA_END: usize = 0x0100_0000 / PAGE_SIZE;
+
+    fn of(index: usize) -> Self {
+        match index < Self::DMA_END {
+            true => Zone::Dma,
+            false => Zone::Normal,
+        }
+    }
+
+    /// Zones to allocate from, in order, when asked for this one
+    fn fallbacks(self) -> &'static [Self] {
+        match self {
+            Zone::Dma => &[Zone::Dma],
+            Zone::Normal => &[Zone::Normal, Zone::Dma],
+            Zone::High => &[Zone::High, Zone::Normal, Zone::Dma],
+        }
+    }
+}
+
+/// Stored in the first frame of every free block
+#[repr(C)]
+struct Node {
+    previous: usize,
+    next: usize,
+}
+
+const NO_FRAME: usize = usize::MAX;
+
+/// Free blocks of the same order and zone
+#[derive(Clone, Copy)]
+struct List {
+    head: usize,
+    len: usize,
+}
+
+impl List {
+    const EMPTY: Self = Self {
+        head: NO_FRAME,
+        len: 0,
+    };
+}
+
+/// Per frame state, only meaningful for the first frame of a block
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct State(u8);
+
+impl State {
+    const NONE: Self = Self(0);
+    const RESERVED: Self = Self(0x20);
+    const FREE: u8 = 0x40;
+    const ALLOCATED: u8 = 0x80;
+
+    const fn free(order: usize) -> Self {
+        Self(Self::FREE | order as u8)
+    }
+
+    const fn allocated(order: usize) -> Self {
+        Self(Self::ALLOCATED | order as u8)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZoneStatistics {
+    pub zone: Zone,
+    pub total: usize,
+    pub free: usize,
+    /// Free blocks of every order
+    pub free_blocks: [usize; ORDER_COUNT],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Statistics {
+    pub total: usize,
+    pub free: usize,
+    pub reserved: usize,
+    pub zones: [ZoneStatistics; Zone::COUNT],
+}
+
+/// Reference count of the frames above the window that are not available
+const UNAVAILABLE: u16 = u16::MAX;
+const NO_HIGH_FRAME: PhysicalAddress = PhysicalAddress::MAX;
+
+/// Frames of the [Zone::High], a free list of single frames
+struct HighMemory {
+    /// Owners of every frame from [HIGH_START], in a block of the window
+    references: *mut u16,
+    count: usize,
+    /// Each free frame starts with the address of the next one
+    free_head: PhysicalAddress,
+}
+
+impl HighMemory {
+    const EMPTY: Self = Self {
+        references: ptr::null_mut(),
+        count: 0,
+        free_head: NO_HIGH_FRAME,
+    };
+
+    fn index(&self, frame: PhysicalAddress) -> Option<usize> {
+        let index = frame.checked_sub(HIGH_START)? / FRAME_SIZE;
+        let index = usize::try_from(index).ok()?;
+        (frame.is_multiple_of(FRAME_SIZE) && index < self.count).then_some(index)
+    }
+
+    fn references(&mut self, index: usize) -> &mut u16 {
+        assert!(index < self.count);
+        unsafe { &mut *self.references.add(index) }
+    }
+
+    fn push(&mut self, frame: PhysicalAddress) {
+        let next = self.free_head;
+        vmm::with_frame(frame, |page| unsafe {
+            (page as *mut PhysicalAddress).write(next)
+        });
+        self.free_head = frame;
+    }
+
+    fn pop(&mut self) -> Option<PhysicalAddress> {
+        let frame = self.free_head;
+        if frame == NO_HIGH_FRAME {
+            return None;
+        }
+        self.free_head = vmm::with_frame(frame, |page| unsafe {
+            (page as *const PhysicalAddress).read()
+        });
+        Some(frame)
+    }
+}
+
+pub struct Allocator {
+    free_lists: [[List; ORDER_COUNT]; Zone::COUNT],
+    states: [State; Self::FRAME_COUNT],
+    /// Owners of every allocated frame
+    references: [u16; Self::FRAME_COUNT],
+    /// Frames backed by available memory
+    total: [usize; Zone::COUNT],
+    free: [usize; Zone::COUNT],
+    reserved: usize,
+    high: HighMemory,
+}
+
+// SAFETY: the reference counts of high memory belong to the allocator
+unsafe impl Send for Allocator {}
+
+impl Allocator {
+    pub const FRAME_COUNT: usize = vmm::PHYSICAL_WINDOW_SIZE / PAGE_SIZE;
+
+    const fn new() -> Self {
+        Self {
+            free_lists: [[List::EMPTY; ORDER_COUNT]; Zone::COUNT],
+            states: [State::NONE; Self::FRAME_COUNT],
+            references: [0; Self::FRAME_COUNT],
+            total: [0; Zone::COUNT],
+            free: [0; Zone::COUNT],
+            reserved: 0,
+            high: HighMemory::EMPTY,
+        }
+    }
+
+    fn node(index: usize) -> *mut Node {
+        vmm::physical_to_virtual(index as PhysicalAddress * FRAME_SIZE) as *mut Node
+    }
+
+    fn push(&mut self, index: usize, order: usize) {
+        let list = &mut self.free_lists[Zone::of(index) as usize][order];
+        unsafe {
+            Self::node(index).write(Node {
+                previous: NO_FRAME,
+                next: list.head,
+            });
+            if list.head != NO_FRAME {
+                (*Self::node(list.head)).previous = index;
+            }
+        }
+        list.head = index;
+        list.len += 1;
+        self.states[index] = State::free(order);
+    }
+
+    fn remove(&mut self, index: usize, order: usize) {
+        let list = &mut self.free_lists[Zone::of(index) as usize][order];
+        unsafe {
+            let Node { previous, next } = Self::node(index).read();
+            match previous {
+                NO_FRAME => list.head = next,
+                previous => (*Self::node(previous)).next = next,
+            }
+            if next != NO_FRAME {
+                (*Self::node(next)).previous = previous;
+            }
+        }
+        list.len -= 1;
+        self.states[index] = State::NONE;
+    }
+
+    /// Give the block of `order` starting at frame `index` back, merging it
+    /// with its buddies
+    fn release(&mut self, mut index: usize, mut order: usize) {
+        let zone = Zone::of(index);
+        self.free[zone as usize] += 1 << order;
+        while order < MAX_ORDER {
+            let buddy = index ^ (1 << order);
+            if self.states[buddy] != State::free(order) {
+                break;
+            }
+            self.remove(buddy, order);
+            index = index.min(buddy);
+            order += 1;
+        }
+        self.push(index, order);
+    }
+
+    /// Is frame `index` part of a free block
+    fn is_free(&self, index: usize) -> bool {
+        (0..ORDER_COUNT).any(|order| {
+            let head = index & !((1 << order) - 1);
+            self.states[head] == State::free(order)
+        })
+    }
+
+    /// Clamp `[start, end)` to the frames this allocator can track
+    fn frame_indices(start: u64, end: u64) -> Range<usize> {
+        let limit = (Self::FRAME_COUNT * PAGE_SIZE) as u64;
+        let start = start.min(limit) as usize;
+        let end = end.min(limit) as usize;
+        start / PAGE_SIZE..end / PAGE_SIZE
+    }
+
+    /// Hand the frames fully contained in `[start, end)` to the allocator,
+    /// except those overlapping one of the `reserved` ranges
+    ///
+    /// Free frames are written to, everything still in use in the region must
+    /// be covered by `reserved`.
+    pub fn add_region(&mut self, start: u64, end: u64, reserved: &[Range<u64>]) {
+        let start = start.next_multiple_of(PAGE_SIZE as u64);
+        for index in Self::frame_indices(start, end) {
+            if self.is_free(index) || self.states[index] == State::RESERVED {
+                continue;
+            }
+            let frame = (index * PAGE_SIZE) as u64..((index + 1) * PAGE_SIZE) as u64;
+            self.total[Zone::of(index) as usize] += 1;
+            match reserved
+                .iter()
+                .any(|range| range.start < frame.end && frame.start < range.end)
+            {
+                true => {
+                    self.states[index] = State::RESERVED;
+                    self.reserved += 1;
+                }
+                false => self.release(index, 0),
+            }
+        }
+    }
+
+    /// Allocate `2^order` contiguous frames, aligned on their size, from
+    /// `zone` or the zones it falls back to
+    pub fn allocate_block(&mut self, order: usize, zone: Zone) -> Option<PhysicalAddress> {
+        assert!(order <= MAX_ORDER, "Frame block order {order} is too big");
+        if zone == Zone::High && order == 0 {
+            if let Some(frame) = self.high.pop() {
+                let index = self
+                    .high
+                    .index(frame)
+                    .expect("Free high frame out of range");
+                *self.high.references(index) = 1;
+                self.free[Zone::High as usize] -= 1;
+                return Some(frame);
+            }
+        }
+        // The lists of the high zone are always empty
+        let (zone, found) = zone.fallbacks().iter().find_map(|&zone| {
+            let lists = &self.free_lists[zone as usize];
+            let found = (order..ORDER_COUNT).find(|&found| lists[found].len != 0)?;
+            Some((zone, found))
+        })?;
+
+        let index = self.free_lists[zone as usize][found].head;
+        self.remove(index, found);
+        for split in (order..found).rev() {
+            self.push(index + (1 << split), split);
+        }
+        self.states[index] = State::allocated(order);
+        self.references[index] = 1;
+        self.free[zone as usize] -= 1 << order;
+        Some(index as PhysicalAddress * FRAME_SIZE)
+    }
+
+    /// # Panic
+    /// When `block` was not returned by [Allocator::allocate_block] with the
+    /// same `order`, or was already freed
+    pub fn free_block(&mut self, block: PhysicalAddress, order: usize) {
+        let index = (block / FRAME_SIZE) as usize;
+        assert!(
+            vmm::is_in_window(block)
+                && block.is_multiple_of(FRAME_SIZE)
+                && index < Self::FRAME_COUNT
+                && self.states[index] == State::allocated(order),
+            "Freeing frame block {block:#x} of order {order} that is not allocated"
+        );
+        assert!(
+            self.references[index] == 1,
+            "Freeing frame block {block:#x} which is shared"
+        );
+        self.references[index] = 0;
+        self.states[index] = State::NONE;
+        self.release(index, order);
+    }
+
+    /// Index of the allocated or reserved frame at `frame`
+    ///
+    /// # Panic
+    /// When `frame` is not page aligned or is neither allocated nor reserved
+    fn owned_frame(&self, frame: PhysicalAddress) -> usize {
+        let index = (frame / FRAME_SIZE) as usize;
+        let owned = vmm::is_in_window(frame)
+            && frame.is_multiple_of(FRAME_SIZE)
+            && index < Self::FRAME_COUNT
+            && [State::RESERVED, State::allocated(0)].contains(&self.states[index]);
+        assert!(owned, "Frame {frame:#x} is not allocated");
+        index
+    }
+
+    /// Owners of the allocated `frame` above the window, none when it is in
+    /// the window
+    ///
+    /// # Panic
+    /// When `frame` is above the window but not allocated
+    fn high_references(&mut self, frame: PhysicalAddress) -> Option<&mut u16> {
+        if vmm::is_in_window(frame) {
+            return None;
+        }
+        let index = self.high.index(frame);
+        let references = index.map(|index| self.high.references(index));
+        let allocated = references.filter(|references| ![0, UNAVAILABLE].contains(*references));
+        assert!(allocated.is_some(), "Frame {frame:#x} is not allocated");
+        allocated
+    }
+
+    /// Record one more owner of `frame`
+    ///
+    /// # Panic
+    /// When `frame` is neither allocated nor reserved
+    pub fn add_reference(&mut self, frame: PhysicalAddress) {
+        if let Some(references) = self.high_references(frame) {
+            *references = references
+                .checked_add(1)
+                .filter(|&references| references != UNAVAILABLE)
+                .expect("Frame shared too many times");
+            return;
+        }
+        let index = self.owned_frame(frame);
+        if self.states[index] != State::RESERVED {
+            self.references[index] = self.references[index]
+                .checked_add(1)
+                .expect("Frame shared too many times");
+        }
+    }
+
+    /// Owners of `frame`, reserved frames are always shared
+    pub fn references(&mut self, frame: PhysicalAddress) -> usize {
+        if let Some(references) = self.high_references(frame) {
+            return *references as usize;
+        }
+        let index = self.owned_frame(frame);
+        match self.states[index] {
+            State::RESERVED => usize::MAX,
+            _ => self.references[index] as usize,
+        }
+    }
+
+    pub fn allocate(&mut self) -> Option<PhysicalAddress> {
+        self.allocate_block(0, Zone::Normal)
+    }
+
+    /// Drop a reference to `frame`, and release it if it was the last one
+    ///
+    /// # Panic
+    /// When `frame` is not page aligned or is neither allocated nor reserved
+    pub fn free(&mut self, frame: PhysicalAddress) {
+        if let Some(references) = self.high_references(frame) {
+            *references -= 1;
+            if *references == 0 {
+                self.high.push(frame);
+                self.free[Zone::High as usize] += 1;
+            }
+            return;
+        }
+        let index = self.owned_frame(frame);
+        match (self.states[index], self.references[index]) {
+            (State::RESERVED, _) => {}
+            (_, 1) => self.free_block(frame, 0),
+            (_, _) => self.references[index] -= 1,
+        }
+    }
+
+    /// Hand the frame at `frame`, above the window, to the allocator unless
+    /// it overlaps one of the `reserved` ranges
+    fn add_high_frame(&mut self, frame: PhysicalAddress, reserved: &[Range<u64>]) {
+        let Some(index) = self.high.index(frame) else {
+            return;
+        };
+        if *self.high.references(index) != UNAVAILABLE {
+            return;
+        }
+        self.total[Zone::High as usize] += 1;
+        match reserved
+            .iter()
+            .any(|range| range.start < frame + FRAME_SIZE && frame < range.end)
+        {
+            true => self.reserved += 1,
+            false => {
+                *self.high.references(index) = 0;
+                self.high.push(frame);
+                self.free[Zone::High as usize] += 1;
+            }
+        }
+    }
+
+    pub fn statistics(&self) -> Statistics {
+        let zones = Zone::ALL.map(|zone| ZoneStatistics {
+            zone,
+            total: self.total[zone as usize],
+            free: self.free[zone as usize],
+            free_blocks: match zone {
+                // Only single frames are free there
+                Zone::High => core::array::from_fn(|order| match order {
+                    0 => self.free[zone as usize],
+                    _ => 0,
+                }),
+                _ => self.free_lists[zone as usize].map(|list| list.len),
+            },
+        });
+        Statistics {
+            total: zones.iter().map(|zone| zone.total).sum(),
+            free: zones.iter().map(|zone| zone.free).sum(),
+            reserved: self.reserved,
+            zones,
+        }
+    }
+}
+
+/// Build the frame allocator from the boot loader memory map
+///
+/// Everything the kernel still needs from boot time is reserved: the first
+/// megabyte, the kernel image, the boot information and the boot modules.
+/// Frames above the physical window are only added by [init_high_memory].
+pub fn init(info: &multiboot::Info, info_address: usize) {
+    let reserved = reserved_ranges(info, info_address);
+    let Some(memory_map) = info.memory_map() else {
+        panic!("Boot loader did not provide a memory map");
+    };
+    let mut allocator = ALLOCATOR.lock();
+    for region in memory_map.regions() {
+        if region.kind == multiboot::MemoryKind::Available {
+            allocator.add_region(region.base, region.end(), &reserved);
+        }
+    }
+}
+
+/// Hand the available memory above the physical window to the allocator
+///
+/// Requires paging, these frames are only reachable through temporary
+/// mappings. Their reference counts take at most a block of the largest
+/// order, which bounds the memory used.
+pub fn init_high_memory(info: &multiboot::Info, info_address: usize) {
+    const MAX_FRAMES: usize = (PAGE_SIZE << MAX_ORDER) / size_of::<u16>();
+
+    let reserved = reserved_ranges(info, info_address);
+    let Some(memory_map) = info.memory_map() else {
+        return;
+    };
+    let available = || {
+        memory_map
+            .regions()
+            .filter(|region| region.kind == multiboot::MemoryKind::Available)
+            .map(|region| region.base..region.end().min(vmm::physical_limit()))
+    };
+    let end = available().map(|region| region.end).max().unwrap_or(0);
+    let count = (end.saturating_sub(HIGH_START) / FRAME_SIZE).min(MAX_FRAMES as u64) as usize;
+    if count == 0 {
+        return;
+    }
+
+    let mut allocator = ALLOCATOR.lock();
+    let order = (count * size_of::<u16>())
+        .div_ceil(PAGE_SIZE)
+        .next_power_of_two()
+        .trailing_zeros() as usize;
+    let Some(block) = allocator.allocate_block(order, Zone::Normal) else {
+        log::warn!("No frame left to track the memory above the physical window");
+        return;
+    };
+    let references = vmm::physical_to_virtual(block) as *mut u16;
+    unsafe { core::slice::from_raw_parts_mut(references, count).fill(UNAVAILABLE) };
+    allocator.high = HighMemory {
+        references,
+        count,
+        free_head: NO_HIGH_FRAME,
+    };
+    for region in available() {
+        let start = region.start.max(HIGH_START).next_multiple_of(FRAME_SIZE);
+        let end = region.end - region.end % FRAME_SIZE;
+        for frame in (start..end).step_by(PAGE_SIZE) {
+            allocator.add_high_frame(frame, &reserved);
+        }
+    }
+}
+
+/// Memory still needed from boot time, see [init]
+fn reserved_ranges(
+    info: &multiboot::Info,
+    info_address: usize,
+) -> collections::ArrayVec<MAX_RESERVED_COUNT, Range<u64>> {
+    const LOW_MEMORY_END: u64 = 0x10_0000;
+
+    let mut reserved = collections::ArrayVec::<MAX_RESERVED_COUNT, Range<u64>>::new();
+    let boot_ranges = [
+        0..LOW_MEMORY_END,
+        linker::kernel_start() as u64..linker::kernel_end() as u64,
+        info_address as u64..(info_address + info.size()) as u64,
+    ];
+    let module_ranges = info
+        .modules()
+        .map(|module| module.start as u64..module.end as u64);
+    for range in boot_ranges.into_iter().chain(module_ranges) {
+        reserved
+            .push(range)
+            .expect("Too many boot modules to reserve");
+    }
+    reserved
+}
+
+pub fn allocate() -> Option<PhysicalAddress> {
+    ALLOCATOR.lock().allocate()
+}
+
+/// Allocate a frame the kernel only accesses through a mapping, above the
+/// physical window if possible
+pub fn allocate_high() -> Option<PhysicalAddress> {
+    ALLOCATOR.lock().allocate_block(0, Zone::High)
+}
+
+pub fn free(frame: PhysicalAddress) {
+    ALLOCATOR.lock().free(frame)
+}
+
+pub fn add_reference(frame: PhysicalAddress) {
+    ALLOCATOR.lock().add_reference(frame)
+}
+
+pub fn references(frame: PhysicalAddress) -> usize {
+    ALLOCATOR.lock().references(frame)
+}
+
+pub fn statistics() -> Statistics {
+    ALLOCATOR.lock().statistics()
+}
@@ -0,0 +1,136 @@
+//! Fast user-space mutexes: programs lock with atomic operations on a word of
+//! their memory, and only call the kernel to wait for it to change
+//!
+//! Waiters are keyed by the physical address of the word, so processes
+//! sharing its page share its waiters. Each word waited for has a
+//! [WaitQueue], and [wake] picks its waiters in the order they came.
+//!
+//! Based of [futex(2)](https://man7.org/linux/man-pages/man2/futex.2.html)
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+
+use crate::{
+    mutex::Mutex,
+    pit, signal,
+    task::{self, TaskId},
+    vmm::PhysicalAddress,
+    wait_queue::WaitQueue,
+};
+
+static FUTEXES: Mutex<BTreeMap<PhysicalAddress, Futex>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The word did not hold the expected value
+    WouldBlock,
+    TimedOut,
+    /// A signal arrived before a wake
+    Interrupted,
+}
+
+#[derive(Default)]
+struct Futex {
+    queue: Arc<WaitQueue>,
+    /// Oldest first
+    waiting: Vec<TaskId>,
+    /// Picked by [wake], until they notice
+    woken: Vec<TaskId>,
+}
+
+/// Wait for a [wake] of `key`, if `is_expected` holds, until the tick
+/// `deadline` if any
+///
+/// `is_expected` reads the word, no wake of `key` may happen between its
+/// check and the wait.
+pub fn wait(
+    key: PhysicalAddress,
+    is_expected: impl FnOnce() -> bool,
+    deadline: Option<u64>,
+) -> Result<(), Error> {
+    let current = task::current();
+    let queue = {
+        let mut futexes = FUTEXES.lock();
+        if !is_expected() {
+            return Err(Error::WouldBlock);
+        }
+        let futex = futexes.entry(key).or_default();
+        futex.waiting.push(current);
+        futex.queue.clone()
+    };
+
+    // Nothing may block after the check of the wakes, such a block would
+    // take the wake of the queue
+    let condition = || {
+        let interrupted = signal::is_interrupted();
+        match (take_woken(key, current), interrupted) {
+            (true, _) => Some(Ok(())),
+            (false, true) => Some(Err(Error::Interrupted)),
+            (false, false) => None,
+        }
+    };
+    let result = match deadline {
+        None => Some(queue.wait_until(condition)),
+        Some(deadline) => {
+            let ticks = deadline.saturating_sub(pit::ticks());
+            queue.wait_timeout(ticks, condition)
+        }
+    };
+    match result {
+        Some(Ok(())) => Ok(()),
+        Some(Err(error)) => leave(key, current, error),
+        None => leave(key, current, Error::TimedOut),
+    }
+}
+
+/// Wake up to `count` tasks waiting for `key`, return how many
+pub fn wake(key: PhysicalAddress, count: usize) -> usize {
+    let mut futexes = FUTEXES.lock();
+    let Some(futex) = futexes.get_mut(&key) else {
+        return 0;
+    };
+    let count = count.min(futex.waiting.len());
+    let woken: Vec<TaskId> = futex.waiting.drain(..count).collect();
+    futex.woken.extend(woken);
+    // Those not picked check again and wait on
+    futex.queue.wake_all();
+    count
+}
+
+/// Whether `task` was picked by a [wake] of `key`, forgetting it then
+fn take_woken(key: PhysicalAddress, task: TaskId) -> bool {
+    let mut futexes = FUTEXES.lock();
+    let Some(futex) = futexes.get_mut(&key) else {
+        return false;
+    };
+    let woken = futex.woken.iter().position(|&woken| woken == task);
+    if let Some(index) = woken {
+        futex.woken.remove(index);
+        remove_if_unused(&mut futexes, key);
+    }
+    woken.is_some()
+}
+
+/// Stop waiting for `key` because of `error`, unless a wake came meanwhile
+fn leave(key: PhysicalAddress, task: TaskId, error: Error) -> Result<(), Error> {
+    let mut futexes = FUTEXES.lock();
+    let Some(futex) = futexes.get_mut(&key) else {
+        return Err(error);
+    };
+    let woken = futex.woken.contains(&task);
+    futex.woken.retain(|&woken| woken != task);
+    futex.waiting.retain(|&waiting| waiting != task);
+    remove_if_unused(&mut futexes, key);
+    match woken {
+        true => Ok(()),
+        false => Err(error),
+    }
+}
+
+fn remove_if_unused(futexes: &mut BTreeMap<PhysicalAddress, Futex>, key: PhysicalAddress) {
+    let unused = futexes
+        .get(&key)
+        .is_some_and(|futex| futex.waiting.is_empty() && futex.woken.is_empty());
+    if unused {
+        futexes.remove(&key);
+    }
+}
@@ -0,0 +1,158 @@
+//! Global descriptor table and task state segment
+//!
+//! Segments are flat, they only set the privilege level. The task state
+//! segment gives the stack the CPU switches to when ring 3 is interrupted.
+//! The thread local storage segment of the running program is the only one
+//! with a base, it is replaced on every switch.
+//!
+//! Based of [OSDev.org](https://wiki.osdev.org/Global_Descriptor_Table)
+
+use core::mem::size_of;
+
+use sync::SpinLock;
+
+pub const KERNEL_CODE: u16 = 0x08;
+pub const KERNEL_DATA: u16 = 0x10;
+/// Requested privilege level included
+pub const USER_CODE: u16 = 0x18 | 3;
+/// Requested privilege level included
+pub const USER_DATA: u16 = 0x20 | 3;
+const TASK_STATE: u16 = 0x28;
+/// Requested privilege level included, programs load it in gs
+pub const THREAD_LOCAL: u16 = 0x30 | 3;
+
+const DESCRIPTOR_COUNT: usize = 7;
+
+static TABLE: SpinLock<Table> = SpinLock::new(Table([
+    Descriptor::NULL,
+    Descriptor::flat(Descriptor::CODE),
+    Descriptor::flat(Descriptor::DATA),
+    Descriptor::flat(Descriptor::CODE | Descriptor::USER),
+    Descriptor::flat(Descriptor::DATA | Descriptor::USER),
+    // Filled by `init`, the address of `TASK_STATE_SEGMENT` is not a constant
+    Descriptor::NULL,
+    // Set by the scheduler to the one of the running task
+    Descriptor::NULL,
+]));
+
+/// Only taken with interrupts disabled, the scheduler updates it on every
+/// switch
+static TASK_STATE_SEGMENT: SpinLock<TaskStateSegment> = SpinLock::new(TaskStateSegment {
+    link: 0,
+    esp0: 0,
+    ss0: KERNEL_DATA as u32,
+    unused: [0; 22],
+    trap: 0,
+    io_map_base: size_of::<TaskStateSegment>() as u16,
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Descriptor(u64);
+
+impl Descriptor {
+    pub const NULL: Self = Self(0);
+
+    const PRESENT: u8 = 1 << 7;
+    const USER: u8 = 3 << 5;
+    const CODE: u8 = Self::PRESENT | Self::CODE_OR_DATA | Self::EXECUTABLE | Self::READ_WRITE;
+    const DATA: u8 = Self::PRESENT | Self::CODE_OR_DATA | Self::READ_WRITE;
+    const AVAILABLE_TASK_STATE: u8 = Self::PRESENT | 0x9;
+
+    const CODE_OR_DATA: u8 = 1 << 4;
+    const EXECUTABLE: u8 = 1 << 3;
+    /// Readable code or writable data
+    const READ_WRITE: u8 = 1 << 1;
+
+    const PAGE_GRANULARITY: u8 = 1 << 3;
+    const PROTECTED_MODE: u8 = 1 << 2;
+
+    const fn new(base: u32, limit: u32, access: u8, flags: u8) -> Self {
+        let (base, limit) = (base as u64, limit as u64);
+        Self(
+            (limit & 0xFFFF)
+                | (base & 0xFF_FFFF) << 16
+                | (access as u64) << 40
+                | (limit >> 16 & 0xF) << 48
+                | (flags as u64) << 52
+                | (base >> 24) << 56,
+        )
+    }
+
+    /// Segment covering the whole address space
+    const fn flat(access: u8) -> Self {
+        Self::new(
+            0,
+            0xF_FFFF,
+            access,
+            Self::PAGE_GRANULARITY | Self::PROTECTED_MODE,
+        )
+    }
+
+    /// Data segment of ring 3 starting at `base`, `limit` counts pages
+    /// instead of bytes when `in_pages` is set
+    pub const fn user_data(base: u32, limit: u32, in_pages: bool, writable: bool) -> Self {
+        let mut access = Self::PRESENT | Self::CODE_OR_DATA | Self::USER;
+        if writable {
+            access |= Self::READ_WRITE;
+        }
+        let flags = match in_pages {
+            true => Self::PAGE_GRANULARITY | Self::PROTECTED_MODE,
+            false => Self::PROTECTED_MODE,
+        };
+        Self::new(base, limit, access, flags)
+    }
+}
+
+#[repr(C, align(8))]
+struct Table([Descriptor; DESCRIPTOR_COUNT]);
+
+#[repr(C)]
+struct TaskStateSegment {
+    link: u32,
+    /// Stack of ring 0 when ring 3 is interrupted
+    esp0: u32,
+    ss0: u32,
+    /// Other stacks and state of hardware task switching
+    unused: [u32; 22],
+    trap: u16,
+    io_map_base: u16,
+}
+
+/// Load the table, in place of the one of the boot loader
+pub fn init() {
+    let mut table = TABLE.lock();
+    let task_state = TASK_STATE_SEGMENT.lock();
+    table.0[(TASK_STATE / 8) as usize] = Descriptor::new(
+        &*task_state as *const TaskStateSegment as u32,
+        size_of::<TaskStateSegment>() as u32 - 1,
+        Descriptor::AVAILABLE_TASK_STATE,
+        0,
+    );
+
+    let pointer = asm::DescriptorTablePointer {
+        limit: (size_of::<Table>() - 1) as u16,
+        base: table.0.as_ptr() as u32,
+    };
+    // SAFETY: the tables are static
+    unsafe {
+        asm::lgdt(&pointer);
+        asm::load_segments(KERNEL_CODE, KERNEL_DATA);
+        asm::ltr(TASK_STATE);
+    }
+}
+
+/// Make interrupts of ring 3 use the stack ending at `top`
+///
+/// Interrupts must be disabled.
+pub fn set_kernel_stack(top: usize) {
+    TASK_STATE_SEGMENT.lock().esp0 = top as u32;
+}
+
+/// Make [THREAD_LOCAL] select `descriptor`
+///
+/// Interrupts must be disabled. Segment registers keep the previous
+/// descriptor until they are loaded again.
+pub fn set_thread_local(descriptor: Descriptor) {
+    TABLE.lock().0[(THREAD_LOCAL / 8) as usize] = descriptor;
+}
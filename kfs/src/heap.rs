@@ -0,0 +1,420 @@
+//! Kernel heap
+//!
+//! Blocks are carved out of `[START, break)`, the break grows a page at a
+//! time and every page in between is backed by a frame as soon as the break
+//! passes it, no fault ever needs resolving there. Free blocks are kept in a
+//! list sorted by address so that neighbours merge back when freed.
+//!
+//! The `heap-debug` feature guards every block with red zones and keeps track
+//! of live allocations, see [debug]. Call sites are recorded through
+//! `#[track_caller]`, allocations made through [GlobalAlloc] are attributed to
+//! the kernel allocator itself.
+
+#[cfg(feature = "heap-debug")]
+mod debug;
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem::size_of,
+    ptr::{self, NonNull},
+};
+
+use sync::SpinLock;
+
+use crate::vmm::{self, page_align_down, page_align_up, Flags, VirtualAddress, PAGE_SIZE};
+
+pub const START: VirtualAddress = vmm::KERNEL_DYNAMIC_START;
+pub const MAX_SIZE: usize = 0x0400_0000;
+pub const END: VirtualAddress = START + MAX_SIZE;
+
+static HEAP: SpinLock<Heap> = SpinLock::new(Heap::new());
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: KernelAllocator = KernelAllocator;
+
+/// Stored at the start of every free block
+#[repr(C)]
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// Stored right before the memory handed out by an allocation
+#[repr(C)]
+struct Header {
+    /// Size of the whole block
+    size: usize,
+    /// Bytes between the start of the block and the header
+    padding: usize,
+    #[cfg(feature = "heap-debug")]
+    debug: debug::Record,
+}
+
+const ALIGN: usize = 2 * size_of::<usize>();
+#[cfg(feature = "heap-debug")]
+use debug::RED_ZONE_SIZE;
+#[cfg(not(feature = "heap-debug"))]
+const RED_ZONE_SIZE: usize = 0;
+const MIN_BLOCK_SIZE: usize = size_of::<Header>() + ALIGN;
+/// Free memory at the end of the heap above which it shrinks
+const TRIM_THRESHOLD: usize = 64 * PAGE_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Statistics {
+    /// Bytes between the heap start and its break
+    pub size: usize,
+    /// Bytes in blocks handed out, headers and padding included
+    pub used: usize,
+}
+
+impl Statistics {
+    pub fn free(&self) -> usize {
+        self.size - self.used
+    }
+}
+
+pub struct Heap {
+    free: *mut FreeBlock,
+    brk: VirtualAddress,
+    /// Size of the free block ending at the break, 0 without one
+    tail: usize,
+    used: usize,
+    #[cfg(feature = "heap-debug")]
+    live: debug::Live,
+}
+
+// SAFETY: the heap is only reachable through its lock
+unsafe impl Send for Heap {}
+
+impl Heap {
+    const MIN_GROWTH: usize = 4 * PAGE_SIZE;
+
+    const fn new() -> Self {
+        Self {
+            free: ptr::null_mut(),
+            brk: START,
+            tail: 0,
+            used: 0,
+            #[cfg(feature = "heap-debug")]
+            live: debug::Live::new(),
+        }
+    }
+
+    /// Move the break, backing or releasing the pages in between
+    fn move_break(&mut self, new_brk: VirtualAddress) -> Result<(), ()> {
+        if !(START..=END).contains(&new_brk) {
+            return Err(());
+        }
+        let mapped_end = page_align_up(self.brk);
+        let new_mapped_end = page_align_up(new_brk);
+
+        match mapped_end < new_mapped_end {
+            true => vmm::map_allocated_range(
+                mapped_end,
+                new_mapped_end - mapped_end,
+                Flags::WRITABLE | Flags::NO_EXECUTE,
+            )
+            .map_err(|_| ())?,
+            false => vmm::unmap_and_free_range(new_mapped_end, mapped_end - new_mapped_end),
+        }
+
+        self.brk = new_brk;
+        Ok(())
+    }
+
+    fn grow(&mut self, increment: usize) -> Result<(), ()> {
+        let start = self.brk;
+        self.move_break(start.checked_add(increment).ok_or(())?)?;
+        unsafe { self.insert_free(start, increment) };
+        Ok(())
+    }
+
+    /// Give back the free memory at the end of the heap
+    fn shrink(&mut self, decrement: usize) -> Result<(), ()> {
+        let new_brk = self.brk.checked_sub(decrement).ok_or(())?;
+        if self.tail < decrement {
+            return Err(());
+        }
+        let last = (self.brk - self.tail) as *mut FreeBlock;
+        let remaining = self.tail - decrement;
+        unsafe {
+            match remaining {
+                0 => {
+                    let mut link: *mut *mut FreeBlock = &mut self.free;
+                    while *link != last {
+                        link = &mut (**link).next;
+                    }
+                    *link = ptr::null_mut();
+                }
+                remaining if remaining >= MIN_BLOCK_SIZE => (*last).size = remaining,
+                _ => return Err(()),
+            }
+        }
+        self.tail = remaining;
+        self.move_break(new_brk)
+    }
+
+    /// Give the free memory at the end of the heap back when there is too
+    /// much of it, keeping enough for the next growth
+    fn trim(&mut self) {
+        if self.tail > TRIM_THRESHOLD {
+            let excess = page_align_down(self.tail - Self::MIN_GROWTH);
+            let _ = self.shrink(excess);
+        }
+    }
+
+    /// Return whether the block, once merged with its neighbours, ends at the
+    /// break
+    ///
+    /// # Safety
+    /// `[start, start + size)` must be mapped, unused and outside of the free list
+    unsafe fn insert_free(&mut self, start: VirtualAddress, size: usize) -> bool {
+        unsafe {
+            let mut previous: *mut FreeBlock = ptr::null_mut();
+            let mut next = self.free;
+            while !next.is_null() && (next as usize) < start {
+                previous = next;
+                next = (*next).next;
+            }
+
+            let block = start as *mut FreeBlock;
+            block.write(FreeBlock { size, next });
+            if !next.is_null() && start + size == next as usize {
+                (*block).size += (*next).size;
+                (*block).next = (*next).next;
+            }
+
+            let merged = match previous.is_null() {
+                true => {
+                    self.free = block;
+                    block
+                }
+                false if previous as usize + (*previous).size == start => {
+                    (*previous).size += (*block).size;
+                    (*previous).next = (*block).next;
+                    previous
+                }
+                false => {
+                    (*previous).next = block;
+                    block
+                }
+            };
+            let is_tail = merged as usize + (*merged).size == self.brk;
+            if is_tail {
+                self.tail = (*merged).size;
+            }
+            is_tail
+        }
+    }
+
+    /// First fit search of the free list
+    fn take(&mut self, size: usize, align: usize) -> Option<NonNull<u8>> {
+        let mut link: *mut *mut FreeBlock = &mut self.free;
+        unsafe {
+            while !(*link).is_null() {
+                let block = *link;
+                let start = block as usize;
+                let payload = (start + size_of::<Header>() + RED_ZONE_SIZE).next_multiple_of(align);
+                let needed = payload + size + RED_ZONE_SIZE - start;
+
+                let block_size = (*block).size;
+                if needed <= block_size {
+                    let remaining = block_size - needed;
+                    let taken = match remaining >= MIN_BLOCK_SIZE {
+                        true => {
+                            let rest = (start + needed) as *mut FreeBlock;
+                            rest.write(FreeBlock {
+                                size: remaining,
+                                next: (*block).next,
+                            });
+                            *link = rest;
+                            needed
+                        }
+                        false => {
+                            *link = (*block).next;
+                            block_size
+                        }
+                    };
+                    if start + block_size == self.brk {
+                        self.tail = block_size - taken;
+                    }
+
+                    let header = header(NonNull::new_unchecked(payload as *mut u8));
+                    (*header).size = taken;
+                    (*header).padding = header as usize - start;
+                    self.used += taken;
+                    return NonNull::new(payload as *mut u8);
+                }
+
+                link = &mut (*block).next;
+            }
+        }
+        None
+    }
+
+    #[track_caller]
+    fn allocate(&mut self, requested: usize, align: usize) -> Option<NonNull<u8>> {
+        let size = requested.max(1).next_multiple_of(ALIGN);
+        let align = align.max(ALIGN);
+        loop {
+            if let Some(pointer) = self.take(size, align) {
+                #[cfg(feature = "heap-debug")]
+                unsafe {
+                    debug::on_allocate(
+                        &mut self.live,
+                        header(pointer),
+                        requested,
+                        core::panic::Location::caller(),
+                    )
+                };
+                return Some(pointer);
+            }
+            let growth = (size + align + MIN_BLOCK_SIZE)
+                .next_multiple_of(PAGE_SIZE)
+                .max(Self::MIN_GROWTH);
+            self.grow(growth).ok()?;
+        }
+    }
+
+    /// Return whether the freed block joined the free memory at the end of
+    /// the heap
+    ///
+    /// # Safety
+    /// `pointer` must come from [Heap::allocate] and not be freed yet
+    #[track_caller]
+    unsafe fn deallocate(&mut self, pointer: NonNull<u8>) -> bool {
+        unsafe {
+            let header = header(pointer);
+            #[cfg(feature = "heap-debug")]
+            debug::on_free(&mut self.live, header);
+            let (size, start) = ((*header).size, header as usize - (*header).padding);
+            self.used -= size;
+            self.insert_free(start, size)
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        Statistics {
+            size: self.brk - START,
+            used: self.used,
+        }
+    }
+}
+
+/// # Safety
+/// `pointer` must come from [Heap::allocate]
+unsafe fn header(pointer: NonNull<u8>) -> *mut Header {
+    unsafe { pointer.as_ptr().sub(RED_ZONE_SIZE + size_of::<Header>()) as *mut Header }
+}
+
+/// Allocate `size` bytes from the kernel heap
+#[track_caller]
+pub fn kmalloc(size: usize) -> Option<NonNull<u8>> {
+    HEAP.lock().allocate(size, ALIGN)
+}
+
+/// # Safety
+/// `pointer` must come from [kmalloc] and not be freed yet
+#[track_caller]
+pub unsafe fn kfree(pointer: NonNull<u8>) {
+    let mut heap = HEAP.lock();
+    if unsafe { heap.deallocate(pointer) } {
+        heap.trim();
+    }
+}
+
+/// Usable size of the block behind `pointer`, it might exceed the requested size
+///
+/// # Safety
+/// `pointer` must come from [kmalloc] and not be freed yet
+pub unsafe fn ksize(pointer: NonNull<u8>) -> usize {
+    let _heap = HEAP.lock();
+    unsafe {
+        let header = header(pointer);
+        #[cfg(feature = "heap-debug")]
+        let usable = debug::requested(header);
+        #[cfg(not(feature = "heap-debug"))]
+        let usable = (*header).size - (*header).padding - size_of::<Header>();
+        usable
+    }
+}
+
+/// Move the heap break by `increment` bytes, rounded to whole pages, and
+/// return the previous break
+///
+/// Memory gained is handed to the allocator, memory given back must be free.
+#[allow(dead_code, reason = "part of the kmalloc interface, no caller yet")]
+pub fn kbrk(increment: isize) -> Result<VirtualAddress, ()> {
+    let mut heap = HEAP.lock();
+    let previous = heap.brk;
+    let size = increment.unsigned_abs().next_multiple_of(PAGE_SIZE);
+    match increment.signum() {
+        0 => {}
+        1 => heap.grow(size)?,
+        _ => heap.shrink(size)?,
+    }
+    Ok(previous)
+}
+
+pub fn statistics() -> Statistics {
+    HEAP.lock().statistics()
+}
+
+/// Log the live allocations grouped by call site
+#[cfg(feature = "heap-debug")]
+pub fn report_leaks() {
+    let report = debug::leak_report(&HEAP.lock().live);
+    log::info!("Live heap allocations:");
+    for site in report.iter() {
+        match site.caller {
+            Some(caller) => log::info!(
+                "{}: {} allocations, {} bytes",
+                caller,
+                site.allocations,
+                site.bytes
+            ),
+            None => log::info!(
+                "Others: {} allocations, {} bytes",
+                site.allocations,
+                site.bytes
+            ),
+        }
+    }
+}
+
+struct KernelAllocator;
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = match layout.align() <= ALIGN {
+            true => kmalloc(layout.size()),
+            false => HEAP.lock().allocate(layout.size(), layout.align()),
+        };
+        pointer.map_or(ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, _layout: Layout) {
+        if let Some(pointer) = NonNull::new(pointer) {
+            unsafe { kfree(pointer) };
+        }
+    }
+
+    /// Blocks are often larger than requested, they then grow in place
+    unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Some(block) = NonNull::new(pointer) else {
+            return ptr::null_mut();
+        };
+        if new_size <= unsafe { ksize(block) } {
+            return pointer;
+        }
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let new_pointer = unsafe { self.alloc(new_layout) };
+        if !new_pointer.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(pointer, new_pointer, layout.size());
+                self.dealloc(pointer, layout);
+            }
+        }
+        new_pointer
+    }
+}
@@ -0,0 +1,221 @@
+//! Bookkeeping of the `heap-debug` feature
+//!
+//! Every payload is surrounded by red zones and every live allocation is
+//! linked in a list along with its call site. Blocks are filled with
+//! [ALLOCATED_BYTE] when handed out and [FREED_BYTE] when given back, so
+//! reads of uninitialized or freed memory stand out in a dump.
+
+use core::{cmp::Reverse, panic::Location, ptr};
+
+use collections::ArrayVec;
+
+use super::{Header, ALIGN};
+
+pub const RED_ZONE_SIZE: usize = 16;
+pub const RED_ZONE_BYTE: u8 = 0xFD;
+pub const ALLOCATED_BYTE: u8 = 0xAA;
+pub const FREED_BYTE: u8 = 0xDD;
+
+const ALLOCATED_MAGIC: u32 = 0xA110_CA7E;
+const FREED_MAGIC: u32 = 0xF4EE_D0FF;
+
+/// Call sites listed by a leak report, the others are summed up together
+pub const MAX_CALL_SITES: usize = 32;
+
+/// Stored in the [Header] of every block
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Record {
+    magic: u32,
+    requested: usize,
+    caller: &'static Location<'static>,
+    previous: *mut Header,
+    next: *mut Header,
+}
+
+/// Allocations not freed yet
+pub struct Live {
+    head: *mut Header,
+}
+
+impl Live {
+    pub const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+        }
+    }
+
+    /// # Safety
+    /// `header` must be a valid header outside of the list
+    unsafe fn push(&mut self, header: *mut Header) {
+        unsafe {
+            (*header).debug.previous = ptr::null_mut();
+            (*header).debug.next = self.head;
+            if !self.head.is_null() {
+                (*self.head).debug.previous = header;
+            }
+        }
+        self.head = header;
+    }
+
+    /// # Safety
+    /// `header` must be a valid header of the list
+    unsafe fn remove(&mut self, header: *mut Header) {
+        unsafe {
+            let Record { previous, next, .. } = (*header).debug;
+            match previous.is_null() {
+                true => self.head = next,
+                false => (*previous).debug.next = next,
+            }
+            if !next.is_null() {
+                (*next).debug.previous = previous;
+            }
+        }
+    }
+}
+
+/// Live allocations made from the same place
+#[derive(Debug, Clone, Copy)]
+pub struct CallSite {
+    /// `None` for the call sites that did not fit in the report
+    pub caller: Option<&'static Location<'static>>,
+    pub allocations: usize,
+    pub bytes: usize,
+}
+
+pub type LeakReport = ArrayVec<MAX_CALL_SITES, CallSite>;
+
+/// Bytes from the end of the requested ones to the end of the back red zone
+fn back_red_zone_size(requested: usize) -> usize {
+    requested.max(1).next_multiple_of(ALIGN) - requested + RED_ZONE_SIZE
+}
+
+/// Mark the block of `header` as handed out
+///
+/// # Safety
+/// `header` must be followed by a front red zone, the `requested` bytes
+/// rounded up to [ALIGN] and a back red zone
+pub unsafe fn on_allocate(
+    live: &mut Live,
+    header: *mut Header,
+    requested: usize,
+    caller: &'static Location<'static>,
+) {
+    unsafe {
+        (*header).debug = Record {
+            magic: ALLOCATED_MAGIC,
+            requested,
+            caller,
+            previous: ptr::null_mut(),
+            next: ptr::null_mut(),
+        };
+        let front = header.add(1) as *mut u8;
+        let payload = front.add(RED_ZONE_SIZE);
+        front.write_bytes(RED_ZONE_BYTE, RED_ZONE_SIZE);
+        payload.write_bytes(ALLOCATED_BYTE, requested);
+        payload
+            .add(requested)
+            .write_bytes(RED_ZONE_BYTE, back_red_zone_size(requested));
+        live.push(header);
+    }
+}
+
+/// Check the block of `header` before it goes back to the free list
+///
+/// # Safety
+/// `header` must be the header of a block
+///
+/// # Panic
+/// On double free or when a red zone was overwritten
+#[track_caller]
+pub unsafe fn on_free(live: &mut Live, header: *mut Header) {
+    let caller = Location::caller();
+    unsafe {
+        let payload = (header.add(1) as *mut u8).add(RED_ZONE_SIZE);
+        match (*header).debug.magic {
+            ALLOCATED_MAGIC => {}
+            FREED_MAGIC => panic!(
+                "Double free of {payload:p} at {caller}, allocated at {}",
+                (*header).debug.caller
+            ),
+            _ => panic!("Free of {payload:p} at {caller} which was not allocated"),
+        }
+
+        let Record {
+            requested,
+            caller: allocated_at,
+            ..
+        } = (*header).debug;
+        let front = core::slice::from_raw_parts(payload.sub(RED_ZONE_SIZE), RED_ZONE_SIZE);
+        let back_size = back_red_zone_size(requested);
+        let back = core::slice::from_raw_parts(payload.add(requested), back_size);
+        for (zone, bytes) in [("front", front), ("back", back)] {
+            if let Some(offset) = bytes.iter().position(|&byte| byte != RED_ZONE_BYTE) {
+                panic!(
+                    "Heap corruption: {zone} red zone of {payload:p} ({requested} bytes, allocated at {allocated_at}) overwritten at offset {offset}, detected by free at {caller}"
+                );
+            }
+        }
+
+        live.remove(header);
+        (*header).debug.magic = FREED_MAGIC;
+        payload
+            .sub(RED_ZONE_SIZE)
+            .write_bytes(FREED_BYTE, RED_ZONE_SIZE + requested + back_size);
+    }
+}
+
+/// Bytes asked for when the block of `header` was allocated
+///
+/// # Safety
+/// `header` must come from [on_allocate]
+pub unsafe fn requested(header: *mut Header) -> usize {
+    unsafe { (*header).debug.requested }
+}
+
+/// Group the live allocations by call site, the biggest first
+pub fn leak_report(live: &Live) -> LeakReport {
+    let mut report = LeakReport::new();
+    let mut others = CallSite {
+        caller: None,
+        allocations: 0,
+        bytes: 0,
+    };
+
+    let mut header = live.head;
+    while !header.is_null() {
+        let Record {
+            requested,
+            caller,
+            next,
+            ..
+        } = unsafe { (*header).debug };
+        let index = report.iter().position(|site| site.caller == Some(caller));
+        let site = match index {
+            Some(index) => &mut report[index],
+            None if report.is_full() => &mut others,
+            None => {
+                let _ = report.push(CallSite {
+                    caller: Some(caller),
+                    allocations: 0,
+                    bytes: 0,
+                });
+                report.last_mut().expect("A call site was just pushed")
+            }
+        };
+        site.allocations += 1;
+        site.bytes += requested;
+        header = next;
+    }
+
+    report.sort_unstable_by_key(|site| Reverse(site.bytes));
+    if others.allocations != 0 {
+        // The list is full, the smallest site makes room for the others
+        if let Some(smallest) = report.pop() {
+            others.allocations += smallest.allocations;
+            others.bytes += smallest.bytes;
+        }
+        let _ = report.push(others);
+    }
+    report
+}
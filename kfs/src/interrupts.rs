@@ -0,0 +1,327 @@
+//! Interrupt descriptor table and dispatch of interrupts to their handlers
+//!
+//! Based of [OSDev.org](https://wiki.osdev.org/Interrupt_Descriptor_Table)
+
+use core::mem::size_of;
+
+use sync::SpinLock;
+
+use crate::{
+    gdt, pic, pit,
+    signal::{self, fault, Info, Signal},
+    syscall, task, timer, vmm,
+    wait_queue::WaitQueue,
+};
+
+pub const VECTOR_COUNT: usize = 256;
+
+extern "C" {
+    /// Entry point of every vector, defined in `interrupts.asm`
+    #[link_name = "interrupt_stubs"]
+    static STUBS: [u32; VECTOR_COUNT];
+
+    /// Restore the context of `frame`, defined in `interrupts.asm`
+    fn interrupt_enter(frame: *const Frame) -> !;
+}
+
+static TABLE: SpinLock<Table> = SpinLock::new(Table([Gate::MISSING; VECTOR_COUNT]));
+
+/// Tasks waiting for each IRQ but the timer one, see [wait_for_irq]
+static IRQ_WAITERS: [WaitQueue; pic::IRQ_COUNT as usize] =
+    [const { WaitQueue::new() }; pic::IRQ_COUNT as usize];
+
+/// Context of the interrupted code, as pushed by `interrupts.asm`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub gs: u32,
+    pub fs: u32,
+    pub es: u32,
+    pub ds: u32,
+    pub edi: u32,
+    pub esi: u32,
+    pub ebp: u32,
+    /// Value of esp before `pushad`, ignored by `popad`
+    pub kernel_esp: u32,
+    pub ebx: u32,
+    pub edx: u32,
+    pub ecx: u32,
+    pub eax: u32,
+    pub vector: u32,
+    pub error_code: u32,
+    pub eip: u32,
+    pub cs: u32,
+    pub eflags: u32,
+    /// Only pushed by the CPU when interrupting ring 3
+    pub user_esp: u32,
+    /// Only pushed by the CPU when interrupting ring 3
+    pub user_ss: u32,
+}
+
+impl Frame {
+    /// Bit 1 of EFLAGS is always set
+    pub const DEFAULT_EFLAGS: u32 = 1 << 1;
+
+    pub fn is_from_user(&self) -> bool {
+        self.cs & 0b11 == 3
+    }
+}
+
+/// Based of [OSDev.org](https://wiki.osdev.org/Exceptions)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    DivisionError,
+    Debug,
+    NonMaskableInterrupt,
+    Breakpoint,
+    Overflow,
+    BoundRangeExceeded,
+    InvalidOpcode,
+    DeviceNotAvailable,
+    DoubleFault,
+    CoprocessorSegmentOverrun,
+    InvalidTss,
+    SegmentNotPresent,
+    StackSegmentFault,
+    GeneralProtectionFault,
+    PageFault,
+    X87FloatingPoint,
+    AlignmentCheck,
+    MachineCheck,
+    SimdFloatingPoint,
+    Virtualization,
+    ControlProtection,
+    HypervisorInjection,
+    VmmCommunication,
+    Security,
+    Reserved(u8),
+}
+
+impl Exception {
+    pub const COUNT: u32 = 32;
+
+    pub fn from_vector(vector: u32) -> Option<Self> {
+        use Exception::*;
+        Some(match vector {
+            0 => DivisionError,
+            1 => Debug,
+            2 => NonMaskableInterrupt,
+            3 => Breakpoint,
+            4 => Overflow,
+            5 => BoundRangeExceeded,
+            6 => InvalidOpcode,
+            7 => DeviceNotAvailable,
+            8 => DoubleFault,
+            9 => CoprocessorSegmentOverrun,
+            10 => InvalidTss,
+            11 => SegmentNotPresent,
+            12 => StackSegmentFault,
+            13 => GeneralProtectionFault,
+            14 => PageFault,
+            16 => X87FloatingPoint,
+            17 => AlignmentCheck,
+            18 => MachineCheck,
+            19 => SimdFloatingPoint,
+            20 => Virtualization,
+            21 => ControlProtection,
+            28 => HypervisorInjection,
+            29 => VmmCommunication,
+            30 => Security,
+            vector if vector < Self::COUNT => Reserved(vector as u8),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+struct Gate {
+    offset_low: u16,
+    selector: u16,
+    zero: u8,
+    attributes: u8,
+    offset_high: u16,
+}
+
+impl Gate {
+    const MISSING: Self = Self {
+        offset_low: 0,
+        selector: 0,
+        zero: 0,
+        attributes: 0,
+        offset_high: 0,
+    };
+
+    /// Present, ring 0, 32-bit interrupt gate
+    const INTERRUPT_GATE: u8 = 0x8E;
+    /// [Self::INTERRUPT_GATE] that ring 3 may also use with `int`
+    const USER_INTERRUPT_GATE: u8 = 0xEE;
+
+    const fn new(handler: u32, selector: u16, attributes: u8) -> Self {
+        Self {
+            offset_low: handler as u16,
+            selector,
+            zero: 0,
+            attributes,
+            offset_high: (handler >> 16) as u16,
+        }
+    }
+}
+
+#[repr(C, align(8))]
+struct Table([Gate; VECTOR_COUNT]);
+
+/// Route every vector to `interrupt_dispatch` and load the table
+pub fn init() {
+    let selector = gdt::KERNEL_CODE;
+    let mut table = TABLE.lock();
+    let stubs = unsafe { &STUBS };
+    for (gate, &stub) in table.0.iter_mut().zip(stubs) {
+        *gate = Gate::new(stub, selector, Gate::INTERRUPT_GATE);
+    }
+    table.0[syscall::VECTOR] =
+        Gate::new(stubs[syscall::VECTOR], selector, Gate::USER_INTERRUPT_GATE);
+
+    let pointer = asm::DescriptorTablePointer {
+        limit: (size_of::<Table>() - 1) as u16,
+        base: table.0.as_ptr() as u32,
+    };
+    // SAFETY: the table is static
+    unsafe { asm::lidt(&pointer) };
+}
+
+/// Switch to the context described by `frame`
+///
+/// # Safety
+/// `frame` must describe a context that can run, such as a program in ring 3
+/// with its address space active
+pub unsafe fn enter(frame: &Frame) -> ! {
+    // Interrupts are enabled again by `iret` if `frame` says so
+    asm::disable_interrupts();
+    unsafe { interrupt_enter(frame) }
+}
+
+pub fn are_enabled() -> bool {
+    asm::read_eflags() & asm::EFLAGS_INTERRUPTS != 0
+}
+
+/// Interrupts stay disabled until it is dropped
+pub struct Disabled {
+    were_enabled: bool,
+}
+
+/// Disable interrupts, for code that must not be preempted such as the
+/// holder of a lock also taken by interrupt handlers
+pub fn disable() -> Disabled {
+    let were_enabled = are_enabled();
+    asm::disable_interrupts();
+    Disabled { were_enabled }
+}
+
+impl Drop for Disabled {
+    fn drop(&mut self) {
+        if self.were_enabled {
+            asm::enable_interrupts();
+        }
+    }
+}
+
+#[no_mangle]
+extern "C" fn interrupt_dispatch(frame: &mut Frame) {
+    let mut interrupted_call = None;
+    match Exception::from_vector(frame.vector) {
+        Some(Exception::PageFault) => page_fault(frame),
+        Some(exception) if frame.is_from_user() => raise(frame, exception),
+        Some(exception) => panic!(
+            "{exception:?} at {:#x} (error code: {:#x})",
+            frame.eip, frame.error_code
+        ),
+        None if frame.vector as usize == syscall::VECTOR => {
+            interrupted_call = syscall::dispatch(frame);
+        }
+        None => match pic::irq_of_vector(frame.vector) {
+            Some(irq) => interrupt_request(irq),
+            None => log::warn!("Unexpected interrupt {}", frame.vector),
+        },
+    }
+    // Signals are acted on whenever a program is about to resume
+    if frame.is_from_user() {
+        signal::deliver(frame, interrupted_call);
+    }
+}
+
+fn interrupt_request(irq: u8) {
+    if pic::is_spurious(irq) {
+        return;
+    }
+    // Acknowledge first, the timer may switch to another task
+    pic::end_of_interrupt(irq);
+    match irq {
+        pic::TIMER => {
+            timer::tick(pit::tick());
+            task::tick();
+        }
+        _ => IRQ_WAITERS[irq as usize].wake_all(),
+    }
+}
+
+/// Block until `condition` gives a value, checking it again after every
+/// `irq`, which must be unmasked
+pub fn wait_for_irq<T>(irq: u8, condition: impl FnMut() -> Option<T>) -> T {
+    IRQ_WAITERS[irq as usize].wait_until(condition)
+}
+
+fn page_fault(frame: &mut Frame) {
+    let fault = vmm::Fault::from_error_code(asm::read_cr2() as usize, frame.error_code);
+    // Resolving the fault may wait for a lock held by a preempted task
+    if frame.eflags & asm::EFLAGS_INTERRUPTS != 0 {
+        asm::enable_interrupts();
+    }
+    let user_space = task::address_space();
+    let result = vmm::handle_page_fault(&fault, user_space.as_deref());
+    drop(user_space);
+    match result {
+        Ok(()) => (),
+        Err(error) if frame.is_from_user() => {
+            log::warn!(
+                "Task {} page fault at {:#x}: {error:?}",
+                task::current(),
+                fault.address
+            );
+            let code = match fault.present {
+                true => fault::ACCESS_ERROR,
+                false => fault::MAPPING_ERROR,
+            };
+            signal::force(Signal::SEGV, Info::fault(code, fault.address));
+        }
+        Err(error) => panic!(
+            "Unresolved page fault at {:#x}: {error:?} ({fault:?})",
+            frame.eip
+        ),
+    }
+}
+
+/// Send the process whose program raised `exception` the signal Linux sends
+/// for it, the kernel is fine
+fn raise(frame: &Frame, exception: Exception) {
+    // The program ran with interrupts enabled, and the process table lock
+    // may belong to a preempted task
+    asm::enable_interrupts();
+    log::warn!(
+        "Task {} raised {exception:?} at {:#x}",
+        task::current(),
+        frame.eip
+    );
+    let address = frame.eip as usize;
+    let (signal, info) = match exception {
+        Exception::DivisionError => (Signal::FPE, Info::fault(fault::INTEGER_DIVIDE, address)),
+        Exception::X87FloatingPoint | Exception::SimdFloatingPoint => (Signal::FPE, Info::kernel()),
+        Exception::Debug | Exception::Breakpoint => {
+            (Signal::TRAP, Info::fault(fault::BREAKPOINT, address))
+        }
+        Exception::InvalidOpcode => (Signal::ILL, Info::fault(fault::ILLEGAL_OPCODE, address)),
+        Exception::AlignmentCheck => (Signal::BUS, Info::fault(fault::ALIGNMENT, address)),
+        _ => (Signal::SEGV, Info::kernel()),
+    };
+    signal::force(signal, info);
+}
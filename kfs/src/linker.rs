@@ -0,0 +1,47 @@
+//! Symbols defined by `conf/i686-elf/linker.ld`
+
+use core::{ops::Range, ptr::addr_of};
+
+extern "C" {
+    #[link_name = "kernel_start"]
+    static KERNEL_START: u8;
+    #[link_name = "kernel_end"]
+    static KERNEL_END: u8;
+    #[link_name = "text_start"]
+    static TEXT_START: u8;
+    #[link_name = "text_end"]
+    static TEXT_END: u8;
+    #[link_name = "rodata_start"]
+    static RODATA_START: u8;
+    #[link_name = "rodata_end"]
+    static RODATA_END: u8;
+    #[link_name = "data_start"]
+    static DATA_START: u8;
+    #[link_name = "data_end"]
+    static DATA_END: u8;
+}
+
+/// Address of the first byte of the kernel image
+pub fn kernel_start() -> usize {
+    addr_of!(KERNEL_START) as usize
+}
+
+/// Address of the first byte after the kernel image, page aligned
+pub fn kernel_end() -> usize {
+    addr_of!(KERNEL_END) as usize
+}
+
+/// Code, boundaries are page aligned
+pub fn text() -> Range<usize> {
+    addr_of!(TEXT_START) as usize..addr_of!(TEXT_END) as usize
+}
+
+/// Constants, boundaries are page aligned
+pub fn rodata() -> Range<usize> {
+    addr_of!(RODATA_START) as usize..addr_of!(RODATA_END) as usize
+}
+
+/// Mutable statics, `.bss` included, boundaries are page aligned
+pub fn data() -> Range<usize> {
+    addr_of!(DATA_START) as usize..addr_of!(DATA_END) as usize
+}
@@ -0,0 +1,219 @@
+//! Loading of ELF executables in new address spaces
+//!
+//! The initial stack follows the System V i386 ABI: from the stack pointer,
+//! `argc`, the `argv` and `envp` null terminated arrays, then the auxiliary
+//! vector, with the strings they point to at the top of the stack.
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use crate::{
+    credentials::Credentials,
+    user,
+    vmm::{self, AddressSpace, Flags, VirtualAddress, PAGE_SIZE},
+};
+
+/// Most bytes taken by the arguments and the environment, pointers included
+pub const MAX_ARGUMENTS_SIZE: usize = 128 * 1024;
+
+/// Auxiliary vector entry types
+mod auxiliary {
+    pub const NULL: u32 = 0;
+    pub const PROGRAM_HEADERS: u32 = 3;
+    pub const PROGRAM_HEADER_SIZE: u32 = 4;
+    pub const PROGRAM_HEADER_COUNT: u32 = 5;
+    pub const PAGE_SIZE: u32 = 6;
+    pub const ENTRY: u32 = 9;
+    pub const UID: u32 = 11;
+    pub const EFFECTIVE_UID: u32 = 12;
+    pub const GID: u32 = 13;
+    pub const EFFECTIVE_GID: u32 = 14;
+    pub const CLOCK_TICKS: u32 = 17;
+    pub const SECURE: u32 = 23;
+    pub const RANDOM: u32 = 25;
+}
+
+/// Ticks of the clock reported by `times`, as user space expects
+const CLOCK_TICKS_PER_SECOND: u32 = 100;
+const RANDOM_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Elf(elf::Error),
+    Memory(vmm::Error),
+    /// A segment is outside of user space or shares a page with another one
+    BadSegment,
+    ArgumentsTooLong,
+}
+
+impl From<elf::Error> for Error {
+    fn from(error: elf::Error) -> Self {
+        Self::Elf(error)
+    }
+}
+
+impl From<vmm::Error> for Error {
+    fn from(error: vmm::Error) -> Self {
+        Self::Memory(error)
+    }
+}
+
+/// A program ready to enter ring 3
+pub struct Program {
+    pub address_space: AddressSpace,
+    pub entry: VirtualAddress,
+    pub stack_pointer: VirtualAddress,
+}
+
+/// Map the executable `bytes` in a new address space and prepare its stack
+/// for a process with `credentials`
+pub fn load(
+    bytes: &[u8],
+    arguments: &[&str],
+    environment: &[&str],
+    credentials: &Credentials,
+) -> Result<Program, Error> {
+    let elf = elf::Elf::parse(bytes)?;
+    let mut address_space = AddressSpace::new()?;
+
+    let mut heap_start = vmm::USER_START;
+    for segment in elf.segments() {
+        let start = vmm::page_align_down(segment.virtual_address as usize);
+        let end = vmm::page_align_up(segment.end() as usize);
+        if start < vmm::USER_START || user::SIGNAL_TRAMPOLINE < end {
+            return Err(Error::BadSegment);
+        }
+
+        let mut flags = Flags::USER;
+        if segment.flags.contains(elf::SegmentFlags::WRITE) {
+            flags |= Flags::WRITABLE;
+        }
+        if !segment.flags.contains(elf::SegmentFlags::EXECUTE) {
+            flags |= Flags::NO_EXECUTE;
+        }
+        address_space
+            .reserve(start, end - start, flags)
+            .map_err(|error| match error {
+                vmm::Error::Overlapping => Error::BadSegment,
+                error => Error::Memory(error),
+            })?;
+        // The rest of the segment is zeroed by demand paging
+        address_space.write(segment.virtual_address as usize, elf.data(&segment))?;
+        heap_start = heap_start.max(end);
+    }
+    address_space.set_heap_start(heap_start);
+
+    user::reserve_stack(&mut address_space)?;
+    user::map_signal_trampoline(&mut address_space)?;
+    let auxiliary_vector = [
+        (auxiliary::PAGE_SIZE, PAGE_SIZE as u32),
+        (
+            auxiliary::PROGRAM_HEADERS,
+            elf.program_headers_address().unwrap_or(0),
+        ),
+        (
+            auxiliary::PROGRAM_HEADER_SIZE,
+            elf.header().program_header_size as u32,
+        ),
+        (
+            auxiliary::PROGRAM_HEADER_COUNT,
+            elf.header().program_header_count as u32,
+        ),
+        (auxiliary::ENTRY, elf.entry()),
+        (auxiliary::UID, credentials.user.real),
+        (auxiliary::EFFECTIVE_UID, credentials.user.effective),
+        (auxiliary::GID, credentials.group.real),
+        (auxiliary::EFFECTIVE_GID, credentials.group.effective),
+        (auxiliary::CLOCK_TICKS, CLOCK_TICKS_PER_SECOND),
+        (auxiliary::SECURE, is_secure(credentials) as u32),
+    ];
+    let stack_pointer = build_stack(
+        &mut address_space,
+        arguments,
+        environment,
+        &auxiliary_vector,
+    )?;
+
+    Ok(Program {
+        address_space,
+        entry: elf.entry() as VirtualAddress,
+        stack_pointer,
+    })
+}
+
+/// `AT_SECURE`: the program runs with ids other than those of its user, so
+/// the C library must not trust the environment
+fn is_secure(credentials: &Credentials) -> bool {
+    credentials.user.real != credentials.user.effective
+        || credentials.group.real != credentials.group.effective
+}
+
+/// Bytes for `AT_RANDOM`, user space seeds its stack protector with them
+fn random_bytes() -> [u8; RANDOM_SIZE] {
+    let mut state = asm::read_timestamp_counter() | 1;
+    let mut bytes = [0; RANDOM_SIZE];
+    for chunk in bytes.chunks_mut(size_of::<u64>()) {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        chunk.copy_from_slice(&state.to_le_bytes()[..chunk.len()]);
+    }
+    bytes
+}
+
+/// Write the initial stack at the top of the stack region, return the
+/// stack pointer
+fn build_stack(
+    address_space: &mut AddressSpace,
+    arguments: &[&str],
+    environment: &[&str],
+    auxiliary_vector: &[(u32, u32)],
+) -> Result<VirtualAddress, Error> {
+    // Strings, from the top of the stack down
+    let strings_size: usize = arguments
+        .iter()
+        .chain(environment)
+        .map(|string| string.len() + 1)
+        .sum::<usize>()
+        + RANDOM_SIZE;
+    let word_count =
+        1 + (arguments.len() + 1) + (environment.len() + 1) + 2 * (auxiliary_vector.len() + 2);
+    if MAX_ARGUMENTS_SIZE < strings_size + word_count * size_of::<u32>() {
+        return Err(Error::ArgumentsTooLong);
+    }
+
+    let strings_start = (user::STACK_TOP - strings_size) & !0xF;
+    let mut strings = Vec::with_capacity(strings_size);
+    let random = strings_start;
+    strings.extend_from_slice(&random_bytes());
+    let mut pointers = |values: &[&str]| {
+        let mut pointers = Vec::with_capacity(values.len() + 1);
+        for value in values {
+            pointers.push((strings_start + strings.len()) as u32);
+            strings.extend_from_slice(value.as_bytes());
+            strings.push(0);
+        }
+        pointers.push(0);
+        pointers
+    };
+    let argument_pointers = pointers(arguments);
+    let environment_pointers = pointers(environment);
+
+    let mut words = Vec::with_capacity(word_count);
+    words.push(arguments.len() as u32);
+    words.extend_from_slice(&argument_pointers);
+    words.extend_from_slice(&environment_pointers);
+    for &(kind, value) in auxiliary_vector {
+        words.extend_from_slice(&[kind, value]);
+    }
+    words.extend_from_slice(&[auxiliary::RANDOM, random as u32]);
+    words.extend_from_slice(&[auxiliary::NULL, 0]);
+
+    // The ABI wants the stack pointer aligned on 16 bytes at the entry point
+    let stack_pointer = (strings_start - words.len() * size_of::<u32>()) & !0xF;
+    let words: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    address_space.write(strings_start, &strings)?;
+    address_space.write(stack_pointer, &words)?;
+    Ok(stack_pointer)
+}
@@ -1,7 +1,46 @@
 #![no_std]
 #![no_main]
-
+#![feature(alloc_error_handler)]
+
+extern crate alloc;
+
+mod console;
+mod credentials;
+mod file;
+mod frame;
+mod futex;
+mod gdt;
+mod heap;
+mod interrupts;
+mod linker;
+mod loader;
+mod meminfo;
+mod module;
+mod mutex;
+mod pic;
+mod pit;
+mod process;
+mod shell;
+mod signal;
+mod slab;
+mod syscall;
+mod task;
+mod timer;
+mod user;
+mod vmalloc;
+mod vmm;
+mod wait_queue;
+
+use alloc::collections::VecDeque;
+
+use sync::SpinLock;
 use tui::{TextBuffer, Widget};
+use wait_queue::WaitQueue;
+
+/// Keyboard events decoded but not handled by the screen yet
+static EVENTS: SpinLock<VecDeque<keyboard::Event>> = SpinLock::new(VecDeque::new());
+/// Woken when [EVENTS] gets an event
+static EVENTS_READY: WaitQueue = WaitQueue::new();
 
 #[panic_handler]
 fn panic_handler(info: &core::panic::PanicInfo) -> ! {
@@ -15,9 +54,25 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
     }
 }
 
+#[alloc_error_handler]
+fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
+    #[cfg(feature = "heap-debug")]
+    heap::report_leaks();
+    let heap = heap::statistics();
+    panic!(
+        "Could not allocate {} bytes aligned on {} (heap: {} used out of {})",
+        layout.size(),
+        layout.align(),
+        heap.used,
+        heap.size
+    );
+}
+
 enum Entry {
+    Prompt(tui::Prompt),
     Log(tui::Logger),
     Text(TextBuffer),
+    Report(tui::Report),
 }
 
 impl Widget for Entry {
@@ -25,29 +80,64 @@ impl Widget for Entry {
 
     fn render(&self, screen: &mut tui::Screen, area: tui::Rectangle) {
         match self {
+            Entry::Prompt(prompt) => prompt.render(screen, area),
             Entry::Log(logger) => logger.render(screen, area),
             Entry::Text(text_buffer) => text_buffer.render(screen, area),
+            Entry::Report(report) => report.render(screen, area),
         }
     }
 
     fn update(&mut self, event: Self::Event) {
         match self {
+            Entry::Prompt(prompt) => prompt.update(event),
             Entry::Log(logger) => logger.update(event),
             Entry::Text(text_buffer) => text_buffer.update(event),
+            Entry::Report(report) => report.update(event),
         }
     }
 }
 
 #[no_mangle]
-extern "C" fn entrypoint() {
+extern "C" fn entrypoint(magic: u32, info_address: usize) {
     log::info!("42");
 
+    assert!(
+        magic == multiboot::BOOTLOADER_MAGIC,
+        "Not started by a multiboot2 boot loader (magic: {magic:#x})"
+    );
+    gdt::init();
+    interrupts::init();
+    pic::init();
+
+    let info = unsafe { multiboot::Info::from_address(info_address) };
+    frame::init(&info, info_address);
+    vmm::init();
+    // The boot information is not always identity mapped
+    let info_window = vmm::physical_to_virtual(info_address as vmm::PhysicalAddress);
+    let info = unsafe { multiboot::Info::from_address(info_window) };
+    frame::init_high_memory(&info, info_address);
+    module::init(&info);
+    let frames = frame::statistics();
+    log::info!(
+        "Paging enabled (PAE: {}, NX: {}), {} KiB free out of {} KiB",
+        vmm::is_pae_enabled(),
+        vmm::is_nx_enabled(),
+        frames.free * vmm::PAGE_SIZE / 1024,
+        frames.total * vmm::PAGE_SIZE / 1024
+    );
+
     log::trace!("TRACE");
     log::debug!("DEBUG");
     log::info!("INFO");
     log::warn!("WARNING");
     log::error!("ERROR");
 
+    task::init();
+    pit::init();
+    asm::enable_interrupts();
+    timer::init();
+    process::init();
+
     let mut port_manager = port::MANAGER.lock();
     let Ok(data_port) = port_manager.try_aquire() else {
         return;
@@ -55,6 +145,7 @@ extern "C" fn entrypoint() {
     let Ok(controller_port) = port_manager.try_aquire() else {
         return;
     };
+    drop(port_manager);
 
     let mut ps2_controller = ps2::Controller {
         data_port,
@@ -65,17 +156,62 @@ extern "C" fn entrypoint() {
         Ok((port_1_type, port_2_type)) => log::info!("{port_1_type:?}, {port_2_type:?}"),
         Err(()) => log::error!("Could not initialize ps2 ports"),
     }
+    ps2_controller.enable_first_port_interrupt();
+    pic::unmask(pic::KEYBOARD);
+
+    let threads = [
+        task::spawn_with_priority("keyboard", task::Priority::High, move || {
+            read_keyboard(ps2_controller)
+        }),
+        task::spawn("screen", draw_screen),
+    ];
+    if process::spawn_kernel("shell", shell::run).is_err() {
+        log::error!("Could not start the shell");
+    }
+    for thread in threads {
+        match thread {
+            Ok(thread) => thread.join(),
+            Err(()) => log::error!("Could not spawn a kernel thread"),
+        }
+    }
+}
 
+/// Decode the bytes of the keyboard into [EVENTS]
+fn read_keyboard(mut ps2_controller: ps2::Controller) {
     let mut decoder = ps2::keyboard::Decoder::ReadNothing;
+    loop {
+        let byte =
+            interrupts::wait_for_irq(pic::KEYBOARD, || ps2_controller.try_read_without_origin());
+        match decoder.feed(byte) {
+            Ok(Some(event)) => {
+                EVENTS.lock().push_back(event);
+                EVENTS_READY.wake_all();
+            }
+            Ok(None) => (),
+            Err(err) => panic!("Could not decode ps2 bytes: {err:?}"),
+        }
+    }
+}
 
-    let keyboard = keyboard::Keyboard::qwerty();
-    // let keyboard = keyboard::Keyboard::ergol();
+/// Longest time between two renders of the screen, for the log to show up
+const FRAME_MILLISECONDS: u64 = 16;
+
+/// Render the widgets, updated with the events of [EVENTS]
+fn draw_screen() {
+    let keyboard = || keyboard::Keyboard::qwerty();
+    // let keyboard = || keyboard::Keyboard::ergol();
 
     let mut screen = tui::Screen::default();
 
     let mut root_widget = tui::MultiScreen::new([
+        Entry::Prompt(tui::Prompt::new(
+            keyboard(),
+            console::submit,
+            console::interrupt,
+        )),
         Entry::Log(tui::Logger),
-        Entry::Text(tui::TextBuffer::new(keyboard)),
+        Entry::Text(tui::TextBuffer::new(keyboard())),
+        Entry::Report(tui::Report::new(meminfo::report)),
     ]);
 
     loop {
@@ -88,13 +224,17 @@ extern "C" fn entrypoint() {
             screen.write_to_vga();
         }
 
-        let event = match decoder.feed(ps2_controller.read_without_origin()) {
-            Ok(Some(event)) => event,
-            Ok(None) => continue,
-            Err(err) => panic!("Could not decode ps2 bytes: {err:?}"),
-        };
-
-        log::debug!("Got event: {event:?}");
-        Widget::update(&mut root_widget, event);
+        loop {
+            let event = EVENTS.lock().pop_front();
+            let Some(event) = event else {
+                break;
+            };
+            log::debug!("Got event: {event:?}");
+            Widget::update(&mut root_widget, event);
+        }
+        // Input is handled at once
+        EVENTS_READY.wait_timeout(pit::milliseconds_to_ticks(FRAME_MILLISECONDS), || {
+            (!EVENTS.lock().is_empty()).then_some(())
+        });
     }
 }
@@ -0,0 +1,117 @@
+//! System wide memory accounting
+
+use core::fmt::{self, Write};
+
+use collections::ArrayVec;
+
+use crate::{
+    frame, heap, linker, slab, vmalloc,
+    vmm::{self, PAGE_SIZE},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelImage {
+    pub text: usize,
+    pub rodata: usize,
+    /// `.data` and `.bss`
+    pub data: usize,
+    pub total: usize,
+}
+
+pub struct MemInfo {
+    pub frames: frame::Statistics,
+    /// Frames used by paging structures
+    pub table_frames: usize,
+    pub heap: heap::Statistics,
+    pub vmalloc: vmalloc::Statistics,
+    pub slabs: ArrayVec<{ slab::MAX_REGISTERED_CACHES }, slab::Statistics>,
+    pub kernel: KernelImage,
+}
+
+/// Snapshot of every memory statistic
+///
+/// Allocators are queried one after the other, so the numbers may be
+/// slightly inconsistent with each other.
+pub fn collect() -> MemInfo {
+    let (text, rodata, data) = (linker::text(), linker::rodata(), linker::data());
+    MemInfo {
+        frames: frame::statistics(),
+        table_frames: vmm::table_frames(),
+        heap: heap::statistics(),
+        vmalloc: vmalloc::statistics(),
+        slabs: slab::statistics(),
+        kernel: KernelImage {
+            text: text.len(),
+            rodata: rodata.len(),
+            data: data.len(),
+            total: linker::kernel_end() - linker::kernel_start(),
+        },
+    }
+}
+
+const fn kib(frames: usize) -> usize {
+    frames * PAGE_SIZE / 1024
+}
+
+/// Human readable report of [collect], as shown by the meminfo screen
+pub fn report(writer: &mut dyn Write) -> fmt::Result {
+    let info = collect();
+    let frames = &info.frames;
+    writeln!(
+        writer,
+        "Frames: {} KiB free, {} KiB total, {} KiB reserved",
+        kib(frames.free),
+        kib(frames.total),
+        kib(frames.reserved)
+    )?;
+    for zone in &frames.zones {
+        write!(
+            writer,
+            "  {:?}: {} KiB free of {} KiB, blocks per order:",
+            zone.zone,
+            kib(zone.free),
+            kib(zone.total)
+        )?;
+        for count in zone.free_blocks {
+            write!(writer, " {count}")?;
+        }
+        writeln!(writer)?;
+    }
+    writeln!(writer, "Page tables: {} KiB", kib(info.table_frames))?;
+    writeln!(
+        writer,
+        "Heap: {} KiB used, {} KiB free, {} KiB total",
+        info.heap.used / 1024,
+        info.heap.free() / 1024,
+        info.heap.size / 1024
+    )?;
+    writeln!(
+        writer,
+        "vmalloc: {} areas, {} KiB used, {} KiB of address space",
+        info.vmalloc.areas,
+        info.vmalloc.used / 1024,
+        info.vmalloc.size / 1024
+    )?;
+    writeln!(
+        writer,
+        "Kernel: {} KiB (text {} KiB, rodata {} KiB, data {} KiB)",
+        info.kernel.total / 1024,
+        info.kernel.text / 1024,
+        info.kernel.rodata / 1024,
+        info.kernel.data / 1024
+    )?;
+    writeln!(writer, "Slab caches:")?;
+    for cache in info.slabs.iter() {
+        writeln!(
+            writer,
+            "  {}: {} B objects, {}/{} active, {} slabs ({} KiB)",
+            cache.name,
+            cache.object_size,
+            cache.active_objects,
+            cache.total_objects(),
+            cache.slabs,
+            cache.size() / 1024
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+//! Files loaded by the boot loader next to the kernel, such as programs
+//!
+//! Their frames are reserved, they stay readable through the physical window.
+
+use collections::ArrayVec;
+use sync::SpinLock;
+
+use crate::{
+    credentials::{self, Permissions},
+    vmm,
+};
+
+pub const MAX_MODULES: usize = 32;
+
+/// Modules belong to root, anyone may read and run them
+pub const PERMISSIONS: Permissions = Permissions {
+    owner: credentials::ROOT,
+    group: 0,
+    mode: 0o555,
+};
+
+static MODULES: SpinLock<ArrayVec<MAX_MODULES, Module>> = SpinLock::new(ArrayVec::new());
+
+#[derive(Debug, Clone, Copy)]
+pub struct Module {
+    /// First word of the command line
+    pub name: &'static str,
+    pub command_line: &'static str,
+    pub data: &'static [u8],
+}
+
+/// Remember the modules of `info`, which must be read through the physical
+/// window
+pub fn init(info: &multiboot::Info<'static>) {
+    let mut modules = MODULES.lock();
+    for module in info.modules() {
+        let start = vmm::physical_to_virtual(module.start as vmm::PhysicalAddress);
+        let data = unsafe { core::slice::from_raw_parts(start as *const u8, module.len()) };
+        let command_line = module.command_line;
+        let name = command_line.split_whitespace().next().unwrap_or("");
+        let module = Module {
+            name,
+            command_line,
+            data,
+        };
+        if modules.push(module).is_err() {
+            log::warn!("Ignoring boot module {name:?}, too many modules");
+        }
+    }
+}
+
+pub fn find(name: &str) -> Option<Module> {
+    MODULES
+        .lock()
+        .iter()
+        .find(|module| module.name == name)
+        .copied()
+}
+
+pub fn modules() -> ArrayVec<MAX_MODULES, Module> {
+    let mut copy = ArrayVec::new();
+    let _ = copy.extend_from_slice(&MODULES.lock());
+    copy
+}
@@ -0,0 +1,94 @@
+//! 8259 Programmable Interrupt Controllers
+//!
+//! Based of [OSDev.org](https://wiki.osdev.org/8259_PIC)
+
+use asm::IOPort;
+
+const MASTER_COMMAND: IOPort = 0x20;
+const MASTER_DATA: IOPort = 0x21;
+const SLAVE_COMMAND: IOPort = 0xA0;
+const SLAVE_DATA: IOPort = 0xA1;
+
+/// Vector of IRQ 0, the default one overlaps with CPU exceptions
+pub const OFFSET: u32 = 0x20;
+pub const IRQ_COUNT: u8 = 16;
+
+pub const TIMER: u8 = 0;
+pub const KEYBOARD: u8 = 1;
+/// Line of the master the slave is wired to
+const CASCADE: u8 = 2;
+
+const INIT: u8 = 0x11;
+const MODE_8086: u8 = 0x01;
+const END_OF_INTERRUPT: u8 = 0x20;
+const READ_IN_SERVICE: u8 = 0x0B;
+
+/// Give the controllers some time to handle the previous command
+fn wait() {
+    asm::out8(0x80, 0);
+}
+
+/// Move the IRQs to [OFFSET] and mask all of them
+pub fn init() {
+    for (command, data, offset, wiring) in [
+        (MASTER_COMMAND, MASTER_DATA, OFFSET, 1 << CASCADE),
+        (SLAVE_COMMAND, SLAVE_DATA, OFFSET + 8, CASCADE),
+    ] {
+        asm::out8(command, INIT);
+        wait();
+        asm::out8(data, offset as u8);
+        wait();
+        asm::out8(data, wiring);
+        wait();
+        asm::out8(data, MODE_8086);
+        wait();
+    }
+    asm::out8(MASTER_DATA, !(1 << CASCADE));
+    asm::out8(SLAVE_DATA, 0xFF);
+}
+
+pub fn irq_of_vector(vector: u32) -> Option<u8> {
+    match (OFFSET..OFFSET + IRQ_COUNT as u32).contains(&vector) {
+        true => Some((vector - OFFSET) as u8),
+        false => None,
+    }
+}
+
+fn data_port(irq: u8) -> (IOPort, u8) {
+    match irq < 8 {
+        true => (MASTER_DATA, irq),
+        false => (SLAVE_DATA, irq - 8),
+    }
+}
+
+pub fn unmask(irq: u8) {
+    let (port, line) = data_port(irq);
+    asm::out8(port, asm::in8(port) & !(1 << line));
+}
+
+/// Combined in-service registers, the slave in the high byte
+fn in_service() -> u16 {
+    asm::out8(MASTER_COMMAND, READ_IN_SERVICE);
+    asm::out8(SLAVE_COMMAND, READ_IN_SERVICE);
+    (asm::in8(SLAVE_COMMAND) as u16) << 8 | asm::in8(MASTER_COMMAND) as u16
+}
+
+/// Whether `irq` was raised by noise rather than a device, such an IRQ must
+/// not be acknowledged by the controller that raised it
+pub fn is_spurious(irq: u8) -> bool {
+    if irq != 7 && irq != 15 || in_service() & (1 << irq) != 0 {
+        return false;
+    }
+    if irq == 15 {
+        // The master did see a real IRQ on the cascade line
+        asm::out8(MASTER_COMMAND, END_OF_INTERRUPT);
+    }
+    true
+}
+
+pub fn end_of_interrupt(irq: u8) {
+    if 8 <= irq {
+        asm::out8(SLAVE_COMMAND, END_OF_INTERRUPT);
+    }
+    asm::out8(MASTER_COMMAND, END_OF_INTERRUPT);
+}
@@ -0,0 +1,49 @@
+//! Programmable Interval Timer, the periodic tick of the scheduler
+//!
+//! Based of [OSDev.org](https://wiki.osdev.org/Programmable_Interval_Timer)
+
+use asm::IOPort;
+use sync::SpinLock;
+
+use crate::{interrupts, pic};
+
+const CHANNEL_0: IOPort = 0x40;
+const COMMAND: IOPort = 0x43;
+
+/// Channel 0, low then high byte of the divisor, rate generator
+const PERIODIC: u8 = 0x34;
+const BASE_FREQUENCY: u32 = 1_193_182;
+
+/// Ticks per second
+pub const FREQUENCY: u32 = 1000;
+
+/// Ticks since [init], only updated by the timer interrupt
+static TICKS: SpinLock<u64> = SpinLock::new(0);
+
+pub fn init() {
+    let divisor = (BASE_FREQUENCY / FREQUENCY) as u16;
+    asm::out8(COMMAND, PERIODIC);
+    asm::out8(CHANNEL_0, divisor as u8);
+    asm::out8(CHANNEL_0, (divisor >> 8) as u8);
+    pic::unmask(pic::TIMER);
+}
+
+/// Count a tick, called by the timer interrupt
+pub fn tick() -> u64 {
+    let mut ticks = TICKS.lock();
+    *ticks += 1;
+    *ticks
+}
+
+pub fn ticks() -> u64 {
+    let _interrupts = interrupts::disable();
+    *TICKS.lock()
+}
+
+pub const fn milliseconds_to_ticks(milliseconds: u64) -> u64 {
+    (milliseconds * FREQUENCY as u64).div_ceil(1000)
+}
+
+pub const fn ticks_to_milliseconds(ticks: u64) -> u64 {
+    ticks * 1000 / FREQUENCY as u64
+}
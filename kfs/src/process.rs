@@ -0,0 +1,554 @@
+//! Processes: programs with an id, a parent and an exit status
+//!
+//! Every process runs in a task of its own. When it exits, it stays a zombie
+//! until its parent waits for it, and its children are given to init, which
+//! waits for them. Some kernel tasks, init and the shell, are processes too
+//! so that they can be parents. Programs can not send them signals.
+//!
+//! Processes act for the users of their [Credentials], inherited through
+//! `fork` and `execve`. Kernel processes and the programs they start act for
+//! root.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::fmt;
+
+use sync::SpinLock;
+
+use crate::{
+    credentials::Credentials,
+    file, gdt,
+    interrupts::Frame,
+    loader,
+    mutex::Mutex,
+    signal::{Info, Signal, Signals},
+    slab::{Cache, SlabBox},
+    task::{self, TaskId},
+    timer::{self, TimerId},
+    user,
+    vmm::{self, AddressSpace, VirtualAddress},
+};
+
+static PROCESSES: Mutex<Table> = Mutex::new(Table::new());
+static DESCRIPTORS: Cache<Process> = Cache::new("process");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProcessId(u32);
+
+impl ProcessId {
+    /// Parent of the orphans
+    pub const INIT: Self = Self(1);
+    /// Parent of init
+    const NONE: Self = Self(0);
+
+    pub fn as_u32(self) -> u32 {
+        self.0
+    }
+}
+
+impl From<u32> for ProcessId {
+    fn from(id: u32) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for ProcessId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Exited(u8),
+    /// By the signal with this number
+    Killed(u8),
+}
+
+impl ExitStatus {
+    /// Encoding of `wait`: the exit code in the second byte, or the signal
+    /// in the first one
+    pub fn wait_status(self) -> u32 {
+        match self {
+            ExitStatus::Exited(code) => (code as u32) << 8,
+            ExitStatus::Killed(signal) => signal as u32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Running,
+    /// Exited, until its parent collects the status
+    Zombie(ExitStatus),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    OutOfMemory,
+    /// The task table is full
+    TooManyTasks,
+    /// Every timer is taken
+    TooManyTimers,
+    /// The caller is not a process running a program
+    NotAProgram,
+}
+
+impl From<vmm::Error> for Error {
+    fn from(_: vmm::Error) -> Self {
+        Self::OutOfMemory
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// The current process has no such child
+    NoChild,
+    /// A signal arrived before a child exited
+    Interrupted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalError {
+    NoProcess,
+    /// Kernel processes and zombies take no signals, programs only take
+    /// those of their users or of root
+    NotPermitted,
+}
+
+/// Children [wait] collects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Child {
+    Any,
+    Id(ProcessId),
+}
+
+struct Process {
+    parent: ProcessId,
+    task: TaskId,
+    name: &'static str,
+    state: State,
+    /// Blocked in [wait]
+    waiting: bool,
+    /// Runs in a kernel task, rather than a program
+    kernel: bool,
+    credentials: Credentials,
+    signals: Signals,
+    files: file::Table,
+    /// Sends [Signal::ALRM], with the interval of the alarm, see [set_alarm]
+    alarm: Option<(TimerId, u64)>,
+}
+
+impl Process {
+    /// Tell the task whether a signal waits, after a change of the signals
+    fn update_interrupted(&self) {
+        task::set_interrupted(self.task, self.signals.is_interrupted());
+    }
+}
+
+/// [Signal::ALRM] sent at the tick `deadline`, then every `interval` ticks
+/// unless it is 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alarm {
+    pub deadline: u64,
+    pub interval: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub id: ProcessId,
+    pub parent: ProcessId,
+    pub task: TaskId,
+    pub name: &'static str,
+    pub state: State,
+    pub credentials: Credentials,
+}
+
+struct Table {
+    processes: BTreeMap<ProcessId, SlabBox<Process>>,
+    next_id: u32,
+}
+
+impl Table {
+    const fn new() -> Self {
+        Self {
+            processes: BTreeMap::new(),
+            next_id: ProcessId::INIT.0,
+        }
+    }
+
+    fn of_task(&self, task: TaskId) -> Option<ProcessId> {
+        self.processes
+            .iter()
+            .find(|(_, process)| process.task == task)
+            .map(|(&id, _)| id)
+    }
+
+    fn current(&self) -> Option<ProcessId> {
+        self.of_task(task::current())
+    }
+
+    fn wake(&self, id: ProcessId) {
+        if let Some(process) = self.processes.get(&id) {
+            task::wake(process.task);
+        }
+    }
+}
+
+/// Make the task `spawn` starts a process, child of `parent`
+///
+/// The table stays locked until it is registered, so that the task can not
+/// look itself up before.
+fn register(
+    name: &'static str,
+    parent: ProcessId,
+    kernel: bool,
+    credentials: Credentials,
+    signals: Signals,
+    files: file::Table,
+    spawn: impl FnOnce() -> Result<task::JoinHandle, ()>,
+) -> Result<ProcessId, Error> {
+    // The task is only known once spawned
+    let process = DESCRIPTORS.boxed(Process {
+        parent,
+        task: task::current(),
+        name,
+        state: State::Running,
+        waiting: false,
+        kernel,
+        credentials,
+        signals,
+        files,
+        alarm: None,
+    });
+    let mut process = process.ok_or(Error::OutOfMemory)?;
+    let mut table = PROCESSES.lock();
+    // Dropping the handle detaches the task, the process record outlives it
+    process.task = spawn().map_err(|()| Error::TooManyTasks)?.id();
+    let id = ProcessId(table.next_id);
+    table.next_id += 1;
+    table.processes.insert(id, process);
+    Ok(id)
+}
+
+/// Start init, the first process, which collects the orphans
+///
+/// Requires the scheduler.
+pub fn init() {
+    DESCRIPTORS
+        .register()
+        .expect("Could not register the process cache");
+    let init = register(
+        "init",
+        ProcessId::NONE,
+        true,
+        Credentials::ROOT,
+        Signals::default(),
+        file::Table::default(),
+        || task::spawn("init", collect_orphans),
+    );
+    assert_eq!(init, Ok(ProcessId::INIT), "Could not start init");
+}
+
+/// Body of init
+fn collect_orphans() {
+    loop {
+        if wait(Child::Any, false) == Err(WaitError::NoChild) {
+            // Woken when an orphan is adopted
+            task::block();
+        }
+    }
+}
+
+/// Run `entry` in a kernel task that is a process, child of the current one
+pub fn spawn_kernel(name: &'static str, entry: fn()) -> Result<ProcessId, Error> {
+    let parent = current().unwrap_or(ProcessId::INIT);
+    register(
+        name,
+        parent,
+        true,
+        Credentials::ROOT,
+        Signals::default(),
+        file::Table::default(),
+        || {
+            task::spawn(name, move || {
+                entry();
+                exit(ExitStatus::Exited(0));
+            })
+        },
+    )
+}
+
+/// Run the program of `address_space` from `entry` with the stack pointer
+/// `stack`, as a child of the current process with the console as standard
+/// streams
+pub fn spawn_user(
+    name: &'static str,
+    address_space: AddressSpace,
+    entry: VirtualAddress,
+    stack: VirtualAddress,
+) -> Result<ProcessId, Error> {
+    let parent = current().unwrap_or(ProcessId::INIT);
+    let address_space = Arc::new(SpinLock::new(address_space));
+    let frame = user::initial_frame(entry, stack);
+    let credentials = Credentials::ROOT;
+    let console = file::open("/dev/console", file::Access::READ_WRITE, &credentials)
+        .expect("root can always open the console");
+    let files = file::Table::standard(console);
+    register(
+        name,
+        parent,
+        false,
+        credentials,
+        Signals::default(),
+        files,
+        || task::spawn_user(name, address_space, gdt::Descriptor::NULL, frame),
+    )
+}
+
+/// Duplicate the current process, the child resumes from the context
+/// `frame` of the parent, with eax cleared
+pub fn fork(frame: &Frame) -> Result<ProcessId, Error> {
+    let (parent, name, credentials, signals, files) = {
+        let table = PROCESSES.lock();
+        let id = table.current().ok_or(Error::NotAProgram)?;
+        let process = &table.processes[&id];
+        let files = process.files.clone();
+        let signals = process.signals.forked();
+        (id, process.name, process.credentials, signals, files)
+    };
+    let address_space = task::address_space().ok_or(Error::NotAProgram)?;
+    let copy = address_space.lock().duplicate()?;
+    let copy = Arc::new(SpinLock::new(copy));
+    let frame = Frame { eax: 0, ..*frame };
+    let thread_local = task::thread_local();
+    register(name, parent, false, credentials, signals, files, || {
+        task::spawn_user(name, copy, thread_local, frame)
+    })
+}
+
+/// Replace the program of the current process by `program`, named `name`
+pub fn exec(name: &'static str, program: loader::Program) -> ! {
+    let closed = {
+        let mut table = PROCESSES.lock();
+        match table.current() {
+            Some(id) => {
+                let process = table.processes.get_mut(&id).unwrap();
+                process.name = name;
+                process.signals.reset_handlers();
+                process.files.exec()
+            }
+            None => Vec::new(),
+        }
+    };
+    // Closing pipes wakes the tasks at their other end
+    drop(closed);
+    let address_space = Arc::new(SpinLock::new(program.address_space));
+    task::replace_program(name, address_space);
+    user::enter(program.entry, program.stack_pointer)
+}
+
+/// End the current process with `status`, its children are given to init
+/// and its parent gets [Signal::CHLD]
+pub fn exit(status: ExitStatus) -> ! {
+    // Closed first, so that the parent sees the end of the pipes
+    drop(with_current_files(core::mem::take));
+    {
+        let mut table = PROCESSES.lock();
+        if let Some(id) = table.current() {
+            assert!(id != ProcessId::INIT, "init exited");
+            let mut orphans = false;
+            for process in table.processes.values_mut() {
+                if process.parent == id {
+                    process.parent = ProcessId::INIT;
+                    orphans = true;
+                }
+            }
+            if orphans {
+                table.wake(ProcessId::INIT);
+            }
+
+            let process = table.processes.get_mut(&id).unwrap();
+            process.state = State::Zombie(status);
+            if let Some((alarm, _)) = process.alarm.take() {
+                timer::cancel(alarm);
+            }
+            let parent = process.parent;
+            if let Some(process) = table.processes.get_mut(&parent) {
+                let woken = process.signals.send(Signal::CHLD, Info::sent_by(id, false));
+                process.update_interrupted();
+                if woken || process.waiting {
+                    task::wake(process.task);
+                }
+            }
+        }
+    }
+    task::exit()
+}
+
+/// Collect a zombie `child` of the current process, waiting for one to exit
+/// unless `no_hang` is set
+///
+/// Fails when the current process has no such child, or when a signal
+/// interrupts the wait.
+pub fn wait(child: Child, no_hang: bool) -> Result<Option<(ProcessId, ExitStatus)>, WaitError> {
+    loop {
+        {
+            let mut table = PROCESSES.lock();
+            let current = table.current().ok_or(WaitError::NoChild)?;
+            let mut children = table.processes.iter().filter(|&(&id, process)| {
+                process.parent == current && (child == Child::Any || child == Child::Id(id))
+            });
+            let mut found = false;
+            let zombie = children.find_map(|(&id, process)| {
+                found = true;
+                match process.state {
+                    State::Zombie(status) => Some((id, status)),
+                    State::Running => None,
+                }
+            });
+
+            let process = table.processes.get_mut(&current).unwrap();
+            let interrupted = process.signals.is_interrupted();
+            let waiting = zombie.is_none() && found && !no_hang && !interrupted;
+            process.waiting = waiting;
+            match (zombie, found) {
+                (Some((id, status)), _) => {
+                    table.processes.remove(&id);
+                    return Ok(Some((id, status)));
+                }
+                (None, false) => return Err(WaitError::NoChild),
+                (None, true) if no_hang => return Ok(None),
+                (None, true) if interrupted => return Err(WaitError::Interrupted),
+                (None, true) => (),
+            }
+        }
+        task::block();
+    }
+}
+
+/// Send `signal` to the process `id`, waking it so that it notices
+///
+/// Without a signal, only check that the process could be sent one.
+pub fn signal(id: ProcessId, signal: Option<Signal>, info: Info) -> Result<(), SignalError> {
+    send(id, signal, info, None)
+}
+
+/// Like [signal], on behalf of a process with `sender` credentials
+pub fn signal_as(
+    sender: &Credentials,
+    id: ProcessId,
+    signal: Option<Signal>,
+    info: Info,
+) -> Result<(), SignalError> {
+    send(id, signal, info, Some(sender))
+}
+
+fn send(
+    id: ProcessId,
+    signal: Option<Signal>,
+    info: Info,
+    sender: Option<&Credentials>,
+) -> Result<(), SignalError> {
+    let mut table = PROCESSES.lock();
+    let process = table.processes.get_mut(&id).ok_or(SignalError::NoProcess)?;
+    let allowed = sender.is_none_or(|sender| sender.may_signal(&process.credentials));
+    if process.kernel || process.state != State::Running || !allowed {
+        return Err(SignalError::NotPermitted);
+    }
+    if let Some(signal) = signal {
+        let woken = process.signals.send(signal, info);
+        process.update_interrupted();
+        if woken {
+            task::wake(process.task);
+        }
+    }
+    Ok(())
+}
+
+/// Send [Signal::ALRM] to the current process as `alarm` says, or never,
+/// replacing its previous alarm
+///
+/// Gives the previous alarm, if its next deadline was not reached. The
+/// previous alarm stays when there is no room for the new one.
+pub fn set_alarm(alarm: Option<Alarm>) -> Result<Option<Alarm>, Error> {
+    let mut table = PROCESSES.lock();
+    let id = table.current().ok_or(Error::NotAProgram)?;
+    let process = table.processes.get_mut(&id).unwrap();
+    let timer = alarm
+        .map(|alarm| {
+            let send = move || {
+                let _ = signal(id, Some(Signal::ALRM), Info::kernel());
+            };
+            let timer = match alarm.interval {
+                0 => timer::call_at(alarm.deadline, send),
+                interval => timer::call_every(alarm.deadline, interval, send),
+            };
+            timer.map(|timer| (timer, alarm.interval))
+        })
+        .transpose()
+        .map_err(|()| Error::TooManyTimers)?;
+    let previous = core::mem::replace(&mut process.alarm, timer);
+    Ok(previous.and_then(|(timer, interval)| {
+        let deadline = timer::cancel(timer)?;
+        Some(Alarm { deadline, interval })
+    }))
+}
+
+/// Run `f` on the signal state of the current process, if it is one
+///
+/// The process table is locked meanwhile, so interrupts must be enabled.
+pub fn with_current_signals<T>(f: impl FnOnce(&mut Signals) -> T) -> Option<T> {
+    let mut table = PROCESSES.lock();
+    let id = table.current()?;
+    let process = table.processes.get_mut(&id)?;
+    let value = f(&mut process.signals);
+    process.update_interrupted();
+    Some(value)
+}
+
+/// Run `f` on the credentials of the current process, if it is one
+pub fn with_current_credentials<T>(f: impl FnOnce(&mut Credentials) -> T) -> Option<T> {
+    let mut table = PROCESSES.lock();
+    let id = table.current()?;
+    Some(f(&mut table.processes.get_mut(&id)?.credentials))
+}
+
+pub fn credentials() -> Option<Credentials> {
+    with_current_credentials(|credentials| *credentials)
+}
+
+/// Run `f` on the descriptor table of the current process, if it is one
+///
+/// The process table is locked meanwhile, so files `f` takes out of the
+/// table should be dropped after it returns.
+pub fn with_current_files<T>(f: impl FnOnce(&mut file::Table) -> T) -> Option<T> {
+    let mut table = PROCESSES.lock();
+    let id = table.current()?;
+    Some(f(&mut table.processes.get_mut(&id)?.files))
+}
+
+pub fn current() -> Option<ProcessId> {
+    PROCESSES.lock().current()
+}
+
+pub fn parent() -> Option<ProcessId> {
+    let table = PROCESSES.lock();
+    let id = table.current()?;
+    Some(table.processes[&id].parent)
+}
+
+pub fn processes() -> Vec<ProcessInfo> {
+    let table = PROCESSES.lock();
+    table
+        .processes
+        .iter()
+        .map(|(&id, process)| ProcessInfo {
+            id,
+            parent: process.parent,
+            task: process.task,
+            name: process.name,
+            state: process.state,
+            credentials: process.credentials,
+        })
+        .collect()
+}
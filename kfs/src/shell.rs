@@ -0,0 +1,254 @@
+//! Line based command interpreter, fed by the prompt widget
+
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+
+use sync::SpinLock;
+
+use crate::{
+    console,
+    credentials::Credentials,
+    loader, meminfo, module, pit,
+    process::{self, Child},
+    task, user,
+    wait_queue::WaitQueue,
+};
+
+/// Lines submitted but not handled yet
+static LINES: SpinLock<VecDeque<String>> = SpinLock::new(VecDeque::new());
+/// Woken when a line is submitted
+static SUBMITTED: WaitQueue = WaitQueue::new();
+
+struct Command {
+    name: &'static str,
+    description: &'static str,
+    run: fn(&str),
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "help",
+        description: "list the commands",
+        run: help,
+    },
+    Command {
+        name: "echo",
+        description: "print its arguments",
+        run: echo,
+    },
+    Command {
+        name: "sleep",
+        description: "wait for a number of milliseconds",
+        run: sleep,
+    },
+    Command {
+        name: "tasks",
+        description: "list the kernel tasks",
+        run: tasks,
+    },
+    Command {
+        name: "nice",
+        description: "set the priority of a task: low, normal or high",
+        run: nice,
+    },
+    Command {
+        name: "slice",
+        description: "set the time slice of a task, in ticks",
+        run: slice,
+    },
+    Command {
+        name: "ps",
+        description: "list the processes",
+        run: ps,
+    },
+    Command {
+        name: "meminfo",
+        description: "print the memory usage",
+        run: meminfo,
+    },
+    Command {
+        name: "modules",
+        description: "list the boot modules",
+        run: modules,
+    },
+    Command {
+        name: "run",
+        description: "run the program of a boot module with arguments and wait for it",
+        run: run_module,
+    },
+    Command {
+        name: "fault",
+        description: "run a program that faults in ring 3",
+        run: fault,
+    },
+];
+
+/// Queue `line` for the shell task
+pub fn submit(line: &str) {
+    LINES.lock().push_back(String::from(line));
+    SUBMITTED.wake_one();
+}
+
+/// Body of the shell task
+pub fn run() {
+    loop {
+        let line = SUBMITTED.wait_until(|| LINES.lock().pop_front());
+        execute(&line);
+        // The screen shows the output before the next line runs
+        task::yield_now();
+    }
+}
+
+fn execute(line: &str) {
+    let line = line.trim();
+    if line.is_empty() {
+        return;
+    }
+    log::info!("> {line}");
+    let (name, arguments) = line.split_once(' ').unwrap_or((line, ""));
+    match COMMANDS.iter().find(|command| command.name == name) {
+        Some(command) => (command.run)(arguments.trim_start()),
+        None => log::error!("{name}: command not found"),
+    }
+}
+
+fn help(_: &str) {
+    for command in COMMANDS {
+        log::info!("{}: {}", command.name, command.description);
+    }
+}
+
+fn echo(arguments: &str) {
+    log::info!("{arguments}");
+}
+
+fn sleep(arguments: &str) {
+    match arguments.parse::<u64>() {
+        Ok(milliseconds) => task::sleep_milliseconds(milliseconds),
+        Err(_) => log::error!("sleep: usage: sleep <milliseconds>"),
+    }
+}
+
+fn tasks(_: &str) {
+    for info in task::tasks().iter() {
+        let statistics = &info.statistics;
+        log::info!(
+            "{} {} {:?} {:?}, {} ms on cpu {}, {} switches",
+            info.id,
+            info.name,
+            info.state,
+            info.priority,
+            pit::ticks_to_milliseconds(statistics.runtime),
+            statistics.last_cpu,
+            statistics.switches
+        );
+    }
+}
+
+fn nice(arguments: &str) {
+    let mut arguments = arguments.split_whitespace();
+    let id = arguments.next().and_then(|id| id.parse::<u32>().ok());
+    let priority = match arguments.next() {
+        Some("low") => Some(task::Priority::Low),
+        Some("normal") => Some(task::Priority::Normal),
+        Some("high") => Some(task::Priority::High),
+        _ => None,
+    };
+    let (Some(id), Some(priority)) = (id, priority) else {
+        log::error!("nice: usage: nice <task> low|normal|high");
+        return;
+    };
+    if task::set_priority(id.into(), priority).is_err() {
+        log::error!("nice: {id}: no such task");
+    }
+}
+
+fn slice(arguments: &str) {
+    let mut arguments = arguments.split_whitespace();
+    let id = arguments.next().and_then(|id| id.parse::<u32>().ok());
+    let ticks = arguments.next().and_then(|ticks| ticks.parse::<u32>().ok());
+    let (Some(id), Some(ticks)) = (id, ticks) else {
+        log::error!("slice: usage: slice <task> <ticks>");
+        return;
+    };
+    if task::set_time_slice(id.into(), ticks).is_err() {
+        log::error!("slice: {id}: no such task, or no ticks");
+    }
+}
+
+fn ps(_: &str) {
+    for info in process::processes() {
+        log::info!(
+            "{} {} parent {} task {} user {} {:?}",
+            info.id,
+            info.name,
+            info.parent,
+            info.task,
+            info.credentials.user.effective,
+            info.state
+        );
+    }
+}
+
+fn meminfo(_: &str) {
+    let mut report = String::new();
+    if meminfo::report(&mut report).is_err() {
+        log::error!("Could not generate the memory report");
+        return;
+    }
+    for line in report.lines() {
+        log::info!("{line}");
+    }
+}
+
+fn fault(_: &str) {
+    match user::spawn_faulting_program() {
+        Ok(id) => log::info!("Started process {id}"),
+        Err(error) => log::error!("Could not start the program: {error:?}"),
+    }
+}
+
+fn modules(_: &str) {
+    for module in module::modules().iter() {
+        log::info!("{}: {} bytes", module.command_line, module.data.len());
+    }
+}
+
+fn run_module(arguments: &str) {
+    let arguments: Vec<&str> = arguments.split_whitespace().collect();
+    let Some(&name) = arguments.first() else {
+        log::error!("run: missing module name");
+        return;
+    };
+    let Some(module) = module::find(name) else {
+        log::error!("run: {name}: no such module");
+        return;
+    };
+    let program = match loader::load(module.data, &arguments, &[], &Credentials::ROOT) {
+        Ok(program) => program,
+        Err(error) => {
+            log::error!("run: {name}: {error:?}");
+            return;
+        }
+    };
+    let started = process::spawn_user(
+        module.name,
+        program.address_space,
+        program.entry,
+        program.stack_pointer,
+    );
+    let id = match started {
+        Ok(id) => id,
+        Err(error) => {
+            log::error!("run: {name}: could not start the program: {error:?}");
+            return;
+        }
+    };
+    // The program reads the lines submitted until it exits
+    console::set_foreground(Some(id));
+    let result = process::wait(Child::Id(id), false);
+    console::set_foreground(None);
+    match result {
+        Ok(Some((_, status))) => log::info!("run: {name}: {status:?}"),
+        _ => log::error!("run: {name}: lost process {id}"),
+    }
+}
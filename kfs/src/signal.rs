@@ -0,0 +1,724 @@
+//! POSIX signals, with the numbers, frames and conventions of Linux on i386
+//!
+//! Signals are sent to processes and delivered when their task is about to
+//! return to ring 3. A user handler runs on the stack of the program, above
+//! a frame holding the interrupted context, and gets back to the kernel with
+//! `sigreturn` through its restorer or the signal trampoline page.
+//!
+//! Based of [signal(7)](https://man7.org/linux/man-pages/man7/signal.7.html)
+
+use core::{fmt, mem::size_of};
+
+use crate::{
+    gdt,
+    interrupts::Frame,
+    process::{self, ExitStatus, ProcessId},
+    syscall::user_memory,
+    task, user,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Signal(u8);
+
+#[allow(dead_code, reason = "every standard signal is named")]
+impl Signal {
+    pub const HUP: Self = Self(1);
+    pub const INT: Self = Self(2);
+    pub const QUIT: Self = Self(3);
+    pub const ILL: Self = Self(4);
+    pub const TRAP: Self = Self(5);
+    pub const ABRT: Self = Self(6);
+    pub const BUS: Self = Self(7);
+    pub const FPE: Self = Self(8);
+    pub const KILL: Self = Self(9);
+    pub const USR1: Self = Self(10);
+    pub const SEGV: Self = Self(11);
+    pub const USR2: Self = Self(12);
+    pub const PIPE: Self = Self(13);
+    pub const ALRM: Self = Self(14);
+    pub const TERM: Self = Self(15);
+    pub const STKFLT: Self = Self(16);
+    pub const CHLD: Self = Self(17);
+    pub const CONT: Self = Self(18);
+    pub const STOP: Self = Self(19);
+    pub const TSTP: Self = Self(20);
+    pub const TTIN: Self = Self(21);
+    pub const TTOU: Self = Self(22);
+    pub const URG: Self = Self(23);
+    pub const XCPU: Self = Self(24);
+    pub const XFSZ: Self = Self(25);
+    pub const VTALRM: Self = Self(26);
+    pub const PROF: Self = Self(27);
+    pub const WINCH: Self = Self(28);
+    pub const IO: Self = Self(29);
+    pub const PWR: Self = Self(30);
+    pub const SYS: Self = Self(31);
+}
+
+impl Signal {
+    /// Real-time signals included
+    pub const COUNT: usize = 64;
+
+    pub fn new(number: u32) -> Option<Self> {
+        (1..=Self::COUNT as u32)
+            .contains(&number)
+            .then_some(Self(number as u8))
+    }
+
+    pub fn number(self) -> u8 {
+        self.0
+    }
+
+    fn index(self) -> usize {
+        self.0 as usize - 1
+    }
+
+    /// Neither caught, ignored nor blocked
+    pub fn is_unstoppable(self) -> bool {
+        self == Self::KILL || self == Self::STOP
+    }
+
+    fn default_action(self) -> DefaultAction {
+        match self {
+            Self::CHLD | Self::URG | Self::WINCH => DefaultAction::Ignore,
+            Self::STOP | Self::TSTP | Self::TTIN | Self::TTOU => DefaultAction::Stop,
+            Self::CONT => DefaultAction::Continue,
+            _ => DefaultAction::Terminate,
+        }
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Set of signals, bit `n - 1` standing for the signal `n`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SignalSet(u64);
+
+impl SignalSet {
+    pub const EMPTY: Self = Self(0);
+    const STOPPING: Self = Self::of(&[Signal::STOP, Signal::TSTP, Signal::TTIN, Signal::TTOU]);
+    const UNSTOPPABLE: Self = Self::of(&[Signal::KILL, Signal::STOP]);
+
+    const fn of(signals: &[Signal]) -> Self {
+        let mut bits = 0;
+        let mut index = 0;
+        while index < signals.len() {
+            bits |= 1 << (signals[index].0 - 1);
+            index += 1;
+        }
+        Self(bits)
+    }
+
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(self, signal: Signal) -> bool {
+        self.0 & 1 << signal.index() != 0
+    }
+
+    pub fn insert(&mut self, signal: Signal) {
+        self.0 |= 1 << signal.index();
+    }
+
+    pub fn remove(&mut self, signal: Signal) {
+        self.0 &= !(1 << signal.index());
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    /// Lowest signal of the set
+    fn first(self) -> Option<Signal> {
+        match self.0 {
+            0 => None,
+            bits => Some(Signal(bits.trailing_zeros() as u8 + 1)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+/// `sa_flags`
+pub mod flags {
+    pub const SIGINFO: u32 = 0x0000_0004;
+    pub const RESTORER: u32 = 0x0400_0000;
+    pub const RESTART: u32 = 0x1000_0000;
+    pub const NODEFER: u32 = 0x4000_0000;
+    pub const RESETHAND: u32 = 0x8000_0000;
+}
+
+/// What a process does with a signal, `struct sigaction` of the kernel
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Action {
+    /// [Action::DEFAULT], [Action::IGNORE] or the address of a handler
+    pub handler: u32,
+    pub flags: u32,
+    /// Where the handler returns to, with [flags::RESTORER]
+    pub restorer: u32,
+    /// Blocked in addition to the signal while the handler runs
+    pub mask: SignalSet,
+}
+
+impl Action {
+    pub const DEFAULT: u32 = 0;
+    pub const IGNORE: u32 = 1;
+
+    fn is_ignored(&self, signal: Signal) -> bool {
+        match self.handler {
+            Self::DEFAULT => signal.default_action() == DefaultAction::Ignore,
+            Self::IGNORE => true,
+            _ => false,
+        }
+    }
+}
+
+/// `si_code` values
+mod code {
+    pub const USER: i32 = 0;
+    pub const KERNEL: i32 = 0x80;
+    pub const TKILL: i32 = -6;
+}
+
+/// `si_code` values of faults
+pub mod fault {
+    pub const ILLEGAL_OPCODE: i32 = 1;
+    pub const INTEGER_DIVIDE: i32 = 1;
+    pub const MAPPING_ERROR: i32 = 1;
+    pub const ACCESS_ERROR: i32 = 2;
+    pub const BREAKPOINT: i32 = 1;
+    pub const ALIGNMENT: i32 = 1;
+}
+
+/// Why a signal was sent, the part of `siginfo_t` that is kept
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Info {
+    code: i32,
+    /// Sender and its user for `kill`, faulting address for faults
+    fields: [u32; 2],
+}
+
+impl Info {
+    /// Sent by the process `sender`, through `tkill` if `thread` is set
+    pub fn sent_by(sender: ProcessId, thread: bool) -> Self {
+        Self {
+            code: match thread {
+                true => code::TKILL,
+                false => code::USER,
+            },
+            fields: [sender.as_u32(), 0],
+        }
+    }
+
+    pub fn kernel() -> Self {
+        Self {
+            code: code::KERNEL,
+            fields: [0; 2],
+        }
+    }
+
+    /// Fault of kind `code`, see [fault], at `address`
+    pub fn fault(code: i32, address: usize) -> Self {
+        Self {
+            code,
+            fields: [address as u32, 0],
+        }
+    }
+}
+
+/// Signal state of a process
+#[derive(Debug, Clone)]
+pub struct Signals {
+    pending: SignalSet,
+    /// Information on each pending signal, standard signals do not queue
+    information: [Info; Signal::COUNT],
+    blocked: SignalSet,
+    actions: [Action; Signal::COUNT],
+    /// Stopped by a signal, until it is continued
+    stopped: bool,
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Self {
+            pending: SignalSet::EMPTY,
+            information: [Info::default(); Signal::COUNT],
+            blocked: SignalSet::EMPTY,
+            actions: [Action::default(); Signal::COUNT],
+            stopped: false,
+        }
+    }
+}
+
+/// What [Signals::take] decided for the next signal
+enum Delivery {
+    Ignore,
+    Terminate(Signal),
+    Stop,
+    Handle {
+        signal: Signal,
+        info: Info,
+        action: Action,
+        /// To restore once the handler returns
+        blocked: SignalSet,
+    },
+}
+
+impl Signals {
+    /// State of a child, which inherits the actions and the blocked signals
+    pub fn forked(&self) -> Self {
+        Self {
+            actions: self.actions,
+            blocked: self.blocked,
+            ..Self::default()
+        }
+    }
+
+    /// Forget the handlers, which belonged to the replaced program
+    pub fn reset_handlers(&mut self) {
+        for action in &mut self.actions {
+            if action.handler != Action::IGNORE {
+                *action = Action::default();
+            }
+        }
+    }
+
+    pub fn blocked(&self) -> SignalSet {
+        self.blocked
+    }
+
+    /// [Signal::KILL] and [Signal::STOP] are never blocked
+    pub fn set_blocked(&mut self, blocked: SignalSet) {
+        self.blocked = blocked.difference(SignalSet::UNSTOPPABLE);
+    }
+
+    pub fn pending(&self) -> SignalSet {
+        self.pending
+    }
+
+    pub fn action(&self, signal: Signal) -> Action {
+        self.actions[signal.index()]
+    }
+
+    /// Pending signals are dropped once ignored
+    pub fn set_action(&mut self, signal: Signal, action: Action) {
+        self.actions[signal.index()] = action;
+        if action.is_ignored(signal) {
+            self.pending.remove(signal);
+        }
+    }
+
+    /// Whether a signal is waiting to be delivered, blocking calls then
+    /// return early
+    pub fn is_interrupted(&self) -> bool {
+        self.pending.difference(self.blocked) != SignalSet::EMPTY
+    }
+
+    /// Make `signal` pending, return whether the task of the process should
+    /// be woken to notice it
+    pub fn send(&mut self, signal: Signal, info: Info) -> bool {
+        if signal == Signal::CONT || signal == Signal::KILL {
+            self.stopped = false;
+        }
+        if signal == Signal::CONT {
+            self.pending = self.pending.difference(SignalSet::STOPPING);
+        } else if SignalSet::STOPPING.contains(signal) {
+            self.pending.remove(Signal::CONT);
+        }
+        let action = self.actions[signal.index()];
+        if action.is_ignored(signal) && !self.blocked.contains(signal) {
+            return signal == Signal::CONT;
+        }
+        self.pending.insert(signal);
+        self.information[signal.index()] = info;
+        true
+    }
+
+    /// Send `signal` even if it is blocked or ignored, as for a fault that
+    /// can not be resumed otherwise
+    pub fn force(&mut self, signal: Signal, info: Info) {
+        self.blocked.remove(signal);
+        let action = &mut self.actions[signal.index()];
+        if action.handler == Action::IGNORE {
+            *action = Action::default();
+        }
+        self.send(signal, info);
+    }
+
+    /// Dequeue the next deliverable signal and decide what to do with it
+    fn take(&mut self) -> Option<Delivery> {
+        let signal = self.pending.difference(self.blocked).first()?;
+        self.pending.remove(signal);
+        let info = self.information[signal.index()];
+        let action = self.actions[signal.index()];
+        Some(match action.handler {
+            Action::IGNORE => Delivery::Ignore,
+            Action::DEFAULT => match signal.default_action() {
+                DefaultAction::Terminate => Delivery::Terminate(signal),
+                DefaultAction::Ignore | DefaultAction::Continue => Delivery::Ignore,
+                DefaultAction::Stop => {
+                    self.stopped = true;
+                    Delivery::Stop
+                }
+            },
+            _ => {
+                let blocked = self.blocked;
+                let mut mask = self.blocked.union(action.mask);
+                if action.flags & flags::NODEFER == 0 {
+                    mask.insert(signal);
+                }
+                self.set_blocked(mask);
+                if action.flags & flags::RESETHAND != 0 {
+                    self.actions[signal.index()] = Action::default();
+                }
+                Delivery::Handle {
+                    signal,
+                    info,
+                    action,
+                    blocked,
+                }
+            }
+        })
+    }
+}
+
+/// Whether the current process has a signal to handle, blocking calls
+/// return [crate::syscall::Errno::Interrupted] then
+///
+/// Never blocks, wait queue conditions may call it.
+pub fn is_interrupted() -> bool {
+    task::is_interrupted()
+}
+
+/// Send `signal` to the current process even if it blocks or ignores it,
+/// for faults of its program
+pub fn force(signal: Signal, info: Info) {
+    if process::with_current_signals(|signals| signals.force(signal, info)).is_none() {
+        process::exit(ExitStatus::Killed(signal.number()));
+    }
+}
+
+/// Act on the pending signals of the current process before it returns to
+/// ring 3 with the context `frame`
+///
+/// `interrupted_call` is the number of the system call that was interrupted
+/// by a signal, it is restarted unless a handler without
+/// [flags::RESTART] runs.
+pub fn deliver(frame: &mut Frame, interrupted_call: Option<u32>) {
+    // The program ran with interrupts enabled, and the process table lock
+    // may belong to a preempted task
+    asm::enable_interrupts();
+    while let Some(Some(delivery)) = process::with_current_signals(Signals::take) {
+        match delivery {
+            Delivery::Ignore => (),
+            Delivery::Terminate(signal) => process::exit(ExitStatus::Killed(signal.number())),
+            Delivery::Stop => wait_until_continued(),
+            Delivery::Handle {
+                signal,
+                info,
+                action,
+                blocked,
+            } => {
+                if let Some(number) = interrupted_call {
+                    if action.flags & flags::RESTART != 0 {
+                        restart(frame, number);
+                    }
+                }
+                if setup_frame(frame, signal, info, &action, blocked).is_err() {
+                    process::exit(ExitStatus::Killed(Signal::SEGV.number()));
+                }
+                return;
+            }
+        }
+    }
+    if let Some(number) = interrupted_call {
+        restart(frame, number);
+    }
+}
+
+fn wait_until_continued() {
+    while process::with_current_signals(|signals| signals.stopped).unwrap_or(false) {
+        task::block();
+    }
+}
+
+/// Make the program run the system call `number` again, `int 0x80` being
+/// two bytes long
+fn restart(frame: &mut Frame, number: u32) {
+    frame.eax = number;
+    frame.eip -= 2;
+}
+
+/// `struct sigcontext`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct Context {
+    gs: u32,
+    fs: u32,
+    es: u32,
+    ds: u32,
+    edi: u32,
+    esi: u32,
+    ebp: u32,
+    esp: u32,
+    ebx: u32,
+    edx: u32,
+    ecx: u32,
+    eax: u32,
+    trap_number: u32,
+    error_code: u32,
+    eip: u32,
+    cs: u32,
+    eflags: u32,
+    esp_at_signal: u32,
+    ss: u32,
+    /// Floating point state is not saved
+    fpstate: u32,
+    /// Low half of the blocked signals to restore
+    old_mask: u32,
+    cr2: u32,
+}
+
+impl Context {
+    fn of(frame: &Frame, blocked: SignalSet) -> Self {
+        Self {
+            gs: frame.gs,
+            fs: frame.fs,
+            es: frame.es,
+            ds: frame.ds,
+            edi: frame.edi,
+            esi: frame.esi,
+            ebp: frame.ebp,
+            esp: frame.user_esp,
+            ebx: frame.ebx,
+            edx: frame.edx,
+            ecx: frame.ecx,
+            eax: frame.eax,
+            trap_number: frame.vector,
+            error_code: frame.error_code,
+            eip: frame.eip,
+            cs: frame.cs,
+            eflags: frame.eflags,
+            esp_at_signal: frame.user_esp,
+            ss: frame.user_ss,
+            fpstate: 0,
+            old_mask: blocked.bits() as u32,
+            cr2: 0,
+        }
+    }
+}
+
+/// Frame of handlers without [flags::SIGINFO], `struct sigframe`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SignalFrame {
+    return_address: u32,
+    signal: u32,
+    context: Context,
+    /// High half of the blocked signals to restore
+    extra_mask: u32,
+}
+
+/// `siginfo_t`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SignalInformation {
+    signal: i32,
+    errno: i32,
+    code: i32,
+    fields: [u32; 29],
+}
+
+/// `ucontext_t`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UserContext {
+    flags: u32,
+    link: u32,
+    /// Alternate stacks are not supported
+    stack: [u32; 3],
+    context: Context,
+    /// Blocked signals to restore, low half first
+    mask: [u32; 2],
+}
+
+/// Frame of handlers with [flags::SIGINFO], `struct rt_sigframe`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RealTimeSignalFrame {
+    return_address: u32,
+    signal: u32,
+    information_address: u32,
+    context_address: u32,
+    information: SignalInformation,
+    user_context: UserContext,
+}
+
+/// Where a frame of `size` bytes goes under the stack pointer `esp`, the
+/// stack being aligned on 16 bytes at the start of the handler
+fn frame_address(esp: u32, size: usize) -> usize {
+    ((esp as usize - size + 4) & !0xF) - 4
+}
+
+/// Make the program run the handler of `signal` then return to `frame`
+fn setup_frame(
+    frame: &mut Frame,
+    signal: Signal,
+    info: Info,
+    action: &Action,
+    blocked: SignalSet,
+) -> Result<(), ()> {
+    let context = Context::of(frame, blocked);
+    let real_time = action.flags & flags::SIGINFO != 0;
+    let restorer = match (action.flags & flags::RESTORER != 0, real_time) {
+        (true, _) => action.restorer,
+        (false, false) => user::SIGNAL_RETURN as u32,
+        (false, true) => user::REAL_TIME_SIGNAL_RETURN as u32,
+    };
+
+    let address = match real_time {
+        false => {
+            let address = frame_address(frame.user_esp, size_of::<SignalFrame>());
+            let signal_frame = SignalFrame {
+                return_address: restorer,
+                signal: signal.number() as u32,
+                context,
+                extra_mask: (blocked.bits() >> 32) as u32,
+            };
+            user_memory::write(address, signal_frame).map_err(|_| ())?;
+            address
+        }
+        true => {
+            let address = frame_address(frame.user_esp, size_of::<RealTimeSignalFrame>());
+            let mut fields = [0; 29];
+            fields[..2].copy_from_slice(&info.fields);
+            let signal_frame = RealTimeSignalFrame {
+                return_address: restorer,
+                signal: signal.number() as u32,
+                information_address: (address + 16) as u32,
+                context_address: (address + 16 + size_of::<SignalInformation>()) as u32,
+                information: SignalInformation {
+                    signal: signal.number() as i32,
+                    errno: 0,
+                    code: info.code,
+                    fields,
+                },
+                user_context: UserContext {
+                    flags: 0,
+                    link: 0,
+                    stack: [0; 3],
+                    context,
+                    mask: [blocked.bits() as u32, (blocked.bits() >> 32) as u32],
+                },
+            };
+            user_memory::write(address, signal_frame).map_err(|_| ())?;
+            frame.edx = signal_frame.information_address;
+            frame.ecx = signal_frame.context_address;
+            address
+        }
+    };
+
+    let data = gdt::USER_DATA as u32;
+    frame.eip = action.handler;
+    frame.user_esp = address as u32;
+    frame.eax = signal.number() as u32;
+    frame.eflags &= !(eflags::DIRECTION | eflags::TRAP);
+    frame.ds = data;
+    frame.es = data;
+    Ok(())
+}
+
+mod eflags {
+    pub const CARRY: u32 = 1 << 0;
+    pub const PARITY: u32 = 1 << 2;
+    pub const AUXILIARY: u32 = 1 << 4;
+    pub const ZERO: u32 = 1 << 6;
+    pub const SIGN: u32 = 1 << 7;
+    pub const TRAP: u32 = 1 << 8;
+    pub const DIRECTION: u32 = 1 << 10;
+    pub const OVERFLOW: u32 = 1 << 11;
+    pub const RESUME: u32 = 1 << 16;
+    pub const ALIGNMENT_CHECK: u32 = 1 << 18;
+
+    /// Flags a program may change with `sigreturn`
+    pub const USER: u32 = CARRY
+        | PARITY
+        | AUXILIARY
+        | ZERO
+        | SIGN
+        | TRAP
+        | DIRECTION
+        | OVERFLOW
+        | RESUME
+        | ALIGNMENT_CHECK;
+}
+
+/// Data segment selector a program may restore, others could not be
+/// loaded by the kernel on its way back to ring 3
+fn data_selector(selector: u32) -> u32 {
+    match selector as u16 {
+        0 | gdt::USER_DATA | gdt::THREAD_LOCAL => selector,
+        _ => gdt::USER_DATA as u32,
+    }
+}
+
+/// Resume the context saved in `context`, blocking `blocked`
+fn restore(frame: &mut Frame, context: &Context, blocked: SignalSet) {
+    frame.gs = data_selector(context.gs);
+    frame.fs = data_selector(context.fs);
+    frame.es = data_selector(context.es);
+    frame.ds = data_selector(context.ds);
+    frame.edi = context.edi;
+    frame.esi = context.esi;
+    frame.ebp = context.ebp;
+    frame.ebx = context.ebx;
+    frame.edx = context.edx;
+    frame.ecx = context.ecx;
+    frame.eax = context.eax;
+    frame.eip = context.eip;
+    frame.user_esp = context.esp;
+    frame.eflags = (frame.eflags & !eflags::USER) | (context.eflags & eflags::USER);
+    process::with_current_signals(|signals| signals.set_blocked(blocked));
+}
+
+/// Return from a handler without [flags::SIGINFO], after its return
+/// address and the signal number were popped
+pub fn sigreturn(frame: &mut Frame) -> Result<u32, ()> {
+    let address = frame.user_esp as usize - 2 * size_of::<u32>();
+    let signal_frame: SignalFrame = user_memory::read(address).map_err(|_| ())?;
+    let context = signal_frame.context;
+    let blocked = (signal_frame.extra_mask as u64) << 32 | context.old_mask as u64;
+    restore(frame, &context, SignalSet::from_bits(blocked));
+    Ok(frame.eax)
+}
+
+/// Return from a handler with [flags::SIGINFO], after its return address
+/// was popped
+pub fn rt_sigreturn(frame: &mut Frame) -> Result<u32, ()> {
+    let address = frame.user_esp as usize - size_of::<u32>();
+    let signal_frame: RealTimeSignalFrame = user_memory::read(address).map_err(|_| ())?;
+    let user_context = signal_frame.user_context;
+    let [low, high] = user_context.mask;
+    let blocked = (high as u64) << 32 | low as u64;
+    restore(frame, &user_context.context, SignalSet::from_bits(blocked));
+    Ok(frame.eax)
+}
@@ -0,0 +1,430 @@
+//! Slab allocator
+//!
+//! A [Cache] hands out objects of a single type from pages split in equal
+//! slots. Slabs are kept in three lists, full, partial and empty, so that an
+//! allocation usually pops a slot from a partial slab. The list of free slots
+//! is stored beside the objects.
+//!
+//! Caches can also hold values owned through a [SlabBox], like a [Box]
+//! allocated from the cache.
+//!
+//! [Box]: alloc::boxed::Box
+
+use core::{
+    marker::PhantomData,
+    mem::{align_of, size_of},
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+};
+
+use collections::ArrayVec;
+use sync::SpinLock;
+
+use crate::{
+    frame,
+    vmm::{self, PAGE_SIZE},
+};
+
+type SlotIndex = u16;
+
+const NO_SLOT: SlotIndex = SlotIndex::MAX;
+
+pub const MAX_REGISTERED_CACHES: usize = 32;
+
+/// Caches reported by [statistics]
+static REGISTERED: SpinLock<ArrayVec<MAX_REGISTERED_CACHES, &'static dyn Inspect>> =
+    SpinLock::new(ArrayVec::new());
+
+/// Type erased access to the statistics of a [Cache]
+pub trait Inspect: Sync {
+    fn statistics(&self) -> Statistics;
+}
+
+/// Stored at the start of every slab page, followed by the free slot list and
+/// the objects
+#[repr(C)]
+struct Slab {
+    previous: *mut Slab,
+    next: *mut Slab,
+    in_use: usize,
+    first_free: SlotIndex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Empty,
+    Partial,
+    Full,
+}
+
+/// Where things are placed in a slab page
+#[derive(Debug, Clone, Copy)]
+struct SlabLayout {
+    object_size: usize,
+    objects_offset: usize,
+    capacity: usize,
+}
+
+impl SlabLayout {
+    const fn of<T>() -> Self {
+        assert!(
+            align_of::<T>() <= PAGE_SIZE,
+            "Slab objects cannot be page aligned"
+        );
+        let object_size = match size_of::<T>() {
+            0 => 1,
+            size => size,
+        };
+        let header_size = size_of::<Slab>();
+        let slot_size = size_of::<SlotIndex>();
+
+        let mut capacity = (PAGE_SIZE - header_size) / (object_size + slot_size);
+        loop {
+            assert!(0 < capacity, "Slab objects must fit in a page");
+            let objects_offset =
+                (header_size + slot_size * capacity).next_multiple_of(align_of::<T>());
+            if objects_offset + capacity * object_size <= PAGE_SIZE {
+                return Self {
+                    object_size,
+                    objects_offset,
+                    capacity,
+                };
+            }
+            capacity -= 1;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct List {
+    head: *mut Slab,
+    len: usize,
+}
+
+impl List {
+    const EMPTY: Self = Self {
+        head: ptr::null_mut(),
+        len: 0,
+    };
+
+    /// # Safety
+    /// `slab` must be a valid slab outside of any list
+    unsafe fn push(&mut self, slab: *mut Slab) {
+        unsafe {
+            (*slab).previous = ptr::null_mut();
+            (*slab).next = self.head;
+            if !self.head.is_null() {
+                (*self.head).previous = slab;
+            }
+        }
+        self.head = slab;
+        self.len += 1;
+    }
+
+    /// # Safety
+    /// `slab` must be a valid slab of this list
+    unsafe fn remove(&mut self, slab: *mut Slab) {
+        unsafe {
+            match (*slab).previous.is_null() {
+                true => self.head = (*slab).next,
+                false => (*(*slab).previous).next = (*slab).next,
+            }
+            if !(*slab).next.is_null() {
+                (*(*slab).next).previous = (*slab).previous;
+            }
+        }
+        self.len -= 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Statistics {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub slabs: usize,
+    pub empty_slabs: usize,
+    pub active_objects: usize,
+    pub allocations: usize,
+    pub frees: usize,
+}
+
+impl Statistics {
+    pub fn total_objects(&self) -> usize {
+        self.slabs * self.objects_per_slab
+    }
+
+    /// Bytes of the pages held by the cache
+    pub fn size(&self) -> usize {
+        self.slabs * PAGE_SIZE
+    }
+}
+
+struct Slabs {
+    empty: List,
+    partial: List,
+    full: List,
+    active_objects: usize,
+    allocations: usize,
+    frees: usize,
+}
+
+// SAFETY: slabs are only reachable through the cache lock
+unsafe impl Send for Slabs {}
+
+impl Slabs {
+    fn list(&mut self, state: State) -> &mut List {
+        match state {
+            State::Empty => &mut self.empty,
+            State::Partial => &mut self.partial,
+            State::Full => &mut self.full,
+        }
+    }
+}
+
+pub struct Cache<T> {
+    name: &'static str,
+    slabs: SpinLock<Slabs>,
+    _objects: PhantomData<fn() -> T>,
+}
+
+impl<T> Cache<T> {
+    const LAYOUT: SlabLayout = SlabLayout::of::<T>();
+    /// Empty slabs kept around when objects are freed
+    const MAX_EMPTY_SLABS: usize = 1;
+
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            slabs: SpinLock::new(Slabs {
+                empty: List::EMPTY,
+                partial: List::EMPTY,
+                full: List::EMPTY,
+                active_objects: 0,
+                allocations: 0,
+                frees: 0,
+            }),
+            _objects: PhantomData,
+        }
+    }
+
+    /// List the cache in [statistics]
+    pub fn register(&'static self) -> Result<(), ()> {
+        REGISTERED.lock().push(self).map_err(|_| ())
+    }
+
+    fn state(slab: *mut Slab) -> State {
+        match unsafe { (*slab).in_use } {
+            0 => State::Empty,
+            in_use if in_use == Self::LAYOUT.capacity => State::Full,
+            _ => State::Partial,
+        }
+    }
+
+    /// # Safety
+    /// `slab` must be a valid slab of this cache
+    unsafe fn free_slots<'a>(slab: *mut Slab) -> &'a mut [SlotIndex] {
+        unsafe {
+            let start = slab.add(1) as *mut SlotIndex;
+            core::slice::from_raw_parts_mut(start, Self::LAYOUT.capacity)
+        }
+    }
+
+    fn object(slab: *mut Slab, index: usize) -> *mut T {
+        let layout = Self::LAYOUT;
+        (slab as usize + layout.objects_offset + index * layout.object_size) as *mut T
+    }
+
+    fn create_slab() -> Option<*mut Slab> {
+        let frame = frame::allocate()?;
+        let slab = vmm::physical_to_virtual(frame) as *mut Slab;
+        unsafe {
+            slab.write(Slab {
+                previous: ptr::null_mut(),
+                next: ptr::null_mut(),
+                in_use: 0,
+                first_free: 0,
+            });
+            for (index, next) in Self::free_slots(slab).iter_mut().enumerate() {
+                *next = match index + 1 < Self::LAYOUT.capacity {
+                    true => (index + 1) as SlotIndex,
+                    false => NO_SLOT,
+                };
+            }
+        }
+        Some(slab)
+    }
+
+    fn release_slab(slab: *mut Slab) {
+        frame::free(vmm::window_to_physical(slab as usize));
+    }
+
+    pub fn alloc(&self) -> Option<NonNull<T>> {
+        let mut slabs = self.slabs.lock();
+        let slab = match (slabs.partial.head, slabs.empty.head) {
+            (partial, _) if !partial.is_null() => partial,
+            (_, empty) if !empty.is_null() => empty,
+            _ => {
+                let slab = Self::create_slab()?;
+                unsafe { slabs.empty.push(slab) };
+                slab
+            }
+        };
+
+        let before = Self::state(slab);
+        let index = unsafe {
+            let index = (*slab).first_free;
+            (*slab).first_free = Self::free_slots(slab)[index as usize];
+            (*slab).in_use += 1;
+            index
+        };
+        let after = Self::state(slab);
+        if before != after {
+            unsafe {
+                slabs.list(before).remove(slab);
+                slabs.list(after).push(slab);
+            }
+        }
+
+        slabs.active_objects += 1;
+        slabs.allocations += 1;
+        NonNull::new(Self::object(slab, index as usize))
+    }
+
+    /// Move `value` into an object of the cache, it is dropped on failure
+    pub fn boxed(&'static self, value: T) -> Option<SlabBox<T>> {
+        let object = self.alloc()?;
+        unsafe { object.as_ptr().write(value) };
+        Some(SlabBox {
+            object,
+            cache: self,
+        })
+    }
+
+    /// # Safety
+    /// `object` must come from [Cache::alloc] on this cache and not be freed yet
+    pub unsafe fn free(&self, object: NonNull<T>) {
+        let layout = Self::LAYOUT;
+        let address = object.as_ptr() as usize;
+        let slab = vmm::page_align_down(address) as *mut Slab;
+        let offset = address - slab as usize - layout.objects_offset;
+        assert!(
+            offset.is_multiple_of(layout.object_size)
+                && offset / layout.object_size < layout.capacity,
+            "Freeing {address:#x} which is not an object of cache {}",
+            self.name
+        );
+        let index = offset / layout.object_size;
+
+        let mut slabs = self.slabs.lock();
+        let before = Self::state(slab);
+        unsafe {
+            Self::free_slots(slab)[index] = (*slab).first_free;
+            (*slab).first_free = index as SlotIndex;
+            (*slab).in_use -= 1;
+        }
+        let after = Self::state(slab);
+        if before != after {
+            unsafe {
+                slabs.list(before).remove(slab);
+                match after == State::Empty && Self::MAX_EMPTY_SLABS <= slabs.empty.len {
+                    true => Self::release_slab(slab),
+                    false => slabs.list(after).push(slab),
+                }
+            }
+        }
+
+        slabs.active_objects -= 1;
+        slabs.frees += 1;
+    }
+
+    /// Give the pages of every empty slab back to the frame allocator and
+    /// return how many were released
+    pub fn reclaim(&self) -> usize {
+        let mut slabs = self.slabs.lock();
+        let released = slabs.empty.len;
+        while !slabs.empty.head.is_null() {
+            let slab = slabs.empty.head;
+            unsafe { slabs.empty.remove(slab) };
+            Self::release_slab(slab);
+        }
+        released
+    }
+
+    pub fn statistics(&self) -> Statistics {
+        let slabs = self.slabs.lock();
+        Statistics {
+            name: self.name,
+            object_size: Self::LAYOUT.object_size,
+            objects_per_slab: Self::LAYOUT.capacity,
+            slabs: slabs.empty.len + slabs.partial.len + slabs.full.len,
+            empty_slabs: slabs.empty.len,
+            active_objects: slabs.active_objects,
+            allocations: slabs.allocations,
+            frees: slabs.frees,
+        }
+    }
+}
+
+impl<T> Inspect for Cache<T> {
+    fn statistics(&self) -> Statistics {
+        Cache::statistics(self)
+    }
+}
+
+impl<T> Drop for Cache<T> {
+    fn drop(&mut self) {
+        self.reclaim();
+        let active_objects = self.slabs.lock().active_objects;
+        if active_objects != 0 {
+            log::warn!(
+                "Cache {} dropped with {} live objects",
+                self.name,
+                active_objects
+            );
+        }
+    }
+}
+
+/// Value in an object of a [Cache], dropped and given back to it with the
+/// box
+pub struct SlabBox<T: 'static> {
+    object: NonNull<T>,
+    cache: &'static Cache<T>,
+}
+
+// SAFETY: the box owns its value
+unsafe impl<T: Send> Send for SlabBox<T> {}
+unsafe impl<T: Sync> Sync for SlabBox<T> {}
+
+impl<T> Deref for SlabBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.object.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.object.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(self.object.as_ptr());
+            self.cache.free(self.object);
+        }
+    }
+}
+
+/// Statistics of every registered cache
+pub fn statistics() -> ArrayVec<MAX_REGISTERED_CACHES, Statistics> {
+    let mut statistics = ArrayVec::new();
+    for cache in REGISTERED.lock().iter() {
+        let _ = statistics.push(cache.statistics());
+    }
+    statistics
+}
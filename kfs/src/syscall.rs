@@ -0,0 +1,393 @@
+//! System calls, with the numbers and conventions of Linux on i386 so that
+//! programs built for it run unmodified
+//!
+//! Programs make them with `int 0x80`, the number in eax and the arguments
+//! in ebx, ecx, edx, esi, edi then ebp. The result is returned in eax, an
+//! error as the negated errno value.
+//!
+//! Based of [the Linux syscall table](https://github.com/torvalds/linux/blob/master/arch/x86/entry/syscalls/syscall_32.tbl)
+
+mod credentials;
+mod futex;
+mod io;
+mod memory;
+mod process;
+mod signal;
+mod socket;
+mod time;
+pub mod user_memory;
+
+use crate::{file, interrupts::Frame, loader, vmm};
+
+/// Gate programs are allowed to interrupt through
+pub const VECTOR: usize = 0x80;
+
+mod number {
+    pub const EXIT: u32 = 1;
+    pub const FORK: u32 = 2;
+    pub const READ: u32 = 3;
+    pub const WRITE: u32 = 4;
+    pub const OPEN: u32 = 5;
+    pub const CLOSE: u32 = 6;
+    pub const WAITPID: u32 = 7;
+    pub const EXECVE: u32 = 11;
+    pub const GETPID: u32 = 20;
+    pub const SETUID: u32 = 23;
+    pub const GETUID: u32 = 24;
+    pub const ALARM: u32 = 27;
+    pub const PAUSE: u32 = 29;
+    pub const KILL: u32 = 37;
+    pub const DUP: u32 = 41;
+    pub const PIPE: u32 = 42;
+    pub const BRK: u32 = 45;
+    pub const SETGID: u32 = 46;
+    pub const GETGID: u32 = 47;
+    pub const GETEUID: u32 = 49;
+    pub const GETEGID: u32 = 50;
+    pub const IOCTL: u32 = 54;
+    pub const FCNTL: u32 = 55;
+    pub const DUP2: u32 = 63;
+    pub const GETPPID: u32 = 64;
+    pub const MUNMAP: u32 = 91;
+    pub const WAIT4: u32 = 114;
+    pub const SOCKETCALL: u32 = 102;
+    pub const SETITIMER: u32 = 104;
+    pub const SIGRETURN: u32 = 119;
+    pub const UNAME: u32 = 122;
+    pub const MPROTECT: u32 = 125;
+    pub const WRITEV: u32 = 146;
+    pub const NANOSLEEP: u32 = 162;
+    pub const RT_SIGRETURN: u32 = 173;
+    pub const RT_SIGACTION: u32 = 174;
+    pub const RT_SIGPROCMASK: u32 = 175;
+    pub const RT_SIGPENDING: u32 = 176;
+    pub const VFORK: u32 = 190;
+    pub const MMAP2: u32 = 192;
+    pub const GETUID32: u32 = 199;
+    pub const GETGID32: u32 = 200;
+    pub const GETEUID32: u32 = 201;
+    pub const GETEGID32: u32 = 202;
+    pub const GETRESUID32: u32 = 209;
+    pub const GETRESGID32: u32 = 211;
+    pub const SETUID32: u32 = 213;
+    pub const SETGID32: u32 = 214;
+    pub const FCNTL64: u32 = 221;
+    pub const GETTID: u32 = 224;
+    pub const TKILL: u32 = 238;
+    pub const FUTEX: u32 = 240;
+    pub const SET_THREAD_AREA: u32 = 243;
+    pub const EXIT_GROUP: u32 = 252;
+    pub const SET_TID_ADDRESS: u32 = 258;
+    pub const CLOCK_NANOSLEEP: u32 = 267;
+    pub const TGKILL: u32 = 270;
+    pub const DUP3: u32 = 330;
+    pub const PIPE2: u32 = 331;
+    pub const SOCKET: u32 = 359;
+    pub const SOCKETPAIR: u32 = 360;
+    pub const BIND: u32 = 361;
+    pub const CONNECT: u32 = 362;
+    pub const LISTEN: u32 = 363;
+    pub const ACCEPT4: u32 = 364;
+    pub const GETSOCKOPT: u32 = 365;
+    pub const SETSOCKOPT: u32 = 366;
+    pub const GETSOCKNAME: u32 = 367;
+    pub const GETPEERNAME: u32 = 368;
+    pub const SENDTO: u32 = 369;
+    pub const SENDMSG: u32 = 370;
+    pub const RECVFROM: u32 = 371;
+    pub const RECVMSG: u32 = 372;
+    pub const SHUTDOWN: u32 = 373;
+    pub const CLOCK_NANOSLEEP_TIME64: u32 = 407;
+    pub const FUTEX_TIME64: u32 = 422;
+}
+
+/// Error numbers of Linux
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    /// `EPERM`
+    NotPermitted = 1,
+    /// `ENOENT`
+    NoEntry = 2,
+    /// `ESRCH`
+    NoProcess = 3,
+    /// `EINTR`
+    Interrupted = 4,
+    /// `E2BIG`
+    TooBig = 7,
+    /// `ENOEXEC`
+    NotExecutable = 8,
+    /// `EBADF`
+    BadFileDescriptor = 9,
+    /// `ECHILD`
+    NoChild = 10,
+    /// `EAGAIN`
+    Again = 11,
+    /// `ENOMEM`
+    OutOfMemory = 12,
+    /// `EACCES`
+    AccessDenied = 13,
+    /// `EFAULT`
+    Fault = 14,
+    /// `ENODEV`
+    NoDevice = 19,
+    /// `EINVAL`
+    Invalid = 22,
+    /// `EMFILE`
+    TooManyFiles = 24,
+    /// `ENOTTY`
+    NotTerminal = 25,
+    /// `EROFS`
+    ReadOnlyFileSystem = 30,
+    /// `EPIPE`
+    BrokenPipe = 32,
+    /// `ENAMETOOLONG`
+    NameTooLong = 36,
+    /// `ENOSYS`
+    NotImplemented = 38,
+    /// `ENOTSOCK`
+    NotSocket = 88,
+    /// `EDESTADDRREQ`
+    DestinationRequired = 89,
+    /// `EMSGSIZE`
+    MessageTooLong = 90,
+    /// `EPROTOTYPE`
+    WrongProtocolType = 91,
+    /// `ENOPROTOOPT`
+    NoProtocolOption = 92,
+    /// `EPROTONOSUPPORT`
+    ProtocolNotSupported = 93,
+    /// `ESOCKTNOSUPPORT`
+    SocketTypeNotSupported = 94,
+    /// `EOPNOTSUPP`
+    NotSupported = 95,
+    /// `EAFNOSUPPORT`
+    FamilyNotSupported = 97,
+    /// `EADDRINUSE`
+    AddressInUse = 98,
+    /// `EISCONN`
+    AlreadyConnected = 106,
+    /// `ENOTCONN`
+    NotConnected = 107,
+    /// `ETIMEDOUT`
+    TimedOut = 110,
+    /// `ECONNREFUSED`
+    ConnectionRefused = 111,
+}
+
+impl From<vmm::Error> for Errno {
+    fn from(error: vmm::Error) -> Self {
+        match error {
+            vmm::Error::OutOfMemory | vmm::Error::TooManyRegions => Self::OutOfMemory,
+            _ => Self::Invalid,
+        }
+    }
+}
+
+impl From<crate::process::Error> for Errno {
+    fn from(error: crate::process::Error) -> Self {
+        use crate::process::Error;
+        match error {
+            Error::OutOfMemory => Self::OutOfMemory,
+            Error::TooManyTasks | Error::TooManyTimers => Self::Again,
+            Error::NotAProgram => Self::Invalid,
+        }
+    }
+}
+
+impl From<crate::process::WaitError> for Errno {
+    fn from(error: crate::process::WaitError) -> Self {
+        use crate::process::WaitError;
+        match error {
+            WaitError::NoChild => Self::NoChild,
+            WaitError::Interrupted => Self::Interrupted,
+        }
+    }
+}
+
+impl From<crate::process::SignalError> for Errno {
+    fn from(error: crate::process::SignalError) -> Self {
+        use crate::process::SignalError;
+        match error {
+            SignalError::NoProcess => Self::NoProcess,
+            SignalError::NotPermitted => Self::NotPermitted,
+        }
+    }
+}
+
+impl From<crate::credentials::Error> for Errno {
+    fn from(error: crate::credentials::Error) -> Self {
+        use crate::credentials::Error;
+        match error {
+            Error::Invalid => Self::Invalid,
+            Error::NotPermitted => Self::NotPermitted,
+        }
+    }
+}
+
+impl From<file::Error> for Errno {
+    fn from(error: file::Error) -> Self {
+        match error {
+            file::Error::BadDescriptor => Self::BadFileDescriptor,
+            file::Error::TooManyFiles => Self::TooManyFiles,
+            file::Error::NotFound => Self::NoEntry,
+            file::Error::ReadOnly => Self::ReadOnlyFileSystem,
+            file::Error::Interrupted => Self::Interrupted,
+            file::Error::PermissionDenied => Self::AccessDenied,
+            file::Error::BrokenPipe => Self::BrokenPipe,
+            file::Error::NotMappable => Self::NoDevice,
+            file::Error::Socket(error) => error.into(),
+        }
+    }
+}
+
+impl From<file::socket::Error> for Errno {
+    fn from(error: file::socket::Error) -> Self {
+        use file::socket::Error;
+        match error {
+            Error::Invalid => Self::Invalid,
+            Error::NotSupported => Self::NotSupported,
+            Error::AddressInUse => Self::AddressInUse,
+            Error::NotFound => Self::NoEntry,
+            Error::ConnectionRefused => Self::ConnectionRefused,
+            Error::WrongKind => Self::WrongProtocolType,
+            Error::AlreadyConnected => Self::AlreadyConnected,
+            Error::NotConnected => Self::NotConnected,
+            Error::NoDestination => Self::DestinationRequired,
+            Error::TooLong => Self::MessageTooLong,
+            Error::BrokenPipe => Self::BrokenPipe,
+            Error::WouldBlock => Self::Again,
+            Error::Interrupted => Self::Interrupted,
+        }
+    }
+}
+
+impl From<crate::futex::Error> for Errno {
+    fn from(error: crate::futex::Error) -> Self {
+        use crate::futex::Error;
+        match error {
+            Error::WouldBlock => Self::Again,
+            Error::TimedOut => Self::TimedOut,
+            Error::Interrupted => Self::Interrupted,
+        }
+    }
+}
+
+impl From<loader::Error> for Errno {
+    fn from(error: loader::Error) -> Self {
+        match error {
+            loader::Error::Elf(_) | loader::Error::BadSegment => Self::NotExecutable,
+            loader::Error::Memory(error) => error.into(),
+            loader::Error::ArgumentsTooLong => Self::TooBig,
+        }
+    }
+}
+
+type Result = core::result::Result<usize, Errno>;
+
+/// Run the system call the program described by `frame` asked for
+///
+/// Return the number of the call if a signal interrupted it, to restart it
+/// unless a handler forbids it.
+pub fn dispatch(frame: &mut Frame) -> Option<u32> {
+    // Calls may wait, such as a read for the next line
+    asm::enable_interrupts();
+    let [a, b, c, d, e, f] = [
+        frame.ebx, frame.ecx, frame.edx, frame.esi, frame.edi, frame.ebp,
+    ]
+    .map(|arg| arg as usize);
+    let number = frame.eax;
+    let result = match number {
+        number::EXIT | number::EXIT_GROUP => process::exit(a as i32),
+        number::FORK | number::VFORK => process::fork(frame),
+        number::EXECVE => process::execve(a, b, c),
+        number::WAITPID => process::wait4(a as i32, b, c as u32, 0),
+        number::WAIT4 => process::wait4(a as i32, b, c as u32, d),
+        number::READ => io::read(a, b, c),
+        number::WRITE => io::write(a, b, c),
+        number::WRITEV => io::writev(a, b, c),
+        number::IOCTL => io::ioctl(a, b as u32, c),
+        number::OPEN => io::open(a, b as u32),
+        number::CLOSE => io::close(a),
+        number::DUP => io::dup(a),
+        number::DUP2 => io::dup2(a, b),
+        number::DUP3 => io::dup3(a, b, c as u32),
+        number::PIPE => io::pipe(a),
+        number::PIPE2 => io::pipe2(a, b as u32),
+        number::FCNTL | number::FCNTL64 => io::fcntl(a, b as u32, c),
+        number::SOCKETCALL => socket::socketcall(a as u32, b),
+        number::SOCKET => socket::socket(a as u32, b as u32, c as u32),
+        number::SOCKETPAIR => socket::socketpair(a as u32, b as u32, c as u32, d),
+        number::BIND => socket::bind(a, b, c),
+        number::CONNECT => socket::connect(a, b, c),
+        number::LISTEN => socket::listen(a, b as i32),
+        number::ACCEPT4 => socket::accept4(a, b, c, d as u32),
+        number::GETSOCKOPT => socket::getsockopt(a, b as i32, c as i32, d, e),
+        number::SETSOCKOPT => socket::setsockopt(a),
+        number::GETSOCKNAME => socket::getsockname(a, b, c),
+        number::GETPEERNAME => socket::getpeername(a, b, c),
+        number::SENDTO => socket::sendto(a, b, c, d as u32, e, f),
+        number::SENDMSG => socket::sendmsg(a, b, c as u32),
+        number::RECVFROM => socket::recvfrom(a, b, c, d as u32, e, f),
+        number::RECVMSG => socket::recvmsg(a, b, c as u32),
+        number::SHUTDOWN => socket::shutdown(a, b as u32),
+        number::BRK => memory::brk(a),
+        number::MMAP2 => memory::mmap2(a, b, c as u32, d as u32, e as i32, f),
+        number::MUNMAP => memory::munmap(a, b),
+        number::MPROTECT => memory::mprotect(a, b, c as u32),
+        number::GETPID | number::GETTID => process::getpid(),
+        number::GETPPID => process::getppid(),
+        number::KILL => signal::kill(a as i32, b as u32),
+        number::TKILL => signal::tkill(a as i32, b as u32),
+        number::TGKILL => signal::tgkill(a as i32, b as i32, c as u32),
+        number::RT_SIGACTION => signal::rt_sigaction(a as u32, b, c, d),
+        number::RT_SIGPROCMASK => signal::rt_sigprocmask(a as u32, b, c, d),
+        number::RT_SIGPENDING => signal::rt_sigpending(a, b),
+        number::SIGRETURN => signal::sigreturn(frame, false),
+        number::RT_SIGRETURN => signal::sigreturn(frame, true),
+        number::PAUSE => signal::pause(),
+        number::ALARM => time::alarm(a as u32),
+        number::SETITIMER => time::setitimer(a as u32, b, c),
+        number::NANOSLEEP => time::nanosleep(a, b),
+        number::CLOCK_NANOSLEEP => time::clock_nanosleep(a as u32, b as u32, c, d),
+        number::CLOCK_NANOSLEEP_TIME64 => time::clock_nanosleep_time64(a as u32, b as u32, c, d),
+        number::FUTEX => futex::futex(a, b as u32, c as u32, d, time::timeout),
+        number::FUTEX_TIME64 => futex::futex(a, b as u32, c as u32, d, time::timeout_time64),
+        number::SET_TID_ADDRESS => process::set_tid_address(a),
+        number::SET_THREAD_AREA => process::set_thread_area(a),
+        number::UNAME => process::uname(a),
+        number::GETUID => credentials::getuid().map(credentials::narrow),
+        number::GETEUID => credentials::geteuid().map(credentials::narrow),
+        number::GETGID => credentials::getgid().map(credentials::narrow),
+        number::GETEGID => credentials::getegid().map(credentials::narrow),
+        number::SETUID => credentials::setuid(credentials::widen(a as u32)),
+        number::SETGID => credentials::setgid(credentials::widen(a as u32)),
+        number::GETUID32 => credentials::getuid(),
+        number::GETEUID32 => credentials::geteuid(),
+        number::GETGID32 => credentials::getgid(),
+        number::GETEGID32 => credentials::getegid(),
+        number::SETUID32 => credentials::setuid(a as u32),
+        number::SETGID32 => credentials::setgid(a as u32),
+        number::GETRESUID32 => credentials::getresuid([a, b, c]),
+        number::GETRESGID32 => credentials::getresgid([a, b, c]),
+        number => {
+            log::debug!("Unimplemented system call {number}");
+            Err(Errno::NotImplemented)
+        }
+    };
+    frame.eax = match result {
+        Ok(value) => value as u32,
+        Err(errno) => (errno as i32).wrapping_neg() as u32,
+    };
+    // A pause is over once a signal arrived, and sleeps report the time left
+    let restartable = !matches!(
+        number,
+        number::PAUSE
+            | number::NANOSLEEP
+            | number::CLOCK_NANOSLEEP
+            | number::CLOCK_NANOSLEEP_TIME64
+    );
+    match result {
+        Err(Errno::Interrupted) if restartable => Some(number),
+        _ => None,
+    }
+}
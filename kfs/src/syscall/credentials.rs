@@ -0,0 +1,77 @@
+//! User and group ids of the calling process
+//!
+//! The calls without the 32 suffix take and give 16 bit ids, the C library
+//! only uses them on old kernels.
+
+use super::{user_memory, Errno, Result};
+use crate::{
+    credentials::{Credentials, Ids},
+    process,
+};
+
+/// Given by the 16 bit calls for ids that do not fit
+const OVERFLOW_ID: u32 = 65534;
+
+fn current() -> core::result::Result<Credentials, Errno> {
+    process::credentials().ok_or(Errno::Invalid)
+}
+
+/// Id given by a 16 bit call
+pub fn narrow(id: usize) -> usize {
+    match id {
+        0..=0xFFFF => id,
+        _ => OVERFLOW_ID as usize,
+    }
+}
+
+/// Id taken by a 16 bit call, -1 staying -1
+pub fn widen(id: u32) -> u32 {
+    match id as u16 {
+        u16::MAX => u32::MAX,
+        id => id as u32,
+    }
+}
+
+pub fn getuid() -> Result {
+    Ok(current()?.user.real as usize)
+}
+
+pub fn geteuid() -> Result {
+    Ok(current()?.user.effective as usize)
+}
+
+pub fn getgid() -> Result {
+    Ok(current()?.group.real as usize)
+}
+
+pub fn getegid() -> Result {
+    Ok(current()?.group.effective as usize)
+}
+
+pub fn setuid(id: u32) -> Result {
+    process::with_current_credentials(|credentials| credentials.set_user(id))
+        .ok_or(Errno::Invalid)??;
+    Ok(0)
+}
+
+pub fn setgid(id: u32) -> Result {
+    process::with_current_credentials(|credentials| credentials.set_group(id))
+        .ok_or(Errno::Invalid)??;
+    Ok(0)
+}
+
+/// Write the real, effective and saved ids to the three addresses
+fn write_ids(ids: Ids, [real, effective, saved]: [usize; 3]) -> Result {
+    user_memory::write(real, ids.real)?;
+    user_memory::write(effective, ids.effective)?;
+    user_memory::write(saved, ids.saved)?;
+    Ok(0)
+}
+
+pub fn getresuid(addresses: [usize; 3]) -> Result {
+    write_ids(current()?.user, addresses)
+}
+
+pub fn getresgid(addresses: [usize; 3]) -> Result {
+    write_ids(current()?.group, addresses)
+}
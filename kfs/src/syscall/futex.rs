@@ -0,0 +1,41 @@
+//! Waits for a word of the calling program to change, and their wakes
+
+use super::{user_memory, Errno, Result};
+use crate::{futex, pit};
+
+mod operation {
+    pub const WAIT: u32 = 0;
+    pub const WAKE: u32 = 1;
+}
+
+/// Only the process itself uses the word, the key is the same anyway
+const PRIVATE_FLAG: u32 = 128;
+
+/// `futex`, reading the timeout of a wait at `timeout` with `read_timeout`
+pub fn futex(
+    address: usize,
+    operation: u32,
+    value: u32,
+    timeout: usize,
+    read_timeout: fn(usize) -> core::result::Result<Option<u64>, Errno>,
+) -> Result {
+    if !address.is_multiple_of(size_of::<u32>()) {
+        return Err(Errno::Invalid);
+    }
+    match operation & !PRIVATE_FLAG {
+        operation::WAIT => {
+            let timeout = read_timeout(timeout)?;
+            let key = user_memory::physical_word(address)?;
+            let deadline = timeout.map(|ticks| pit::ticks().saturating_add(ticks));
+            let is_expected = || user_memory::read::<u32>(address) == Ok(value);
+            futex::wait(key, is_expected, deadline)?;
+            Ok(0)
+        }
+        operation::WAKE => {
+            let key = user_memory::physical_word(address)?;
+            let count = (value as i32).max(0) as usize;
+            Ok(futex::wake(key, count))
+        }
+        _ => Err(Errno::NotImplemented),
+    }
+}
@@ -0,0 +1,239 @@
+//! Input and output through the descriptors of the calling process
+
+use alloc::sync::Arc;
+
+use super::{user_memory, Errno, Result};
+use crate::{
+    console,
+    file::{self, Access, OpenFile},
+    process,
+    signal::{Info, Signal},
+};
+
+/// Vectors longer than this are refused, as by Linux
+pub(super) const MAX_IO_VECTORS: usize = 1024;
+
+mod request {
+    /// Size of the terminal
+    pub const TIOCGWINSZ: u32 = 0x5413;
+}
+
+/// Flags of `open`, `pipe2` and `dup3`
+mod flags {
+    pub const ACCESS_MODE: u32 = 0b11;
+    pub const READ_ONLY: u32 = 0;
+    pub const WRITE_ONLY: u32 = 1;
+    pub const READ_WRITE: u32 = 2;
+    pub const CLOSE_ON_EXEC: u32 = 0x80000;
+}
+
+/// Commands of `fcntl`
+mod command {
+    pub const DUPLICATE: u32 = 0;
+    pub const GET_DESCRIPTOR_FLAGS: u32 = 1;
+    pub const SET_DESCRIPTOR_FLAGS: u32 = 2;
+    pub const GET_STATUS_FLAGS: u32 = 3;
+    pub const SET_STATUS_FLAGS: u32 = 4;
+    pub const DUPLICATE_CLOSE_ON_EXEC: u32 = 1030;
+
+    /// Only descriptor flag
+    pub const CLOSE_ON_EXEC: usize = 1;
+}
+
+/// Element of the array given to `writev`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct IoVector {
+    pub(super) base: u32,
+    pub(super) length: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct WindowSize {
+    rows: u16,
+    columns: u16,
+    /// In pixels, unknown
+    width: u16,
+    /// In pixels, unknown
+    height: u16,
+}
+
+/// Run `f` on the descriptor table of the caller
+fn with_files<T>(
+    f: impl FnOnce(&mut file::Table) -> core::result::Result<T, file::Error>,
+) -> core::result::Result<T, Errno> {
+    let result = process::with_current_files(f).ok_or(Errno::BadFileDescriptor)?;
+    Ok(result?)
+}
+
+pub(super) fn file(descriptor: usize) -> core::result::Result<Arc<OpenFile>, Errno> {
+    with_files(|files| files.get(descriptor))
+}
+
+pub fn read(descriptor: usize, buffer: usize, count: usize) -> Result {
+    let file = file(descriptor)?;
+    let buffer = user_memory::slice_mut(buffer, count)?;
+    Ok(file.read(buffer)?)
+}
+
+/// Writing to a pipe nobody reads also sends `SIGPIPE`
+pub fn write(descriptor: usize, buffer: usize, count: usize) -> Result {
+    let file = file(descriptor)?;
+    match file.write(user_memory::slice(buffer, count)?) {
+        Err(file::Error::BrokenPipe) => {
+            if let Some(current) = process::current() {
+                let _ = process::signal(current, Some(Signal::PIPE), Info::kernel());
+            }
+            Err(Errno::BrokenPipe)
+        }
+        result => Ok(result?),
+    }
+}
+
+pub fn writev(descriptor: usize, vectors: usize, count: usize) -> Result {
+    if MAX_IO_VECTORS < count {
+        return Err(Errno::Invalid);
+    }
+    let mut written = 0;
+    for index in 0..count {
+        let address = vectors + index * core::mem::size_of::<IoVector>();
+        let vector: IoVector = user_memory::read(address)?;
+        let count = write(descriptor, vector.base as usize, vector.length as usize)?;
+        written += count;
+        if count < vector.length as usize {
+            break;
+        }
+    }
+    Ok(written)
+}
+
+pub fn ioctl(descriptor: usize, request: u32, argument: usize) -> Result {
+    if !file(descriptor)?.is_terminal() {
+        return Err(Errno::NotTerminal);
+    }
+    match request {
+        request::TIOCGWINSZ => {
+            let size = WindowSize {
+                rows: console::ROWS as u16,
+                columns: console::COLUMNS as u16,
+                width: 0,
+                height: 0,
+            };
+            user_memory::write(argument, size).map(|()| 0)
+        }
+        _ => Err(Errno::NotTerminal),
+    }
+}
+
+/// Files can not be created, other flags than the access mode and
+/// `O_CLOEXEC` are ignored
+pub fn open(path: usize, flags: u32) -> Result {
+    let path = user_memory::path(path)?;
+    let access = match flags & flags::ACCESS_MODE {
+        flags::READ_ONLY => Access::READ,
+        flags::WRITE_ONLY => Access::WRITE,
+        flags::READ_WRITE => Access::READ_WRITE,
+        _ => return Err(Errno::Invalid),
+    };
+    let credentials = process::credentials().ok_or(Errno::Invalid)?;
+    let file = file::open(&path, access, &credentials)?;
+    let close_on_exec = flags & flags::CLOSE_ON_EXEC != 0;
+    with_files(|files| files.insert(file, 0, close_on_exec))
+}
+
+pub fn close(descriptor: usize) -> Result {
+    let file = with_files(|files| files.close(descriptor))?;
+    // Closing a pipe end wakes the tasks at the other one
+    drop(file);
+    Ok(0)
+}
+
+pub fn dup(descriptor: usize) -> Result {
+    with_files(|files| files.insert(files.get(descriptor)?, 0, false))
+}
+
+pub fn dup2(descriptor: usize, target: usize) -> Result {
+    if descriptor == target {
+        return file(descriptor).map(|_| target);
+    }
+    dup3(descriptor, target, 0)
+}
+
+pub fn dup3(descriptor: usize, target: usize, flags: u32) -> Result {
+    if descriptor == target || flags & !flags::CLOSE_ON_EXEC != 0 {
+        return Err(Errno::Invalid);
+    }
+    if file::MAX_DESCRIPTORS <= target {
+        return Err(Errno::BadFileDescriptor);
+    }
+    let close_on_exec = flags & flags::CLOSE_ON_EXEC != 0;
+    let replaced =
+        with_files(|files| Ok(files.set(target, files.get(descriptor)?, close_on_exec)))?;
+    drop(replaced);
+    Ok(target)
+}
+
+pub fn pipe(descriptors: usize) -> Result {
+    pipe2(descriptors, 0)
+}
+
+pub fn pipe2(descriptors: usize, flags: u32) -> Result {
+    if flags & !flags::CLOSE_ON_EXEC != 0 {
+        return Err(Errno::Invalid);
+    }
+    let close_on_exec = flags & flags::CLOSE_ON_EXEC != 0;
+    let (reader, writer) = file::pipe();
+    let (reader, writer) = (Arc::new(reader), Arc::new(writer));
+    let ends = with_files(|files| {
+        let reader = files.insert(reader, 0, close_on_exec)?;
+        match files.insert(writer, 0, close_on_exec) {
+            Ok(writer) => Ok([reader, writer]),
+            Err(error) => {
+                let _ = files.close(reader);
+                Err(error)
+            }
+        }
+    })?;
+    let [reader, writer] = ends;
+    if let Err(errno) = user_memory::write(descriptors, ends.map(|end| end as u32)) {
+        let closed = with_files(|files| Ok([files.close(reader), files.close(writer)]));
+        drop(closed);
+        return Err(errno);
+    }
+    Ok(0)
+}
+
+pub fn fcntl(descriptor: usize, command: u32, argument: usize) -> Result {
+    match command {
+        command::DUPLICATE | command::DUPLICATE_CLOSE_ON_EXEC => {
+            let close_on_exec = command == command::DUPLICATE_CLOSE_ON_EXEC;
+            if file::MAX_DESCRIPTORS <= argument {
+                return Err(Errno::Invalid);
+            }
+            with_files(|files| files.insert(files.get(descriptor)?, argument, close_on_exec))
+        }
+        command::GET_DESCRIPTOR_FLAGS => {
+            let close_on_exec = with_files(|files| files.close_on_exec(descriptor))?;
+            Ok(match close_on_exec {
+                true => command::CLOSE_ON_EXEC,
+                false => 0,
+            })
+        }
+        command::SET_DESCRIPTOR_FLAGS => {
+            let close_on_exec = argument & command::CLOSE_ON_EXEC != 0;
+            with_files(|files| files.set_close_on_exec(descriptor, close_on_exec)).map(|()| 0)
+        }
+        command::GET_STATUS_FLAGS => {
+            let access = file(descriptor)?.access();
+            Ok(match (access.read, access.write) {
+                (true, true) => flags::READ_WRITE,
+                (false, true) => flags::WRITE_ONLY,
+                _ => flags::READ_ONLY,
+            } as usize)
+        }
+        // Status flags such as `O_NONBLOCK` are not supported
+        command::SET_STATUS_FLAGS => file(descriptor).map(|_| 0),
+        _ => Err(Errno::Invalid),
+    }
+}
@@ -0,0 +1,204 @@
+//! Memory of the program: its break and its mappings, anonymous or of a file
+
+use alloc::{vec, vec::Vec};
+
+use super::{io, Errno, Result};
+use crate::{
+    file::OpenFile,
+    frame, task, user,
+    vmm::{self, Flags, PhysicalAddress, PAGE_SIZE},
+};
+
+mod protection {
+    pub const READ: u32 = 1 << 0;
+    pub const WRITE: u32 = 1 << 1;
+    pub const EXECUTE: u32 = 1 << 2;
+}
+
+mod flags {
+    pub const SHARED: u32 = 1 << 0;
+    pub const PRIVATE: u32 = 1 << 1;
+    pub const FIXED: u32 = 1 << 4;
+    pub const ANONYMOUS: u32 = 1 << 5;
+}
+
+/// Mappings are placed under the stack and the signal trampoline, from the
+/// top down
+const MAPPINGS_END: vmm::VirtualAddress = user::SIGNAL_TRAMPOLINE;
+
+/// Move the program break to `end` if possible, return the program break
+///
+/// Failures leave it where it was, programs compare the result.
+pub fn brk(end: usize) -> Result {
+    let address_space = task::address_space().ok_or(Errno::OutOfMemory)?;
+    let mut address_space = address_space.lock();
+    if end != 0 {
+        let _ = address_space.set_program_break(end);
+    }
+    Ok(address_space.program_break())
+}
+
+fn flags_of(protection: u32) -> Flags {
+    let mut flags = Flags::NONE;
+    if protection & (protection::READ | protection::WRITE | protection::EXECUTE) != 0 {
+        flags |= Flags::USER;
+    }
+    if protection & protection::WRITE != 0 {
+        flags |= Flags::WRITABLE;
+    }
+    if protection & protection::EXECUTE == 0 {
+        flags |= Flags::NO_EXECUTE;
+    }
+    flags
+}
+
+/// Map `length` bytes, at `address` if [flags::FIXED] is set
+///
+/// File mappings get a copy of the file from `page_offset`, bytes past its
+/// end read as zeroes. The frames of a program are never shared, so
+/// anonymous mappings can not be [flags::SHARED] and shared file mappings
+/// behave as [flags::PRIVATE], but still need to be allowed to write the
+/// file to be writable.
+///
+/// A failure leaves the mappings that [flags::FIXED] would replace in place.
+pub fn mmap2(
+    address: usize,
+    length: usize,
+    protection: u32,
+    flags: u32,
+    descriptor: i32,
+    page_offset: usize,
+) -> Result {
+    if length == 0 || flags & (flags::SHARED | flags::PRIVATE) == 0 {
+        return Err(Errno::Invalid);
+    }
+    if flags & (flags::SHARED | flags::ANONYMOUS) == flags::SHARED | flags::ANONYMOUS {
+        return Err(Errno::Invalid);
+    }
+    let file = match flags & flags::ANONYMOUS != 0 {
+        true => None,
+        false => {
+            let file = io::file(descriptor as usize)?;
+            let access = file.access();
+            let writes_file = flags & flags::SHARED != 0 && protection & protection::WRITE != 0;
+            if !access.read || (writes_file && !access.write) {
+                return Err(Errno::AccessDenied);
+            }
+            Some(file)
+        }
+    };
+    let offset = page_offset.checked_mul(PAGE_SIZE).ok_or(Errno::Invalid)?;
+    let size = length
+        .checked_add(PAGE_SIZE - 1)
+        .ok_or(Errno::OutOfMemory)?
+        & !(PAGE_SIZE - 1);
+
+    if flags & flags::FIXED != 0 {
+        check_range(address, size)?;
+    }
+    // Read before touching the address space, which a failure keeps as is
+    let frames = match file {
+        Some(file) => read_pages(&file, size, offset)?,
+        None => Vec::new(),
+    };
+
+    let address_space = task::address_space().ok_or(Errno::OutOfMemory)?;
+    let mut address_space = address_space.lock();
+    let start = match flags & flags::FIXED != 0 {
+        true => Some(address),
+        false => address_space.find_free_range(size, vmm::USER_START, MAPPINGS_END),
+    };
+    let result = match start {
+        Some(start) => address_space
+            .replace(start, size, flags_of(protection), &frames)
+            .map(|()| start)
+            .map_err(Errno::from),
+        None => Err(Errno::OutOfMemory),
+    };
+    if result.is_err() {
+        frames.iter().for_each(|&frame| frame::free(frame));
+    }
+    result
+}
+
+/// Frames holding up to `size` bytes of `file` from `offset`, bytes past its
+/// end are zeroes
+fn read_pages(
+    file: &OpenFile,
+    size: usize,
+    offset: usize,
+) -> core::result::Result<Vec<PhysicalAddress>, Errno> {
+    let mut frames = Vec::new();
+    let result = read_pages_into(&mut frames, file, size, offset);
+    if result.is_err() {
+        frames.iter().for_each(|&frame| frame::free(frame));
+    }
+    result.map(|()| frames)
+}
+
+fn read_pages_into(
+    frames: &mut Vec<PhysicalAddress>,
+    file: &OpenFile,
+    size: usize,
+    offset: usize,
+) -> core::result::Result<(), Errno> {
+    let mut page = vec![0; PAGE_SIZE];
+    for copied in (0..size).step_by(PAGE_SIZE) {
+        let count = file.read_at(offset.saturating_add(copied), &mut page)?;
+        if count == 0 {
+            break;
+        }
+        frames.try_reserve(1).map_err(|_| Errno::OutOfMemory)?;
+        let frame = vmm::allocate_zeroed_frame()?;
+        frames.push(frame);
+        vmm::with_frame(frame, |destination| unsafe {
+            core::ptr::copy_nonoverlapping(page.as_ptr(), destination, count);
+        });
+        if count < PAGE_SIZE {
+            break;
+        }
+    }
+    Ok(())
+}
+
+pub fn munmap(address: usize, length: usize) -> Result {
+    check_range(address, length)?;
+    let address_space = task::address_space().ok_or(Errno::Invalid)?;
+    address_space.lock().unreserve(address, length)?;
+    Ok(0)
+}
+
+/// Change the protection of the mapped pages of `[address, address + length)`
+pub fn mprotect(address: usize, length: usize, protection: u32) -> Result {
+    if length == 0 {
+        return match vmm::is_page_aligned(address) {
+            true => Ok(0),
+            false => Err(Errno::Invalid),
+        };
+    }
+    check_range(address, length)?;
+    let address_space = task::address_space().ok_or(Errno::OutOfMemory)?;
+    let result = address_space
+        .lock()
+        .protect(address, length, flags_of(protection));
+    match result {
+        Ok(()) => Ok(0),
+        // Part of the range is not mapped
+        Err(vmm::Error::NotMapped) => Err(Errno::OutOfMemory),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Check that `[address, address + size)` is page aligned user memory
+fn check_range(address: usize, size: usize) -> core::result::Result<(), Errno> {
+    let valid = vmm::is_page_aligned(address)
+        && size != 0
+        && vmm::is_user_address(address)
+        && address
+            .checked_add(size)
+            .is_some_and(|end| vmm::is_user_address(end - 1));
+    match valid {
+        true => Ok(()),
+        false => Err(Errno::Invalid),
+    }
+}
@@ -0,0 +1,172 @@
+//! Identity and lifecycle of the calling process
+
+use alloc::vec::Vec;
+
+use super::{user_memory, Errno, Result};
+use crate::{
+    credentials::Permissions,
+    gdt,
+    interrupts::Frame,
+    loader, module,
+    process::{self, Child, ExitStatus, ProcessId},
+    task,
+};
+
+/// Length of every field of `struct utsname`, terminating null included
+const UTS_FIELD_LENGTH: usize = 65;
+/// Size of `struct rusage`
+const RESOURCE_USAGE_SIZE: usize = 72;
+
+mod wait_options {
+    pub const NO_HANG: u32 = 1 << 0;
+    /// Stopped children are not reported
+    pub const UNTRACED: u32 = 1 << 1;
+    /// Continued children are not reported
+    pub const CONTINUED: u32 = 1 << 3;
+}
+
+/// Segment description given to `set_thread_area`, `struct user_desc`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UserDescriptor {
+    /// Index in the descriptor table, -1 to let the kernel choose
+    entry_number: u32,
+    base: u32,
+    limit: u32,
+    flags: u32,
+}
+
+impl UserDescriptor {
+    /// Data, stack or code
+    const CONTENTS: u32 = 0b11 << 1;
+    const READ_EXECUTE_ONLY: u32 = 1 << 3;
+    const LIMIT_IN_PAGES: u32 = 1 << 4;
+    const NOT_PRESENT: u32 = 1 << 5;
+}
+
+/// Only the low byte of `status` reaches the parent
+pub fn exit(status: i32) -> ! {
+    process::exit(ExitStatus::Exited(status as u8))
+}
+
+/// Every process runs in a single task, so it is also the thread id
+pub fn getpid() -> Result {
+    let id = process::current().ok_or(Errno::Invalid)?;
+    Ok(id.as_u32() as usize)
+}
+
+pub fn getppid() -> Result {
+    let id = process::parent().ok_or(Errno::Invalid)?;
+    Ok(id.as_u32() as usize)
+}
+
+/// The child returns 0 from the same call
+pub fn fork(frame: &Frame) -> Result {
+    let child = process::fork(frame)?;
+    Ok(child.as_u32() as usize)
+}
+
+/// Run the boot module named by the last component of `path` in place of
+/// the program, if the caller may execute it
+///
+/// The call only returns on failure.
+pub fn execve(path: usize, arguments: usize, environment: usize) -> Result {
+    let (name, program) = load(path, arguments, environment)?;
+    process::exec(name, program)
+}
+
+/// Load the program of [execve], dropping every copy of the arguments
+/// before the program replaces the current one
+fn load(
+    path: usize,
+    arguments: usize,
+    environment: usize,
+) -> core::result::Result<(&'static str, loader::Program), Errno> {
+    let path = user_memory::path(path)?;
+    let name = path.rsplit('/').next().unwrap_or_default();
+    let module = module::find(name).ok_or(Errno::NoEntry)?;
+    let credentials = process::credentials().ok_or(Errno::Invalid)?;
+    if !module::PERMISSIONS.allows(&credentials, Permissions::EXECUTE) {
+        return Err(Errno::AccessDenied);
+    }
+    let arguments = user_memory::strings(arguments, loader::MAX_ARGUMENTS_SIZE)?;
+    let environment = user_memory::strings(environment, loader::MAX_ARGUMENTS_SIZE)?;
+
+    let arguments: Vec<&str> = arguments.iter().map(|argument| argument.as_str()).collect();
+    let environment: Vec<&str> = environment
+        .iter()
+        .map(|variable| variable.as_str())
+        .collect();
+    let program = loader::load(module.data, &arguments, &environment, &credentials)?;
+    Ok((module.name, program))
+}
+
+/// `waitpid` is `wait4` without `usage`, which is zeroed since no usage is
+/// accounted
+///
+/// Process groups do not exist, so `pid` 0 and below -1 wait for any child
+/// like -1 does.
+pub fn wait4(pid: i32, status: usize, options: u32, usage: usize) -> Result {
+    let known = wait_options::NO_HANG | wait_options::UNTRACED | wait_options::CONTINUED;
+    if options & !known != 0 {
+        return Err(Errno::Invalid);
+    }
+    let child = match pid {
+        1.. => Child::Id(ProcessId::from(pid as u32)),
+        _ => Child::Any,
+    };
+    let no_hang = options & wait_options::NO_HANG != 0;
+    let Some((id, exit_status)) = process::wait(child, no_hang)? else {
+        return Ok(0);
+    };
+    if status != 0 {
+        user_memory::write(status, exit_status.wait_status())?;
+    }
+    if usage != 0 {
+        user_memory::write(usage, [0u8; RESOURCE_USAGE_SIZE])?;
+    }
+    Ok(id.as_u32() as usize)
+}
+
+/// Linux clears the word at the address when the thread exits, for the
+/// threads sharing its memory, no task shares the memory of a program here
+pub fn set_tid_address(_address: usize) -> Result {
+    getpid()
+}
+
+/// Install the thread local storage segment of the program, the only entry
+/// it may use is [gdt::THREAD_LOCAL]
+pub fn set_thread_area(address: usize) -> Result {
+    let mut descriptor: UserDescriptor = user_memory::read(address)?;
+    let entry = (gdt::THREAD_LOCAL / 8) as u32;
+    if descriptor.entry_number != u32::MAX && descriptor.entry_number != entry {
+        return Err(Errno::Invalid);
+    }
+    let flags = descriptor.flags;
+    if flags & UserDescriptor::CONTENTS != 0 {
+        // Only data segments are supported
+        return Err(Errno::Invalid);
+    }
+    descriptor.entry_number = entry;
+    user_memory::write(address, descriptor)?;
+
+    task::set_thread_local(match flags & UserDescriptor::NOT_PRESENT != 0 {
+        true => gdt::Descriptor::NULL,
+        false => gdt::Descriptor::user_data(
+            descriptor.base,
+            descriptor.limit,
+            flags & UserDescriptor::LIMIT_IN_PAGES != 0,
+            flags & UserDescriptor::READ_EXECUTE_ONLY == 0,
+        ),
+    });
+    Ok(0)
+}
+
+pub fn uname(address: usize) -> Result {
+    let fields = ["kfs", "kfs", env!("CARGO_PKG_VERSION"), "kfs", "i686", ""];
+    let mut name = [0u8; 6 * UTS_FIELD_LENGTH];
+    for (field, value) in name.chunks_mut(UTS_FIELD_LENGTH).zip(fields) {
+        field[..value.len()].copy_from_slice(value.as_bytes());
+    }
+    user_memory::write(address, name).map(|()| 0)
+}
@@ -0,0 +1,215 @@
+//! Signals sent, caught and blocked by the calling process
+
+use super::{user_memory, Errno, Result};
+use crate::{
+    credentials::Credentials,
+    interrupts::Frame,
+    process::{self, ProcessId},
+    signal::{self, Action, Info, Signal, SignalSet},
+    task,
+};
+
+/// Size of the signal sets of the kernel, 64 signals
+const SIGNAL_SET_SIZE: usize = 8;
+
+mod how {
+    pub const BLOCK: u32 = 0;
+    pub const UNBLOCK: u32 = 1;
+    pub const SET_MASK: u32 = 2;
+}
+
+/// `struct sigaction` of the kernel, not of the C library
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SignalAction {
+    handler: u32,
+    flags: u32,
+    restorer: u32,
+    mask: [u32; 2],
+}
+
+fn read_set(address: usize) -> core::result::Result<SignalSet, Errno> {
+    let [low, high]: [u32; 2] = user_memory::read(address)?;
+    Ok(SignalSet::from_bits((high as u64) << 32 | low as u64))
+}
+
+fn write_set(address: usize, set: SignalSet) -> core::result::Result<(), Errno> {
+    let bits = set.bits();
+    user_memory::write(address, [bits as u32, (bits >> 32) as u32])
+}
+
+/// Signal 0 checks that the signal could be sent
+fn signal_of(number: u32) -> core::result::Result<Option<Signal>, Errno> {
+    match number {
+        0 => Ok(None),
+        number => Signal::new(number).map(Some).ok_or(Errno::Invalid),
+    }
+}
+
+/// Process groups do not exist, so `pid` 0 is the caller and below -1 is
+/// the process `-pid`
+///
+/// Programs that are not run by root may only signal the processes of
+/// their user.
+pub fn kill(pid: i32, number: u32) -> Result {
+    let signal = signal_of(number)?;
+    let current = process::current().ok_or(Errno::Invalid)?;
+    let credentials = process::credentials().ok_or(Errno::Invalid)?;
+    let info = Info::sent_by(current, false);
+    let target = match pid {
+        1.. => ProcessId::from(pid as u32),
+        0 => current,
+        -1 => return kill_all(current, &credentials, signal, info),
+        _ => ProcessId::from(pid.unsigned_abs()),
+    };
+    process::signal_as(&credentials, target, signal, info)?;
+    Ok(0)
+}
+
+/// Every process the caller may signal, except init and itself
+fn kill_all(
+    current: ProcessId,
+    credentials: &Credentials,
+    signal: Option<Signal>,
+    info: Info,
+) -> Result {
+    let sent = process::processes()
+        .into_iter()
+        .filter(|process| process.id != ProcessId::INIT && process.id != current)
+        .filter(|process| process::signal_as(credentials, process.id, signal, info).is_ok())
+        .count();
+    match sent {
+        0 => Err(Errno::NoProcess),
+        _ => Ok(0),
+    }
+}
+
+/// Every process runs in a single task, so threads are processes
+pub fn tkill(tid: i32, number: u32) -> Result {
+    if tid <= 0 {
+        return Err(Errno::Invalid);
+    }
+    let signal = signal_of(number)?;
+    let current = process::current().ok_or(Errno::Invalid)?;
+    let credentials = process::credentials().ok_or(Errno::Invalid)?;
+    process::signal_as(
+        &credentials,
+        ProcessId::from(tid as u32),
+        signal,
+        Info::sent_by(current, true),
+    )?;
+    Ok(0)
+}
+
+pub fn tgkill(tgid: i32, tid: i32, number: u32) -> Result {
+    if tgid <= 0 {
+        return Err(Errno::Invalid);
+    }
+    match tgid == tid {
+        true => tkill(tid, number),
+        false => Err(Errno::NoProcess),
+    }
+}
+
+pub fn rt_sigaction(number: u32, action: usize, old_action: usize, set_size: usize) -> Result {
+    if set_size != SIGNAL_SET_SIZE {
+        return Err(Errno::Invalid);
+    }
+    let signal = Signal::new(number).ok_or(Errno::Invalid)?;
+    // Read before writing the old action, both may be the same
+    let action = match action {
+        0 => None,
+        _ if signal.is_unstoppable() => return Err(Errno::Invalid),
+        address => {
+            let action: SignalAction = user_memory::read(address)?;
+            let [low, high] = action.mask;
+            Some(Action {
+                handler: action.handler,
+                flags: action.flags,
+                restorer: action.restorer,
+                mask: SignalSet::from_bits((high as u64) << 32 | low as u64),
+            })
+        }
+    };
+    let old = process::with_current_signals(|signals| {
+        let old = signals.action(signal);
+        if let Some(action) = action {
+            signals.set_action(signal, action);
+        }
+        old
+    })
+    .ok_or(Errno::Invalid)?;
+    if old_action != 0 {
+        let mask = old.mask.bits();
+        let old = SignalAction {
+            handler: old.handler,
+            flags: old.flags,
+            restorer: old.restorer,
+            mask: [mask as u32, (mask >> 32) as u32],
+        };
+        user_memory::write(old_action, old)?;
+    }
+    Ok(0)
+}
+
+pub fn rt_sigprocmask(how: u32, set: usize, old_set: usize, set_size: usize) -> Result {
+    if set_size != SIGNAL_SET_SIZE {
+        return Err(Errno::Invalid);
+    }
+    let set = match set {
+        0 => None,
+        address => Some(read_set(address)?),
+    };
+    if set.is_some() && !matches!(how, how::BLOCK | how::UNBLOCK | how::SET_MASK) {
+        return Err(Errno::Invalid);
+    }
+    let old = process::with_current_signals(|signals| {
+        let old = signals.blocked();
+        if let Some(set) = set {
+            signals.set_blocked(match how {
+                how::BLOCK => old.union(set),
+                how::UNBLOCK => old.difference(set),
+                _ => set,
+            });
+        }
+        old
+    })
+    .ok_or(Errno::Invalid)?;
+    if old_set != 0 {
+        write_set(old_set, old)?;
+    }
+    Ok(0)
+}
+
+pub fn rt_sigpending(set: usize, set_size: usize) -> Result {
+    if set_size != SIGNAL_SET_SIZE {
+        return Err(Errno::Invalid);
+    }
+    let pending =
+        process::with_current_signals(|signals| signals.pending()).ok_or(Errno::Invalid)?;
+    write_set(set, pending).map(|()| 0)
+}
+
+/// Wait for a signal, the call never succeeds
+pub fn pause() -> Result {
+    while !signal::is_interrupted() {
+        task::block();
+    }
+    Err(Errno::Interrupted)
+}
+
+/// Resume the context a handler interrupted, a corrupted frame kills the
+/// program
+pub fn sigreturn(frame: &mut Frame, real_time: bool) -> Result {
+    let result = match real_time {
+        false => signal::sigreturn(frame),
+        true => signal::rt_sigreturn(frame),
+    };
+    match result {
+        Ok(eax) => Ok(eax as usize),
+        Err(()) => {
+            signal::force(Signal::SEGV, Info::kernel());
+            Err(Errno::Fault)
+        }
+    }
+}
@@ -0,0 +1,556 @@
+//! UNIX domain sockets of the calling process
+//!
+//! Linux on i386 long only had `socketcall`, which multiplexes the other
+//! calls, programs may use either.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use super::{
+    io::{self, IoVector, MAX_IO_VECTORS},
+    user_memory, Errno, Result,
+};
+use crate::{
+    file::{
+        socket::{self, Address, Kind, Received},
+        OpenFile, Socket,
+    },
+    process,
+    signal::{Info, Signal},
+};
+
+/// `AF_UNIX`, also known as `AF_LOCAL`
+const FAMILY_UNIX: u16 = 1;
+/// Size of `sun_path` in `struct sockaddr_un`
+const PATH_SIZE: usize = 108;
+
+/// `SOL_SOCKET`, level of the options and control messages of any socket
+const SOCKET_LEVEL: i32 = 1;
+
+/// Types of `socket` and their flags
+mod kind {
+    pub const STREAM: u32 = 1;
+    pub const DATAGRAM: u32 = 2;
+    pub const MASK: u32 = 0xF;
+    pub const NONBLOCK: u32 = 0o4000;
+    pub const CLOSE_ON_EXEC: u32 = 0x80000;
+}
+
+/// Flags of the sends and receives
+mod message {
+    /// Some control messages did not fit
+    pub const CONTROL_TRUNCATED: u32 = 0x8;
+    /// The datagram did not fit
+    pub const TRUNCATED: u32 = 0x20;
+    pub const DONT_WAIT: u32 = 0x40;
+    /// No `SIGPIPE` on a broken connection
+    pub const NO_SIGNAL: u32 = 0x4000;
+    /// Received descriptors are closed on `execve`
+    pub const CLOSE_ON_EXEC: u32 = 0x4000_0000;
+}
+
+/// Options of `getsockopt`
+mod option {
+    pub const TYPE: i32 = 3;
+    pub const ERROR: i32 = 4;
+}
+
+/// Control message with open files, `SCM_RIGHTS`
+const RIGHTS: i32 = 1;
+
+/// Calls of `socketcall`, with their argument count
+mod call {
+    pub const SOCKET: u32 = 1;
+    pub const BIND: u32 = 2;
+    pub const CONNECT: u32 = 3;
+    pub const LISTEN: u32 = 4;
+    pub const ACCEPT: u32 = 5;
+    pub const GETSOCKNAME: u32 = 6;
+    pub const GETPEERNAME: u32 = 7;
+    pub const SOCKETPAIR: u32 = 8;
+    pub const SEND: u32 = 9;
+    pub const RECV: u32 = 10;
+    pub const SENDTO: u32 = 11;
+    pub const RECVFROM: u32 = 12;
+    pub const SHUTDOWN: u32 = 13;
+    pub const SETSOCKOPT: u32 = 14;
+    pub const GETSOCKOPT: u32 = 15;
+    pub const SENDMSG: u32 = 16;
+    pub const RECVMSG: u32 = 17;
+    pub const ACCEPT4: u32 = 18;
+
+    pub const ARGUMENT_COUNTS: [usize; 19] =
+        [0, 3, 3, 3, 2, 3, 3, 3, 4, 4, 4, 6, 6, 2, 5, 5, 3, 3, 4];
+}
+
+/// `struct msghdr`
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct MessageHeader {
+    name: u32,
+    name_length: u32,
+    vectors: u32,
+    vector_count: u32,
+    control: u32,
+    control_length: u32,
+    flags: u32,
+}
+
+/// `struct cmsghdr`, followed by the data of the control message
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ControlHeader {
+    /// Header included
+    length: u32,
+    level: i32,
+    kind: i32,
+}
+
+const CONTROL_HEADER_SIZE: usize = core::mem::size_of::<ControlHeader>();
+
+/// Run `f` on the socket open as `descriptor`
+fn with_socket<T>(
+    descriptor: usize,
+    f: impl FnOnce(&Socket) -> core::result::Result<T, socket::Error>,
+) -> core::result::Result<T, Errno> {
+    let file = io::file(descriptor)?;
+    let socket = file.socket().ok_or(Errno::NotSocket)?;
+    Ok(f(socket)?)
+}
+
+/// Insert `file` in the descriptor table of the caller
+fn insert(file: Arc<OpenFile>, close_on_exec: bool) -> Result {
+    let descriptor = process::with_current_files(|files| files.insert(file, 0, close_on_exec))
+        .ok_or(Errno::BadFileDescriptor)?;
+    Ok(descriptor?)
+}
+
+/// Kind of socket and flags of `socket` and `socketpair`
+fn kind_of(domain: u32, kind: u32, protocol: u32) -> core::result::Result<(Kind, u32), Errno> {
+    if domain != FAMILY_UNIX as u32 {
+        return Err(Errno::FamilyNotSupported);
+    }
+    if protocol != 0 {
+        return Err(Errno::ProtocolNotSupported);
+    }
+    let flags = kind & !kind::MASK;
+    if flags & !(kind::NONBLOCK | kind::CLOSE_ON_EXEC) != 0 {
+        return Err(Errno::Invalid);
+    }
+    match kind & kind::MASK {
+        kind::STREAM => Ok((Kind::Stream, flags)),
+        kind::DATAGRAM => Ok((Kind::Datagram, flags)),
+        _ => Err(Errno::SocketTypeNotSupported),
+    }
+}
+
+/// `struct sockaddr_un` of `length` bytes at `address`, [None] when it has
+/// no name
+fn read_address(address: usize, length: usize) -> core::result::Result<Option<Address>, Errno> {
+    if !(2..=2 + PATH_SIZE).contains(&length) {
+        return Err(Errno::Invalid);
+    }
+    let bytes = user_memory::slice(address, length)?;
+    if u16::from_ne_bytes([bytes[0], bytes[1]]) != FAMILY_UNIX {
+        return Err(Errno::Invalid);
+    }
+    let path = &bytes[2..];
+    match path.first() {
+        None => Ok(None),
+        Some(0) => Ok(Some(Address::Abstract(path[1..].to_vec()))),
+        Some(_) => {
+            let end = path
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(path.len());
+            let path = core::str::from_utf8(&path[..end]).map_err(|_| Errno::Invalid)?;
+            Ok(Some(Address::Path(String::from(path))))
+        }
+    }
+}
+
+/// `struct sockaddr_un` of `address`, as long as needed
+fn encode_address(address: Option<&Address>) -> Vec<u8> {
+    let mut bytes = Vec::from(FAMILY_UNIX.to_ne_bytes());
+    match address {
+        Some(Address::Path(path)) => {
+            bytes.extend_from_slice(path.as_bytes());
+            bytes.push(0);
+        }
+        Some(Address::Abstract(name)) => {
+            bytes.push(0);
+            bytes.extend_from_slice(name);
+        }
+        None => (),
+    }
+    bytes
+}
+
+/// Copy what fits of `address` to `buffer`, its size being read from and
+/// written to `length`, unless `buffer` is null
+fn write_address(
+    address: Option<&Address>,
+    buffer: usize,
+    length: usize,
+) -> core::result::Result<(), Errno> {
+    if buffer == 0 {
+        return Ok(());
+    }
+    let bytes = encode_address(address);
+    let size: u32 = user_memory::read(length)?;
+    let count = bytes.len().min(size as usize);
+    user_memory::slice_mut(buffer, count)?.copy_from_slice(&bytes[..count]);
+    user_memory::write(length, bytes.len() as u32)
+}
+
+/// Sending on a broken connection also sends `SIGPIPE`, unless
+/// [message::NO_SIGNAL] is set
+fn sent(result: core::result::Result<usize, socket::Error>, flags: u32) -> Result {
+    if result == Err(socket::Error::BrokenPipe) && flags & message::NO_SIGNAL == 0 {
+        if let Some(current) = process::current() {
+            let _ = process::signal(current, Some(Signal::PIPE), Info::kernel());
+        }
+    }
+    Ok(result?)
+}
+
+pub fn socket(domain: u32, kind: u32, protocol: u32) -> Result {
+    let (kind, flags) = kind_of(domain, kind, protocol)?;
+    let socket = socket::socket(kind, flags & kind::NONBLOCK != 0);
+    insert(Arc::new(socket), flags & kind::CLOSE_ON_EXEC != 0)
+}
+
+pub fn socketpair(domain: u32, kind: u32, protocol: u32, descriptors: usize) -> Result {
+    let (kind, flags) = kind_of(domain, kind, protocol)?;
+    let close_on_exec = flags & kind::CLOSE_ON_EXEC != 0;
+    let (first, second) = socket::pair(kind, flags & kind::NONBLOCK != 0);
+    let first = insert(Arc::new(first), close_on_exec)?;
+    let second = match insert(Arc::new(second), close_on_exec) {
+        Ok(second) => second,
+        Err(errno) => {
+            let closed = process::with_current_files(|files| files.close(first));
+            drop(closed);
+            return Err(errno);
+        }
+    };
+    if let Err(errno) = user_memory::write(descriptors, [first as u32, second as u32]) {
+        let closed = process::with_current_files(|files| [files.close(first), files.close(second)]);
+        drop(closed);
+        return Err(errno);
+    }
+    Ok(0)
+}
+
+/// An address without name binds to a new abstract name
+pub fn bind(descriptor: usize, address: usize, length: usize) -> Result {
+    let address = read_address(address, length)?;
+    with_socket(descriptor, |socket| socket.bind(address))?;
+    Ok(0)
+}
+
+pub fn connect(descriptor: usize, address: usize, length: usize) -> Result {
+    let address = read_address(address, length)?.ok_or(Errno::Invalid)?;
+    with_socket(descriptor, |socket| socket.connect(&address))?;
+    Ok(0)
+}
+
+pub fn listen(descriptor: usize, backlog: i32) -> Result {
+    with_socket(descriptor, |socket| socket.listen(backlog.max(0) as usize))?;
+    Ok(0)
+}
+
+pub fn accept4(descriptor: usize, address: usize, length: usize, flags: u32) -> Result {
+    if flags & !(kind::NONBLOCK | kind::CLOSE_ON_EXEC) != 0 {
+        return Err(Errno::Invalid);
+    }
+    let nonblocking = flags & kind::NONBLOCK != 0;
+    let (file, peer) = with_socket(descriptor, |socket| socket.accept(nonblocking))?;
+    // The connection is dropped if the address can not be written
+    write_address(peer.as_ref(), address, length)?;
+    insert(Arc::new(file), flags & kind::CLOSE_ON_EXEC != 0)
+}
+
+pub fn getsockname(descriptor: usize, address: usize, length: usize) -> Result {
+    let name = with_socket(descriptor, |socket| Ok(socket.address()))?;
+    write_address(name.as_ref(), address, length).map(|()| 0)
+}
+
+pub fn getpeername(descriptor: usize, address: usize, length: usize) -> Result {
+    let name = with_socket(descriptor, |socket| socket.peer_address())?;
+    write_address(name.as_ref(), address, length).map(|()| 0)
+}
+
+/// Only the type and the pending error, never set, can be read
+pub fn getsockopt(descriptor: usize, level: i32, name: i32, value: usize, length: usize) -> Result {
+    let kind = with_socket(descriptor, |socket| Ok(socket.kind()))?;
+    let value_of = match (level, name) {
+        (SOCKET_LEVEL, option::TYPE) => match kind {
+            Kind::Stream => kind::STREAM,
+            Kind::Datagram => kind::DATAGRAM,
+        },
+        (SOCKET_LEVEL, option::ERROR) => 0,
+        _ => return Err(Errno::NoProtocolOption),
+    };
+    let size: u32 = user_memory::read(length)?;
+    if (size as usize) < core::mem::size_of::<u32>() {
+        return Err(Errno::Invalid);
+    }
+    user_memory::write(value, value_of)?;
+    user_memory::write(length, core::mem::size_of::<u32>() as u32).map(|()| 0)
+}
+
+/// No option can be set
+pub fn setsockopt(descriptor: usize) -> Result {
+    with_socket(descriptor, |_| Ok(()))?;
+    Err(Errno::NoProtocolOption)
+}
+
+pub fn sendto(
+    descriptor: usize,
+    buffer: usize,
+    length: usize,
+    flags: u32,
+    address: usize,
+    address_length: usize,
+) -> Result {
+    let destination = match address {
+        0 => None,
+        _ => Some(read_address(address, address_length)?.ok_or(Errno::Invalid)?),
+    };
+    let data = user_memory::slice(buffer, length)?;
+    let dont_wait = flags & message::DONT_WAIT != 0;
+    let result = with_socket(descriptor, |socket| {
+        Ok(socket.send(data, Vec::new(), destination.as_ref(), dont_wait))
+    })?;
+    sent(result, flags)
+}
+
+/// Files received without `recvmsg` are closed
+pub fn recvfrom(
+    descriptor: usize,
+    buffer: usize,
+    length: usize,
+    flags: u32,
+    address: usize,
+    address_length: usize,
+) -> Result {
+    let buffer = user_memory::slice_mut(buffer, length)?;
+    let dont_wait = flags & message::DONT_WAIT != 0;
+    let received = with_socket(descriptor, |socket| socket.receive(buffer, dont_wait))?;
+    write_address(received.sender.as_ref(), address, address_length)?;
+    Ok(received.count)
+}
+
+/// The vectors of `header`
+fn vectors_of(header: &MessageHeader) -> core::result::Result<Vec<IoVector>, Errno> {
+    let count = header.vector_count as usize;
+    if MAX_IO_VECTORS < count {
+        return Err(Errno::MessageTooLong);
+    }
+    (0..count)
+        .map(|index| {
+            user_memory::read(header.vectors as usize + index * core::mem::size_of::<IoVector>())
+        })
+        .collect()
+}
+
+/// Total length of `vectors`, which must fit in a `ssize_t`
+fn length_of(vectors: &[IoVector]) -> core::result::Result<usize, Errno> {
+    vectors
+        .iter()
+        .try_fold(0usize, |length, vector| {
+            length.checked_add(vector.length as usize)
+        })
+        .filter(|&length| length <= isize::MAX as usize)
+        .ok_or(Errno::Invalid)
+}
+
+/// Open files of the `SCM_RIGHTS` control messages of `header`
+fn files_of(header: &MessageHeader) -> core::result::Result<Vec<Arc<OpenFile>>, Errno> {
+    let mut files = Vec::new();
+    let (control, length) = (header.control as usize, header.control_length as usize);
+    let mut offset = 0;
+    while offset + CONTROL_HEADER_SIZE <= length {
+        let message: ControlHeader = user_memory::read(control + offset)?;
+        let size = message.length as usize;
+        if size < CONTROL_HEADER_SIZE || length - offset < size {
+            return Err(Errno::Invalid);
+        }
+        if message.level != SOCKET_LEVEL || message.kind != RIGHTS {
+            return Err(Errno::Invalid);
+        }
+        let count = (size - CONTROL_HEADER_SIZE) / core::mem::size_of::<u32>();
+        if socket::MAX_FILES < files.len() + count {
+            return Err(Errno::Invalid);
+        }
+        for index in 0..count {
+            let address = control + offset + CONTROL_HEADER_SIZE + index * 4;
+            let descriptor: u32 = user_memory::read(address)?;
+            files.push(io::file(descriptor as usize)?);
+        }
+        // Messages are aligned on 4 bytes
+        offset += size.next_multiple_of(core::mem::size_of::<u32>());
+    }
+    Ok(files)
+}
+
+/// Send the bytes of the vectors and the files of the control messages
+pub fn sendmsg(descriptor: usize, header: usize, flags: u32) -> Result {
+    let header: MessageHeader = user_memory::read(header)?;
+    let destination = match header.name {
+        0 => None,
+        name => {
+            Some(read_address(name as usize, header.name_length as usize)?.ok_or(Errno::Invalid)?)
+        }
+    };
+    let vectors = vectors_of(&header)?;
+    let length = length_of(&vectors)?;
+    // Datagrams go whole, a stream takes at most a full queue at once
+    let kind = with_socket(descriptor, |socket| Ok(socket.kind()))?;
+    if kind == Kind::Datagram && socket::CAPACITY < length {
+        return Err(Errno::MessageTooLong);
+    }
+    let mut data = Vec::with_capacity(length.min(socket::CAPACITY));
+    for vector in vectors {
+        let part = (vector.length as usize).min(socket::CAPACITY - data.len());
+        data.extend_from_slice(user_memory::slice(vector.base as usize, part)?);
+    }
+    let files = files_of(&header)?;
+    let dont_wait = flags & message::DONT_WAIT != 0;
+    let result = with_socket(descriptor, |socket| {
+        Ok(socket.send(&data, files, destination.as_ref(), dont_wait))
+    })?;
+    sent(result, flags)
+}
+
+/// Install the received `files` and describe them in the control buffer of
+/// `header`, those that do not fit are closed
+fn write_files(
+    header: &mut MessageHeader,
+    files: Vec<Arc<OpenFile>>,
+    close_on_exec: bool,
+) -> core::result::Result<(), Errno> {
+    if files.is_empty() {
+        header.control_length = 0;
+        return Ok(());
+    }
+    let room = (header.control_length as usize).saturating_sub(CONTROL_HEADER_SIZE)
+        / core::mem::size_of::<u32>();
+    let descriptors = process::with_current_files(|table| {
+        let mut descriptors = Vec::new();
+        for file in files.iter().take(room) {
+            match table.insert(file.clone(), 0, close_on_exec) {
+                Ok(descriptor) => descriptors.push(descriptor as u32),
+                Err(_) => break,
+            }
+        }
+        descriptors
+    })
+    .unwrap_or_default();
+    if descriptors.len() < files.len() {
+        header.flags |= message::CONTROL_TRUNCATED;
+    }
+    // Closed outside of the process table, the descriptors hold the others
+    drop(files);
+    if descriptors.is_empty() {
+        header.control_length = 0;
+        return Ok(());
+    }
+
+    let size = CONTROL_HEADER_SIZE + descriptors.len() * core::mem::size_of::<u32>();
+    let message = ControlHeader {
+        length: size as u32,
+        level: SOCKET_LEVEL,
+        kind: RIGHTS,
+    };
+    let control = header.control as usize;
+    user_memory::write(control, message)?;
+    for (index, descriptor) in descriptors.iter().enumerate() {
+        user_memory::write(control + CONTROL_HEADER_SIZE + index * 4, *descriptor)?;
+    }
+    header.control_length = size as u32;
+    Ok(())
+}
+
+/// Receive in the vectors, with the sender and the files in the control
+/// buffer
+pub fn recvmsg(descriptor: usize, address: usize, flags: u32) -> Result {
+    let mut header: MessageHeader = user_memory::read(address)?;
+    let vectors = vectors_of(&header)?;
+    // Nothing bigger than a full queue comes at once
+    let length = length_of(&vectors)?.min(socket::CAPACITY);
+    let mut buffer = alloc::vec![0; length];
+    let dont_wait = flags & message::DONT_WAIT != 0;
+    let Received {
+        count,
+        size,
+        files,
+        sender,
+    } = with_socket(descriptor, |socket| socket.receive(&mut buffer, dont_wait))?;
+
+    let mut copied = 0;
+    for vector in vectors {
+        let part = (vector.length as usize).min(count - copied);
+        user_memory::slice_mut(vector.base as usize, part)?
+            .copy_from_slice(&buffer[copied..copied + part]);
+        copied += part;
+    }
+    header.flags = 0;
+    if count < size {
+        header.flags |= message::TRUNCATED;
+    }
+    if header.name != 0 {
+        let name = encode_address(sender.as_ref());
+        let part = name.len().min(header.name_length as usize);
+        user_memory::slice_mut(header.name as usize, part)?.copy_from_slice(&name[..part]);
+        header.name_length = name.len() as u32;
+    }
+    write_files(&mut header, files, flags & message::CLOSE_ON_EXEC != 0)?;
+    user_memory::write(address, header)?;
+    Ok(count)
+}
+
+pub fn shutdown(descriptor: usize, how: u32) -> Result {
+    let (read, write) = match how {
+        0 => (true, false),
+        1 => (false, true),
+        2 => (true, true),
+        _ => return Err(Errno::Invalid),
+    };
+    with_socket(descriptor, |socket| socket.shutdown(read, write))?;
+    Ok(0)
+}
+
+/// Run the socket call `call` with the arguments in the array at
+/// `arguments`
+pub fn socketcall(call: u32, arguments: usize) -> Result {
+    let count = *call::ARGUMENT_COUNTS
+        .get(call as usize)
+        .filter(|&&count| count != 0)
+        .ok_or(Errno::Invalid)?;
+    let mut values = [0; 6];
+    for (index, value) in values.iter_mut().take(count).enumerate() {
+        *value = user_memory::read::<u32>(arguments + index * 4)? as usize;
+    }
+    let [a, b, c, d, e, f] = values;
+    match call {
+        call::SOCKET => socket(a as u32, b as u32, c as u32),
+        call::BIND => bind(a, b, c),
+        call::CONNECT => connect(a, b, c),
+        call::LISTEN => listen(a, b as i32),
+        call::ACCEPT => accept4(a, b, c, 0),
+        call::ACCEPT4 => accept4(a, b, c, d as u32),
+        call::GETSOCKNAME => getsockname(a, b, c),
+        call::GETPEERNAME => getpeername(a, b, c),
+        call::SOCKETPAIR => socketpair(a as u32, b as u32, c as u32, d),
+        call::SEND => sendto(a, b, c, d as u32, 0, 0),
+        call::RECV => recvfrom(a, b, c, d as u32, 0, 0),
+        call::SENDTO => sendto(a, b, c, d as u32, e, f),
+        call::RECVFROM => recvfrom(a, b, c, d as u32, e, f),
+        call::SHUTDOWN => shutdown(a, b as u32),
+        call::SETSOCKOPT => setsockopt(a),
+        call::GETSOCKOPT => getsockopt(a, b as i32, c as i32, d, e),
+        call::SENDMSG => sendmsg(a, b, c as u32),
+        call::RECVMSG => recvmsg(a, b, c as u32),
+        _ => Err(Errno::Invalid),
+    }
+}
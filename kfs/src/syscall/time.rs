@@ -0,0 +1,245 @@
+//! Sleeps and alarms of the calling process, with the precision of a tick
+//!
+//! There is no wall clock yet, the real time clock counts from boot like the
+//! monotonic one.
+
+use super::{user_memory, Errno, Result};
+use crate::{
+    pit,
+    process::{self, Alarm},
+    signal, task,
+};
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+const NANOSECONDS_PER_TICK: u64 = NANOSECONDS_PER_SECOND / pit::FREQUENCY as u64;
+
+mod clock {
+    pub const REALTIME: u32 = 0;
+    pub const MONOTONIC: u32 = 1;
+}
+
+/// Flag of `clock_nanosleep`: the time is a deadline rather than a duration
+const TIMER_ABSTIME: u32 = 1;
+
+/// `ITIMER_REAL`, the only interval timer since the time processes run is
+/// not accounted
+const TIMER_REAL: u32 = 0;
+
+/// `struct timespec` of the 32 bit calls
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TimeSpec {
+    seconds: i32,
+    nanoseconds: i32,
+}
+
+/// `struct timeval`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct TimeVal {
+    seconds: i32,
+    microseconds: i32,
+}
+
+/// `struct itimerval`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct IntervalTimer {
+    interval: TimeVal,
+    value: TimeVal,
+}
+
+/// `struct __kernel_timespec`, of the calls with a `time64` suffix
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TimeSpec64 {
+    seconds: i64,
+    nanoseconds: i64,
+}
+
+impl TimeSpec {
+    fn ticks(self) -> core::result::Result<u64, Errno> {
+        to_ticks(self.seconds as i64, self.nanoseconds as i64)
+    }
+
+    fn from_ticks(ticks: u64) -> Self {
+        let (seconds, nanoseconds) = split(ticks);
+        Self {
+            seconds: seconds.try_into().unwrap_or(i32::MAX),
+            nanoseconds: nanoseconds as i32,
+        }
+    }
+}
+
+impl TimeVal {
+    fn ticks(self) -> core::result::Result<u64, Errno> {
+        let nanoseconds = self.microseconds as i64 * 1000;
+        to_ticks(self.seconds as i64, nanoseconds)
+    }
+
+    fn from_ticks(ticks: u64) -> Self {
+        let (seconds, nanoseconds) = split(ticks);
+        Self {
+            seconds: seconds.try_into().unwrap_or(i32::MAX),
+            microseconds: (nanoseconds / 1000) as i32,
+        }
+    }
+}
+
+impl TimeSpec64 {
+    fn ticks(self) -> core::result::Result<u64, Errno> {
+        // Programs only set the low half of the nanoseconds
+        to_ticks(self.seconds, self.nanoseconds as i32 as i64)
+    }
+
+    fn from_ticks(ticks: u64) -> Self {
+        let (seconds, nanoseconds) = split(ticks);
+        Self {
+            seconds,
+            nanoseconds,
+        }
+    }
+}
+
+/// Ticks in a time, rounded up so that sleeps are never shorter
+fn to_ticks(seconds: i64, nanoseconds: i64) -> core::result::Result<u64, Errno> {
+    let valid = seconds >= 0 && (0..NANOSECONDS_PER_SECOND as i64).contains(&nanoseconds);
+    if !valid {
+        return Err(Errno::Invalid);
+    }
+    let ticks = (nanoseconds as u64).div_ceil(NANOSECONDS_PER_TICK);
+    Ok((seconds as u64)
+        .saturating_mul(pit::FREQUENCY as u64)
+        .saturating_add(ticks))
+}
+
+/// Seconds and nanoseconds in `ticks`
+fn split(ticks: u64) -> (i64, i64) {
+    let frequency = pit::FREQUENCY as u64;
+    let seconds = ticks / frequency;
+    let nanoseconds = (ticks % frequency) * NANOSECONDS_PER_TICK;
+    (seconds as i64, nanoseconds as i64)
+}
+
+/// Sleep until the tick `deadline`, a signal interrupting it gives the
+/// remaining ticks
+fn sleep_until(deadline: u64) -> core::result::Result<(), u64> {
+    loop {
+        let now = pit::ticks();
+        if now >= deadline {
+            return Ok(());
+        }
+        if signal::is_interrupted() {
+            return Err(deadline - now);
+        }
+        task::block_until(deadline);
+    }
+}
+
+/// Sleep until the tick `deadline`, writing the time left at `remaining`
+/// when a signal interrupts a relative sleep
+fn sleep<T: Copy>(deadline: u64, absolute: bool, remaining: usize, time: fn(u64) -> T) -> Result {
+    match sleep_until(deadline) {
+        Ok(()) => Ok(0),
+        Err(left) => {
+            if !absolute && remaining != 0 {
+                user_memory::write(remaining, time(left))?;
+            }
+            Err(Errno::Interrupted)
+        }
+    }
+}
+
+/// Deadline of a `clock_nanosleep` of `ticks`
+fn clock_deadline(clock: u32, flags: u32, ticks: u64) -> core::result::Result<u64, Errno> {
+    if clock != clock::REALTIME && clock != clock::MONOTONIC {
+        return Err(Errno::Invalid);
+    }
+    match flags & TIMER_ABSTIME != 0 {
+        true => Ok(ticks),
+        false => Ok(pit::ticks().saturating_add(ticks)),
+    }
+}
+
+/// Duration in ticks of the `struct timespec` at `address`, none when null
+pub fn timeout(address: usize) -> core::result::Result<Option<u64>, Errno> {
+    match address {
+        0 => Ok(None),
+        _ => user_memory::read::<TimeSpec>(address)?.ticks().map(Some),
+    }
+}
+
+/// Like [timeout], with a 64 bit `struct timespec`
+pub fn timeout_time64(address: usize) -> core::result::Result<Option<u64>, Errno> {
+    match address {
+        0 => Ok(None),
+        _ => user_memory::read::<TimeSpec64>(address)?.ticks().map(Some),
+    }
+}
+
+pub fn nanosleep(duration: usize, remaining: usize) -> Result {
+    let ticks = user_memory::read::<TimeSpec>(duration)?.ticks()?;
+    let deadline = pit::ticks().saturating_add(ticks);
+    sleep(deadline, false, remaining, TimeSpec::from_ticks)
+}
+
+pub fn clock_nanosleep(clock: u32, flags: u32, time: usize, remaining: usize) -> Result {
+    let ticks = user_memory::read::<TimeSpec>(time)?.ticks()?;
+    let deadline = clock_deadline(clock, flags, ticks)?;
+    let absolute = flags & TIMER_ABSTIME != 0;
+    sleep(deadline, absolute, remaining, TimeSpec::from_ticks)
+}
+
+pub fn clock_nanosleep_time64(clock: u32, flags: u32, time: usize, remaining: usize) -> Result {
+    let ticks = user_memory::read::<TimeSpec64>(time)?.ticks()?;
+    let deadline = clock_deadline(clock, flags, ticks)?;
+    let absolute = flags & TIMER_ABSTIME != 0;
+    sleep(deadline, absolute, remaining, TimeSpec64::from_ticks)
+}
+
+/// Send `SIGALRM` in `seconds`, none with 0, return the seconds left before
+/// the previous alarm
+pub fn alarm(seconds: u32) -> Result {
+    let now = pit::ticks();
+    let deadline = match seconds {
+        0 => None,
+        _ => Some(now + seconds as u64 * pit::FREQUENCY as u64),
+    };
+    let alarm = deadline.map(|deadline| Alarm {
+        deadline,
+        interval: 0,
+    });
+    let previous = process::set_alarm(alarm)?;
+    let left = previous.map_or(0, |previous| {
+        previous
+            .deadline
+            .saturating_sub(now)
+            .div_ceil(pit::FREQUENCY as u64)
+    });
+    Ok(left as usize)
+}
+
+/// Send `SIGALRM` after the value of the `struct itimerval` at `new`, then
+/// every interval, none with a zero value, writing the previous alarm at
+/// `old`
+pub fn setitimer(which: u32, new: usize, old: usize) -> Result {
+    if which != TIMER_REAL {
+        return Err(Errno::Invalid);
+    }
+    let new = user_memory::read::<IntervalTimer>(new)?;
+    let (value, interval) = (new.value.ticks()?, new.interval.ticks()?);
+    let now = pit::ticks();
+    let alarm = (value != 0).then(|| Alarm {
+        deadline: now.saturating_add(value),
+        interval,
+    });
+    let previous = process::set_alarm(alarm)?;
+    if old != 0 {
+        let previous = previous.map_or(IntervalTimer::default(), |previous| IntervalTimer {
+            interval: TimeVal::from_ticks(previous.interval),
+            value: TimeVal::from_ticks(previous.deadline.saturating_sub(now)),
+        });
+        user_memory::write(old, previous)?;
+    }
+    Ok(0)
+}
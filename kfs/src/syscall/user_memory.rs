@@ -0,0 +1,129 @@
+//! Access to the memory of the calling program
+//!
+//! Pointers are checked against the address space before being
+//! dereferenced, the page fault handler then commits the pages. No other
+//! task shares the address space, so nothing unmaps them in between.
+
+use alloc::{string::String, vec::Vec};
+use core::{
+    mem::size_of,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use super::Errno;
+use crate::{
+    task,
+    vmm::{self, PhysicalAddress, PAGE_SIZE},
+};
+
+/// Longest path, terminating null included
+const PATH_MAX: usize = 4096;
+
+/// Check that the program may access the `size` bytes at `address`
+fn check(address: usize, size: usize, write: bool) -> Result<(), Errno> {
+    if size == 0 {
+        return Ok(());
+    }
+    let end = address.checked_add(size).ok_or(Errno::Fault)?;
+    if !vmm::is_user_address(address) || !vmm::is_user_address(end - 1) {
+        return Err(Errno::Fault);
+    }
+    let address_space = task::address_space().ok_or(Errno::Fault)?;
+    let address_space = address_space.lock();
+    match (vmm::page_align_down(address)..end)
+        .step_by(PAGE_SIZE)
+        .all(|page| address_space.is_user_accessible(page, write))
+    {
+        true => Ok(()),
+        false => Err(Errno::Fault),
+    }
+}
+
+pub fn slice<'a>(address: usize, size: usize) -> Result<&'a [u8], Errno> {
+    check(address, size, false)?;
+    match size {
+        0 => Ok(&[]),
+        _ => Ok(unsafe { core::slice::from_raw_parts(address as *const u8, size) }),
+    }
+}
+
+pub fn slice_mut<'a>(address: usize, size: usize) -> Result<&'a mut [u8], Errno> {
+    check(address, size, true)?;
+    match size {
+        0 => Ok(&mut []),
+        _ => Ok(unsafe { core::slice::from_raw_parts_mut(address as *mut u8, size) }),
+    }
+}
+
+/// `T` must be valid for any bit pattern
+pub fn read<T: Copy>(address: usize) -> Result<T, Errno> {
+    check(address, size_of::<T>(), false)?;
+    Ok(unsafe { (address as *const T).read_unaligned() })
+}
+
+pub fn write<T: Copy>(address: usize, value: T) -> Result<(), Errno> {
+    check(address, size_of::<T>(), true)?;
+    unsafe { (address as *mut T).write_unaligned(value) };
+    Ok(())
+}
+
+/// Physical address of the writable word at `address`
+///
+/// A write leaving it unchanged commits its page first, copying it if it is
+/// shared copy on write, so that the address stays the same.
+pub fn physical_word(address: usize) -> Result<PhysicalAddress, Errno> {
+    check(address, size_of::<u32>(), true)?;
+    unsafe { AtomicU32::from_ptr(address as *mut u32).fetch_or(0, Ordering::SeqCst) };
+    let address_space = task::address_space().ok_or(Errno::Fault)?;
+    let physical = address_space.lock().translate(address);
+    physical.ok_or(Errno::Fault)
+}
+
+/// Null terminated string at `address`, of at most `max_size` bytes
+/// terminator included
+pub fn string(address: usize, max_size: usize) -> Result<String, Errno> {
+    let mut bytes = Vec::new();
+    let mut current = address;
+    loop {
+        let chunk = slice(current, PAGE_SIZE - current % PAGE_SIZE)?;
+        let end = chunk.iter().position(|&byte| byte == 0);
+        bytes.extend_from_slice(&chunk[..end.unwrap_or(chunk.len())]);
+        if max_size <= bytes.len() {
+            return Err(Errno::TooBig);
+        }
+        if end.is_some() {
+            return Ok(String::from_utf8_lossy(&bytes).into_owned());
+        }
+        current += chunk.len();
+    }
+}
+
+/// Null terminated path at `address`
+pub fn path(address: usize) -> Result<String, Errno> {
+    string(address, PATH_MAX).map_err(|error| match error {
+        Errno::TooBig => Errno::NameTooLong,
+        error => error,
+    })
+}
+
+/// Strings of the null terminated array of pointers at `address`, such as
+/// `argv`, taking at most `max_size` bytes pointers included
+///
+/// A null `address` is an empty array.
+pub fn strings(address: usize, max_size: usize) -> Result<Vec<String>, Errno> {
+    let mut strings = Vec::new();
+    if address == 0 {
+        return Ok(strings);
+    }
+    let mut size = 0;
+    loop {
+        let pointer: u32 = read(address + strings.len() * size_of::<u32>())?;
+        size += size_of::<u32>();
+        if pointer == 0 {
+            return Ok(strings);
+        }
+        let string = string(pointer as usize, max_size.saturating_sub(size))?;
+        size += string.len() + 1;
+        strings.push(string);
+    }
+}
@@ -0,0 +1,773 @@
+//! Kernel threads and their preemptive scheduler
+//!
+//! Every task but the boot one runs on its own stack, taken from [vmalloc] so
+//! that an overflow hits a guard page. Switching saves the registers the
+//! calling convention preserves on the stack being left and the stack
+//! pointer in the task, see `switch.asm`.
+//!
+//! The highest priority ready task runs, tasks of the same priority take
+//! turns every time slice. The timer interrupt preempts the running task, so
+//! the scheduler lock is only ever taken with interrupts disabled, and
+//! nothing is allocated while holding it since the heap lock may belong to an
+//! interrupted task. A task holding a spin lock is only preempted once it
+//! released it, at a later tick, so that a task spinning on the lock can not
+//! keep it from running.
+
+use alloc::{boxed::Box, sync::Arc};
+use core::{fmt, mem::size_of, ptr::NonNull};
+
+use collections::{ArrayRing, ArrayVec};
+use sync::{SpinLock, SpinLockGuard};
+
+use crate::{
+    gdt,
+    interrupts::{self, Frame},
+    pit,
+    slab::{Cache, SlabBox},
+    timer, vmalloc,
+    vmm::{self, AddressSpace, PhysicalAddress, PAGE_SIZE},
+};
+
+pub const STACK_SIZE: usize = 4 * PAGE_SIZE;
+pub const MAX_TASKS: usize = 64;
+/// Ticks a task runs before letting the tasks of its priority run
+pub const DEFAULT_TIME_SLICE: u32 = 10;
+
+extern "C" {
+    /// Save the current context, store its stack pointer in `previous_esp`
+    /// and resume the context saved at `next_esp`
+    fn switch_to(previous_esp: *mut usize, next_esp: usize);
+}
+
+static SCHEDULER: SpinLock<Scheduler> = SpinLock::new(Scheduler::new());
+static DESCRIPTORS: Cache<Task> = Cache::new("task");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u32);
+
+impl From<u32> for TaskId {
+    fn from(id: u32) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Ready,
+    Running,
+    /// Waiting for the tick `until`, or for [wake]
+    Sleeping {
+        until: u64,
+    },
+    /// Waiting for [wake]
+    Blocked,
+    Exited,
+}
+
+/// Scheduling class, a task never runs while a task of a higher priority is
+/// ready
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub const COUNT: usize = 3;
+    /// From the highest to the lowest
+    pub const ALL: [Self; Self::COUNT] = [Self::High, Self::Normal, Self::Low];
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Statistics {
+    /// Ticks spent running
+    pub runtime: u64,
+    /// Times the task was switched to
+    pub switches: u64,
+    /// Processor the task last ran on
+    pub last_cpu: u32,
+}
+
+type Entry = Box<dyn FnOnce() + Send>;
+
+struct Task {
+    id: TaskId,
+    name: &'static str,
+    state: State,
+    priority: Priority,
+    time_slice: u32,
+    /// Ticks left in the current time slice
+    remaining: u32,
+    statistics: Statistics,
+    /// Saved by `switch_to` while the task is not running
+    esp: usize,
+    /// `None` for the boot task, which runs on the boot stack
+    stack: Option<NonNull<u8>>,
+    /// `None` for the idle task
+    entry: Option<Entry>,
+    /// Memory of the program run in ring 3, if any
+    address_space: Option<Arc<SpinLock<AddressSpace>>>,
+    /// Address space loaded while the task runs
+    root: PhysicalAddress,
+    /// Segment of [gdt::THREAD_LOCAL] while the task runs
+    thread_local: gdt::Descriptor,
+    /// Task blocked in [JoinHandle::join]
+    joiner: Option<TaskId>,
+    /// Nobody will join the task, it is forgotten as soon as it exits
+    detached: bool,
+    /// [wake] came while the task was not blocked, its next [block] returns
+    /// at once
+    wake_pending: bool,
+    /// A signal waits for the process of the task, see [set_interrupted]
+    interrupted: bool,
+}
+
+// SAFETY: the stack is only freed once the task exited
+unsafe impl Send for Task {}
+
+impl Task {
+    /// Where interrupts of ring 3 start their stack
+    fn kernel_stack_top(&self) -> Option<usize> {
+        self.stack.map(|stack| stack.as_ptr() as usize + STACK_SIZE)
+    }
+}
+
+/// What `switch_to` pops when it first switches to a task
+#[repr(C)]
+struct InitialFrame {
+    edi: usize,
+    esi: usize,
+    ebx: usize,
+    ebp: usize,
+    eip: usize,
+    /// Return address of [task_start], which never returns
+    return_address: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: &'static str,
+    pub state: State,
+    pub priority: Priority,
+    pub time_slice: u32,
+    pub statistics: Statistics,
+}
+
+struct Scheduler {
+    /// Boxed so that they do not move while a switch is in progress
+    tasks: ArrayVec<MAX_TASKS, SlabBox<Task>>,
+    /// One queue per priority, indexed by [Priority]
+    ready: [ArrayRing<MAX_TASKS, TaskId>; Priority::COUNT],
+    current: TaskId,
+    /// Runs when no other task is ready, it is never queued
+    idle: TaskId,
+    next_id: u32,
+}
+
+/// Only the bootstrap processor runs tasks
+const fn cpu_id() -> u32 {
+    0
+}
+
+impl Scheduler {
+    const fn new() -> Self {
+        Self {
+            tasks: ArrayVec::new(),
+            ready: [const { ArrayRing::new() }; Priority::COUNT],
+            current: TaskId(0),
+            idle: TaskId(0),
+            next_id: 0,
+        }
+    }
+
+    fn position(&self, id: TaskId) -> Option<usize> {
+        self.tasks.iter().position(|task| task.id == id)
+    }
+
+    fn task(&self, id: TaskId) -> &Task {
+        let index = self.position(id).expect("Unknown task");
+        &self.tasks[index]
+    }
+
+    fn task_mut(&mut self, id: TaskId) -> &mut Task {
+        let index = self.position(id).expect("Unknown task");
+        &mut self.tasks[index]
+    }
+
+    fn add(&mut self, mut task: SlabBox<Task>) -> Result<TaskId, SlabBox<Task>> {
+        let id = TaskId(self.next_id);
+        task.id = id;
+        self.tasks.push(task)?;
+        self.next_id += 1;
+        Ok(id)
+    }
+
+    /// Queue `id` after the ready tasks of its priority
+    fn enqueue(&mut self, id: TaskId) {
+        let idle = self.idle;
+        let task = self.task_mut(id);
+        task.state = State::Ready;
+        if id == idle {
+            return;
+        }
+        if task.remaining == 0 {
+            task.remaining = task.time_slice;
+        }
+        let queue = &mut self.ready[task.priority as usize];
+        queue.push_back(id).expect("Every task fits in a queue");
+    }
+
+    /// Queue the running task `id`, before the tasks of its priority if its
+    /// time slice is not over
+    fn preempt(&mut self, id: TaskId) {
+        let task = self.task(id);
+        match id != self.idle && task.remaining != 0 {
+            true => {
+                let priority = task.priority;
+                self.task_mut(id).state = State::Ready;
+                let queue = &mut self.ready[priority as usize];
+                queue.push_front(id).expect("Every task fits in a queue");
+            }
+            false => self.enqueue(id),
+        }
+    }
+
+    fn wake(&mut self, id: TaskId) {
+        let task = self.task_mut(id);
+        match task.state {
+            State::Blocked | State::Sleeping { .. } => self.enqueue(id),
+            State::Ready | State::Running => task.wake_pending = true,
+            State::Exited => (),
+        }
+    }
+
+    fn highest_ready_priority(&self) -> Option<Priority> {
+        Priority::ALL
+            .into_iter()
+            .find(|&priority| !self.ready[priority as usize].is_empty())
+    }
+
+    fn pop_ready(&mut self) -> TaskId {
+        match self.highest_ready_priority() {
+            Some(priority) => self.ready[priority as usize].pop_front().unwrap(),
+            None => self.idle,
+        }
+    }
+
+    /// Forget the task `id`, the caller releases it once unlocked
+    fn remove(&mut self, id: TaskId) -> Option<SlabBox<Task>> {
+        let index = self.position(id)?;
+        self.tasks.remove(index)
+    }
+}
+
+/// Free a forgotten task, never while holding the scheduler lock
+fn release(mut task: SlabBox<Task>) {
+    if let Some(stack) = task.stack.take() {
+        unsafe { vmalloc::vfree(stack) };
+    }
+}
+
+fn new_task(
+    name: &'static str,
+    priority: Priority,
+    entry: Option<Entry>,
+) -> Result<SlabBox<Task>, ()> {
+    let stack = vmalloc::vmalloc(STACK_SIZE).ok_or(())?;
+    let bottom = stack.as_ptr() as usize;
+    let top = bottom + STACK_SIZE;
+
+    let esp = top - size_of::<InitialFrame>();
+    unsafe {
+        (esp as *mut InitialFrame).write(InitialFrame {
+            edi: 0,
+            esi: 0,
+            ebx: 0,
+            ebp: 0,
+            eip: task_start as extern "C" fn() -> ! as usize,
+            return_address: 0,
+        });
+    }
+
+    let task = DESCRIPTORS.boxed(Task {
+        id: TaskId(0),
+        name,
+        state: State::Ready,
+        priority,
+        time_slice: DEFAULT_TIME_SLICE,
+        remaining: DEFAULT_TIME_SLICE,
+        statistics: Statistics::default(),
+        esp,
+        stack: Some(stack),
+        entry,
+        address_space: None,
+        root: vmm::kernel_root(),
+        thread_local: gdt::Descriptor::NULL,
+        joiner: None,
+        detached: false,
+        wake_pending: false,
+        interrupted: false,
+    });
+    task.ok_or_else(|| unsafe { vmalloc::vfree(stack) })
+}
+
+/// Turn the boot context into the first task and create the idle task
+///
+/// Requires the kernel heap, the timer may only start afterwards.
+pub fn init() {
+    DESCRIPTORS
+        .register()
+        .expect("Could not register the task cache");
+    let main = DESCRIPTORS.boxed(Task {
+        id: TaskId(0),
+        name: "main",
+        state: State::Running,
+        priority: Priority::Normal,
+        time_slice: DEFAULT_TIME_SLICE,
+        remaining: DEFAULT_TIME_SLICE,
+        statistics: Statistics::default(),
+        esp: 0,
+        stack: None,
+        entry: None,
+        address_space: None,
+        root: vmm::kernel_root(),
+        thread_local: gdt::Descriptor::NULL,
+        joiner: None,
+        detached: true,
+        wake_pending: false,
+        interrupted: false,
+    });
+    let main = main.expect("Could not create the main task");
+    let mut idle = new_task("idle", Priority::Low, None).expect("Could not create the idle task");
+    idle.detached = true;
+
+    let _interrupts = interrupts::disable();
+    let mut scheduler = SCHEDULER.lock();
+    let (Ok(main), Ok(idle)) = (scheduler.add(main), scheduler.add(idle)) else {
+        unreachable!("The task table is empty");
+    };
+    scheduler.current = main;
+    scheduler.idle = idle;
+}
+
+/// Run `entry` in a new task of [Priority::Normal]
+pub fn spawn<F>(name: &'static str, entry: F) -> Result<JoinHandle, ()>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_with_priority(name, Priority::Normal, entry)
+}
+
+pub fn spawn_with_priority<F>(
+    name: &'static str,
+    priority: Priority,
+    entry: F,
+) -> Result<JoinHandle, ()>
+where
+    F: FnOnce() + Send + 'static,
+{
+    reap();
+    let task = new_task(name, priority, Some(Box::new(entry)))?;
+    start(task)
+}
+
+/// Run the program of `address_space` in ring 3, from the context `frame`
+/// with the thread local storage segment `thread_local`
+pub fn spawn_user(
+    name: &'static str,
+    address_space: Arc<SpinLock<AddressSpace>>,
+    thread_local: gdt::Descriptor,
+    frame: Frame,
+) -> Result<JoinHandle, ()> {
+    reap();
+    let root = address_space.lock().root();
+    // SAFETY: the task runs with its address space active
+    let enter = move || unsafe { interrupts::enter(&frame) };
+    let mut task = new_task(name, Priority::Normal, Some(Box::new(enter)))?;
+    task.address_space = Some(address_space);
+    task.root = root;
+    task.thread_local = thread_local;
+    start(task)
+}
+
+/// Make the new `task` ready
+fn start(task: SlabBox<Task>) -> Result<JoinHandle, ()> {
+    let interrupts = interrupts::disable();
+    let mut scheduler = SCHEDULER.lock();
+    let id = match scheduler.add(task) {
+        Ok(id) => id,
+        Err(task) => {
+            drop(scheduler);
+            drop(interrupts);
+            release(task);
+            return Err(());
+        }
+    };
+    scheduler.enqueue(id);
+    Ok(JoinHandle { id })
+}
+
+extern "C" fn task_start() -> ! {
+    // Every switch happens with interrupts disabled
+    let entry = {
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current;
+        scheduler.task_mut(current).entry.take()
+    };
+    asm::enable_interrupts();
+    match entry {
+        Some(entry) => entry(),
+        None => idle(),
+    }
+    exit()
+}
+
+fn idle() {
+    loop {
+        reap();
+        asm::enable_interrupts_and_halt();
+    }
+}
+
+/// Run the next ready task, the current one must already be queued,
+/// sleeping, blocked or exited
+///
+/// Interrupts must be disabled, they stay so until the caller is resumed.
+fn switch(mut scheduler: SpinLockGuard<Scheduler>) {
+    let previous = scheduler.current;
+    let next = scheduler.pop_ready();
+    let task = scheduler.task_mut(next);
+    task.state = State::Running;
+    if next == previous {
+        return;
+    }
+    task.statistics.switches += 1;
+    task.statistics.last_cpu = cpu_id();
+    scheduler.current = next;
+
+    let previous_esp: *mut usize = &mut scheduler.task_mut(previous).esp;
+    let task = scheduler.task(next);
+    let next_esp = task.esp;
+    if let Some(top) = task.kernel_stack_top() {
+        gdt::set_kernel_stack(top);
+    }
+    gdt::set_thread_local(task.thread_local);
+    if asm::read_cr3() as PhysicalAddress != task.root {
+        // SAFETY: kernel stacks are mapped in every address space
+        unsafe { asm::write_cr3(task.root as u32) };
+    }
+    drop(scheduler);
+    // SAFETY: tasks are boxed and only freed once exited and switched away
+    // from, so `previous_esp` stays valid
+    unsafe { switch_to(previous_esp, next_esp) };
+}
+
+/// Account a tick to the running task and preempt it if needed, called by the
+/// timer interrupt
+pub fn tick() {
+    let preemptible = !sync::holds_spin_lock();
+    let mut scheduler = SCHEDULER.lock();
+    if scheduler.tasks.is_empty() {
+        return;
+    }
+    let current = scheduler.current;
+    let idle = scheduler.idle;
+    let task = scheduler.task_mut(current);
+    task.statistics.runtime += 1;
+    task.remaining = task.remaining.saturating_sub(1);
+    let (priority, remaining) = (task.priority, task.remaining);
+
+    let preempt = match scheduler.highest_ready_priority() {
+        None => false,
+        Some(_) if current == idle => true,
+        Some(ready) => priority < ready || (priority == ready && remaining == 0),
+    };
+    if preempt && preemptible {
+        scheduler.preempt(current);
+        switch(scheduler);
+    }
+}
+
+/// Free the exited tasks, now that none of them runs
+fn reap() {
+    let mut reaped = ArrayVec::<MAX_TASKS, SlabBox<Task>>::new();
+    let mut stacks = ArrayVec::<MAX_TASKS, NonNull<u8>>::new();
+    {
+        let _interrupts = interrupts::disable();
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current;
+        let mut index = 0;
+        while index < scheduler.tasks.len() {
+            let task = &mut scheduler.tasks[index];
+            if task.state != State::Exited || task.id == current {
+                index += 1;
+                continue;
+            }
+            match task.detached {
+                true => {
+                    let _ = reaped.push(scheduler.tasks.remove(index).unwrap());
+                }
+                false => {
+                    // The record stays until joined
+                    if let Some(stack) = task.stack.take() {
+                        let _ = stacks.push(stack);
+                    }
+                    index += 1;
+                }
+            }
+        }
+    }
+    while let Some(task) = reaped.pop() {
+        release(task);
+    }
+    while let Some(stack) = stacks.pop() {
+        unsafe { vmalloc::vfree(stack) };
+    }
+}
+
+/// Let the other ready tasks of the same priority run before coming back
+pub fn yield_now() {
+    let _interrupts = interrupts::disable();
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    scheduler.task_mut(current).remaining = 0;
+    scheduler.enqueue(current);
+    switch(scheduler);
+}
+
+/// Stop running the current task for at least `ticks` timer ticks
+pub fn sleep(ticks: u64) {
+    let until = pit::ticks() + ticks;
+    while pit::ticks() < until {
+        block_until(until);
+    }
+}
+
+pub fn sleep_milliseconds(milliseconds: u64) {
+    sleep(pit::milliseconds_to_ticks(milliseconds));
+}
+
+/// Stop running the current task until it is passed to [wake], unless it
+/// was since its last call
+pub fn block() {
+    let _interrupts = interrupts::disable();
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    let task = scheduler.task_mut(current);
+    if core::mem::take(&mut task.wake_pending) {
+        return;
+    }
+    task.state = State::Blocked;
+    switch(scheduler);
+}
+
+/// Like [block], also returning at the tick `deadline`
+pub fn block_until(deadline: u64) {
+    if deadline <= pit::ticks() {
+        let _interrupts = interrupts::disable();
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current;
+        scheduler.task_mut(current).wake_pending = false;
+        return;
+    }
+    let timer = timer::wake_at(deadline, current());
+    {
+        let _interrupts = interrupts::disable();
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current;
+        let task = scheduler.task_mut(current);
+        if !core::mem::take(&mut task.wake_pending) {
+            task.state = State::Sleeping { until: deadline };
+            switch(scheduler);
+        }
+    }
+    timer::cancel(timer);
+}
+
+/// Make the blocked or sleeping task `id` ready
+pub fn wake(id: TaskId) {
+    let _interrupts = interrupts::disable();
+    let mut scheduler = SCHEDULER.lock();
+    if scheduler.position(id).is_some() {
+        scheduler.wake(id);
+    }
+}
+
+/// Stop the current task
+pub fn exit() -> ! {
+    let _interrupts = interrupts::disable();
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    let task = scheduler.task_mut(current);
+    task.state = State::Exited;
+    let joiner = task.joiner.take();
+    if let Some(joiner) = joiner {
+        scheduler.wake(joiner);
+    }
+    switch(scheduler);
+    unreachable!("Exited task {current} was scheduled again");
+}
+
+/// Address space of the program the current task runs, if any
+pub fn address_space() -> Option<Arc<SpinLock<AddressSpace>>> {
+    let _interrupts = interrupts::disable();
+    let scheduler = SCHEDULER.lock();
+    scheduler.task(scheduler.current).address_space.clone()
+}
+
+/// Make the current task run the program of `address_space`, named `name`,
+/// in place of its previous one, which is freed
+pub fn replace_program(name: &'static str, address_space: Arc<SpinLock<AddressSpace>>) {
+    let root = address_space.lock().root();
+    let previous = {
+        let _interrupts = interrupts::disable();
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current;
+        let task = scheduler.task_mut(current);
+        task.name = name;
+        task.root = root;
+        task.thread_local = gdt::Descriptor::NULL;
+        gdt::set_thread_local(task.thread_local);
+        // SAFETY: kernel stacks are mapped in every address space
+        unsafe { asm::write_cr3(root as u32) };
+        task.address_space.replace(address_space)
+    };
+    drop(previous);
+}
+
+pub fn thread_local() -> gdt::Descriptor {
+    let _interrupts = interrupts::disable();
+    let scheduler = SCHEDULER.lock();
+    scheduler.task(scheduler.current).thread_local
+}
+
+/// Give the current task its own thread local storage segment
+pub fn set_thread_local(descriptor: gdt::Descriptor) {
+    let _interrupts = interrupts::disable();
+    let mut scheduler = SCHEDULER.lock();
+    let current = scheduler.current;
+    scheduler.task_mut(current).thread_local = descriptor;
+    gdt::set_thread_local(descriptor);
+}
+
+/// Record whether a signal waits for the process of the task `id`
+pub fn set_interrupted(id: TaskId, interrupted: bool) {
+    let _interrupts = interrupts::disable();
+    let mut scheduler = SCHEDULER.lock();
+    if let Some(index) = scheduler.position(id) {
+        scheduler.tasks[index].interrupted = interrupted;
+    }
+}
+
+/// Whether a signal waits for the process of the current task
+///
+/// Never blocks, so that the conditions of wait queues may check it.
+pub fn is_interrupted() -> bool {
+    let _interrupts = interrupts::disable();
+    let scheduler = SCHEDULER.lock();
+    scheduler.task(scheduler.current).interrupted
+}
+
+pub fn current() -> TaskId {
+    let _interrupts = interrupts::disable();
+    SCHEDULER.lock().current
+}
+
+pub fn set_priority(id: TaskId, priority: Priority) -> Result<(), ()> {
+    let _interrupts = interrupts::disable();
+    let mut scheduler = SCHEDULER.lock();
+    let index = scheduler.position(id).ok_or(())?;
+    // A queued task only changes queue the next time it is queued
+    scheduler.tasks[index].priority = priority;
+    Ok(())
+}
+
+/// Let `id` run `ticks` ticks before the other tasks of its priority
+pub fn set_time_slice(id: TaskId, ticks: u32) -> Result<(), ()> {
+    if ticks == 0 {
+        return Err(());
+    }
+    let _interrupts = interrupts::disable();
+    let mut scheduler = SCHEDULER.lock();
+    let index = scheduler.position(id).ok_or(())?;
+    scheduler.tasks[index].time_slice = ticks;
+    Ok(())
+}
+
+pub fn tasks() -> ArrayVec<MAX_TASKS, TaskInfo> {
+    let mut infos = ArrayVec::new();
+    let _interrupts = interrupts::disable();
+    for task in SCHEDULER.lock().tasks.iter() {
+        let _ = infos.push(TaskInfo {
+            id: task.id,
+            name: task.name,
+            state: task.state,
+            priority: task.priority,
+            time_slice: task.time_slice,
+            statistics: task.statistics,
+        });
+    }
+    infos
+}
+
+/// Owned permission to wait for a task, the task is detached when dropped
+pub struct JoinHandle {
+    id: TaskId,
+}
+
+impl JoinHandle {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// Block until the task exits
+    pub fn join(self) {
+        let id = self.id;
+        core::mem::forget(self);
+
+        let task = loop {
+            {
+                let _interrupts = interrupts::disable();
+                let mut scheduler = SCHEDULER.lock();
+                if scheduler.task(id).state == State::Exited {
+                    break scheduler.remove(id);
+                }
+                let current = scheduler.current;
+                scheduler.task_mut(id).joiner = Some(current);
+            }
+            // Woken by the exit, or by an earlier wake meant for something else
+            block();
+        };
+        if let Some(task) = task {
+            release(task);
+        }
+    }
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        let interrupts = interrupts::disable();
+        let mut scheduler = SCHEDULER.lock();
+        let task = match scheduler.task(self.id).state {
+            State::Exited => scheduler.remove(self.id),
+            _ => {
+                scheduler.task_mut(self.id).detached = true;
+                None
+            }
+        };
+        drop(scheduler);
+        drop(interrupts);
+        if let Some(task) = task {
+            release(task);
+        }
+    }
+}
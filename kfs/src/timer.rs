@@ -0,0 +1,244 @@
+//! Kernel timers: actions run once the tick reaches their deadline
+//!
+//! Pending timers are kept ordered by deadline, so that a tick only looks at
+//! the earliest ones. The timer interrupt wakes the tasks of [wake_at]
+//! itself. Callbacks may wait, for a [Mutex] for example, so they are run by
+//! the `timers` task, which the interrupt wakes, and periodic ones are put
+//! back once they ran. There is room for a timeout of every task, callbacks
+//! are limited.
+//!
+//! The heap can not be used with interrupts disabled, so the timers live in
+//! a fixed array: callbacks are boxed before the timers are locked, and
+//! dropped once they are unlocked.
+//!
+//! [Mutex]: crate::mutex::Mutex
+
+use alloc::boxed::Box;
+
+use collections::ArrayVec;
+use sync::SpinLock;
+
+use crate::{
+    interrupts, pit,
+    task::{self, TaskId},
+};
+
+/// Each task waits on one timer at most, see [task::block_until]
+const MAX_WAKES: usize = task::MAX_TASKS;
+/// Timers with a callback, the alarms of the processes among them
+const MAX_CALLS: usize = task::MAX_TASKS;
+const MAX_TIMERS: usize = MAX_WAKES + MAX_CALLS;
+
+/// Only locked with interrupts disabled, since the tick reads it
+static TIMERS: SpinLock<Timers> = SpinLock::new(Timers::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+type Callback = Box<dyn FnMut() + Send>;
+
+enum Action {
+    Wake(TaskId),
+    /// Taken out of the timer while it runs
+    Call(Option<Callback>),
+}
+
+struct Timer {
+    id: TimerId,
+    deadline: u64,
+    /// Ticks between two runs, 0 for a timer that runs once
+    period: u64,
+    action: Action,
+    /// Reached its deadline
+    due: bool,
+}
+
+struct Timers {
+    /// Earliest deadline first
+    timers: ArrayVec<MAX_TIMERS, Timer>,
+    /// Timers with a callback
+    calls: usize,
+    next_id: u64,
+    /// Task running the callbacks
+    worker: Option<TaskId>,
+}
+
+impl Timers {
+    const fn new() -> Self {
+        Self {
+            timers: ArrayVec::new(),
+            calls: 0,
+            next_id: 0,
+            worker: None,
+        }
+    }
+
+    fn position(&self, id: TimerId) -> Option<usize> {
+        self.timers.iter().position(|timer| timer.id == id)
+    }
+
+    /// Keep the timers ordered, `timer` after those of the same deadline
+    fn insert(&mut self, timer: Timer) -> Result<(), Timer> {
+        let index = self
+            .timers
+            .partition_point(|other| other.deadline <= timer.deadline);
+        self.timers.insert(index, timer)
+    }
+}
+
+/// Start the task running the callbacks
+///
+/// Requires the scheduler.
+pub fn init() {
+    let worker = task::spawn_with_priority("timers", task::Priority::High, run)
+        .expect("Could not start the timer task");
+    let _interrupts = interrupts::disable();
+    TIMERS.lock().worker = Some(worker.id());
+}
+
+/// Give `action` back when there is no room for it, to be dropped once the
+/// timers are unlocked
+fn add(deadline: u64, period: u64, action: Action) -> Result<TimerId, Action> {
+    let _interrupts = interrupts::disable();
+    let mut timers = TIMERS.lock();
+    let is_call = matches!(action, Action::Call(_));
+    if is_call && timers.calls == MAX_CALLS {
+        return Err(action);
+    }
+    let id = TimerId(timers.next_id);
+    timers.next_id += 1;
+    let timer = Timer {
+        id,
+        deadline,
+        period,
+        action,
+        due: false,
+    };
+    if let Err(timer) = timers.insert(timer) {
+        return Err(timer.action);
+    }
+    timers.calls += is_call as usize;
+    Ok(id)
+}
+
+/// Wake `task` at the tick `deadline`, see [task::wake]
+///
+/// The timer stays until cancelled, even once due.
+pub fn wake_at(deadline: u64, task: TaskId) -> TimerId {
+    match add(deadline, 0, Action::Wake(task)) {
+        Ok(id) => id,
+        Err(_) => panic!("Task {task} waits on several timers"),
+    }
+}
+
+/// Run `callback` once, at the tick `deadline`, unless too many callbacks
+/// are pending
+pub fn call_at(deadline: u64, callback: impl FnMut() + Send + 'static) -> Result<TimerId, ()> {
+    call_every(deadline, 0, callback)
+}
+
+/// Like [call_at], running `callback` again every `period` ticks after
+/// `deadline` until cancelled, or once if `period` is 0
+pub fn call_every(
+    deadline: u64,
+    period: u64,
+    callback: impl FnMut() + Send + 'static,
+) -> Result<TimerId, ()> {
+    let callback: Callback = Box::new(callback);
+    add(deadline, period, Action::Call(Some(callback))).map_err(drop)
+}
+
+/// Remove the timer `id`, return its next deadline if it was not reached
+pub fn cancel(id: TimerId) -> Option<u64> {
+    let timer = {
+        let _interrupts = interrupts::disable();
+        let mut timers = TIMERS.lock();
+        let index = timers.position(id)?;
+        let timer = timers.timers.remove(index)?;
+        if let Action::Call(_) = timer.action {
+            timers.calls -= 1;
+        }
+        Some(timer)
+    }?;
+    // A callback is dropped out of the lock
+    (!timer.due).then_some(timer.deadline)
+}
+
+/// Run the actions of the timers the tick `now` reached, called by the timer
+/// interrupt
+pub fn tick(now: u64) {
+    let mut timers = TIMERS.lock();
+    let timers = &mut *timers;
+    let mut calls = false;
+    for timer in timers.timers.iter_mut() {
+        if now < timer.deadline {
+            break;
+        }
+        if core::mem::replace(&mut timer.due, true) {
+            continue;
+        }
+        match timer.action {
+            Action::Wake(task) => task::wake(task),
+            Action::Call(_) => calls = true,
+        }
+    }
+    if let (true, Some(worker)) = (calls, timers.worker) {
+        task::wake(worker);
+    }
+}
+
+/// Body of the `timers` task
+fn run() {
+    loop {
+        let Some((id, mut callback)) = take_due_callback() else {
+            task::block();
+            continue;
+        };
+        callback();
+        // Dropped out of the lock, unless the timer runs again
+        drop(finish(id, callback));
+    }
+}
+
+/// Take the callback of a due timer, removing the timer unless periodic
+fn take_due_callback() -> Option<(TimerId, Callback)> {
+    let _interrupts = interrupts::disable();
+    let mut timers = TIMERS.lock();
+    let index = timers
+        .timers
+        .iter()
+        .position(|timer| timer.due && matches!(timer.action, Action::Call(Some(_))))?;
+    let timer = &mut timers.timers[index];
+    let id = timer.id;
+    let callback = match &mut timer.action {
+        Action::Call(callback) => callback.take()?,
+        Action::Wake(_) => return None,
+    };
+    if timer.period == 0 {
+        timers.timers.remove(index);
+        timers.calls -= 1;
+    }
+    Some((id, callback))
+}
+
+/// Schedule the next run of the periodic timer `id` whose `callback` ran,
+/// give `callback` back if the timer is gone
+fn finish(id: TimerId, callback: Callback) -> Option<Callback> {
+    let now = pit::ticks();
+    let _interrupts = interrupts::disable();
+    let mut timers = TIMERS.lock();
+    let Some(index) = timers.position(id) else {
+        // Ran once, or cancelled while running
+        return Some(callback);
+    };
+    let mut timer = timers.timers.remove(index)?;
+    // Late runs are not caught up
+    while timer.deadline <= now {
+        timer.deadline += timer.period;
+    }
+    timer.action = Action::Call(Some(callback));
+    timer.due = false;
+    // Takes the place it just left
+    let _ = timers.insert(timer);
+    None
+}
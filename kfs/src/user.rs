@@ -0,0 +1,96 @@
+//! Execution of programs in ring 3
+
+use crate::{
+    gdt,
+    interrupts::{self, Frame},
+    process::{self, ProcessId},
+    vmm::{self, AddressSpace, Flags, VirtualAddress, PAGE_SIZE},
+};
+
+/// Programs get their stack right below the kernel memory
+pub const STACK_TOP: VirtualAddress = vmm::USER_END;
+pub const STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Page under the stack where signal handlers return by default
+pub const SIGNAL_TRAMPOLINE: VirtualAddress = STACK_TOP - STACK_SIZE - PAGE_SIZE;
+/// Return of handlers without `SA_SIGINFO`, which pops the signal number
+pub const SIGNAL_RETURN: VirtualAddress = SIGNAL_TRAMPOLINE;
+/// Return of handlers with `SA_SIGINFO`
+pub const REAL_TIME_SIGNAL_RETURN: VirtualAddress = SIGNAL_TRAMPOLINE + 8;
+
+const SIGNAL_TRAMPOLINE_CODE: [u8; 15] = [
+    0x58, // pop eax
+    0xB8, 119, 0x00, 0x00, 0x00, // mov eax, 119 (sigreturn)
+    0xCD, 0x80, // int 0x80
+    0xB8, 173, 0x00, 0x00, 0x00, // mov eax, 173 (rt_sigreturn)
+    0xCD, 0x80, // int 0x80
+];
+
+/// Context of a program starting at `entry` with the stack pointer `stack`
+pub fn initial_frame(entry: VirtualAddress, stack: VirtualAddress) -> Frame {
+    let data = gdt::USER_DATA as u32;
+    Frame {
+        gs: data,
+        fs: data,
+        es: data,
+        ds: data,
+        edi: 0,
+        esi: 0,
+        ebp: 0,
+        kernel_esp: 0,
+        ebx: 0,
+        edx: 0,
+        ecx: 0,
+        eax: 0,
+        vector: 0,
+        error_code: 0,
+        eip: entry as u32,
+        cs: gdt::USER_CODE as u32,
+        eflags: Frame::DEFAULT_EFLAGS | asm::EFLAGS_INTERRUPTS,
+        user_esp: stack as u32,
+        user_ss: data,
+    }
+}
+
+/// Leave the kernel for the program of the active address space, at `entry`
+/// with the stack pointer `stack`
+pub fn enter(entry: VirtualAddress, stack: VirtualAddress) -> ! {
+    unsafe { interrupts::enter(&initial_frame(entry, stack)) }
+}
+
+/// Reserve the stack of a program
+pub fn reserve_stack(address_space: &mut AddressSpace) -> Result<(), vmm::Error> {
+    address_space.reserve(
+        STACK_TOP - STACK_SIZE,
+        STACK_SIZE,
+        Flags::USER | Flags::WRITABLE | Flags::NO_EXECUTE,
+    )
+}
+
+/// Map the code signal handlers return to, for the programs that give no
+/// restorer of their own
+pub fn map_signal_trampoline(address_space: &mut AddressSpace) -> Result<(), vmm::Error> {
+    address_space.reserve(SIGNAL_TRAMPOLINE, PAGE_SIZE, Flags::USER)?;
+    address_space.write(SIGNAL_TRAMPOLINE, &SIGNAL_TRAMPOLINE_CODE)
+}
+
+/// Counts down then runs `cli`, which is not allowed in ring 3
+const FAULTING_PROGRAM: [u8; 9] = [
+    0xB9, 0x00, 0x00, 0x00, 0x01, // mov ecx, 0x1000000
+    0x49, // dec ecx
+    0x75, 0xFD, // jnz -3
+    0xFA, // cli
+];
+
+/// Run a program that gets killed by a general protection fault, to check
+/// that the kernel survives it
+pub fn spawn_faulting_program() -> Result<ProcessId, process::Error> {
+    let mut address_space = AddressSpace::new()?;
+    let code = vmm::USER_START;
+    address_space
+        .reserve(code, PAGE_SIZE, Flags::USER)
+        .and_then(|()| address_space.write(code, &FAULTING_PROGRAM))
+        .and_then(|()| reserve_stack(&mut address_space))
+        .and_then(|()| map_signal_trampoline(&mut address_space))?;
+    process::spawn_user("faulting", address_space, code, STACK_TOP)
+}
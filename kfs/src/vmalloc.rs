@@ -0,0 +1,157 @@
+//! Virtually contiguous kernel allocations
+//!
+//! Areas are handed out from `[START, break)`, the break moving with [vbrk]
+//! as areas come and go, and backed when allocated by frames that need not be
+//! contiguous, like the heap they must not fault. An unmapped guard page
+//! follows every area so that an overflow faults instead of spilling into the
+//! next one.
+
+use alloc::collections::BTreeMap;
+use core::ptr::NonNull;
+
+use sync::SpinLock;
+
+use crate::{
+    heap,
+    vmm::{self, page_align_up, Flags, VirtualAddress, PAGE_SIZE},
+};
+
+pub const START: VirtualAddress = heap::END;
+pub const END: VirtualAddress = vmm::TEMPORARY_START;
+
+const GUARD_SIZE: usize = PAGE_SIZE;
+
+static ARENA: SpinLock<Arena> = SpinLock::new(Arena::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Statistics {
+    pub areas: usize,
+    /// Bytes reserved by areas, guard pages excluded
+    pub used: usize,
+    /// Bytes between the arena start and its break
+    pub size: usize,
+}
+
+struct Arena {
+    /// Size of every area indexed by start address
+    areas: BTreeMap<VirtualAddress, usize>,
+    brk: VirtualAddress,
+}
+
+impl Arena {
+    const fn new() -> Self {
+        Self {
+            areas: BTreeMap::new(),
+            brk: START,
+        }
+    }
+
+    /// First address after the last area and its guard page
+    fn areas_end(&self) -> VirtualAddress {
+        self.areas
+            .last_key_value()
+            .map_or(START, |(&start, &size)| start + size + GUARD_SIZE)
+    }
+
+    /// First fit search between the areas
+    fn find_gap(&self, size: usize) -> Option<VirtualAddress> {
+        let needed = size + GUARD_SIZE;
+        let mut candidate = START;
+        for (&start, &area_size) in &self.areas {
+            if candidate + needed <= start {
+                return Some(candidate);
+            }
+            candidate = start + area_size + GUARD_SIZE;
+        }
+        (candidate + needed <= self.brk).then_some(candidate)
+    }
+
+    fn move_break(&mut self, new_brk: VirtualAddress) -> Result<(), ()> {
+        match (self.areas_end()..=END).contains(&new_brk) {
+            true => {
+                self.brk = new_brk;
+                Ok(())
+            }
+            false => Err(()),
+        }
+    }
+
+    /// Place an area of `size` bytes, a multiple of the page size, below the
+    /// break
+    fn allocate(&mut self, size: usize) -> Option<NonNull<u8>> {
+        let start = self.find_gap(size)?;
+        vmm::map_allocated_range(start, size, Flags::WRITABLE | Flags::NO_EXECUTE).ok()?;
+
+        self.areas.insert(start, size);
+        NonNull::new(start as *mut u8)
+    }
+
+    fn deallocate(&mut self, start: VirtualAddress) {
+        let Some(size) = self.areas.remove(&start) else {
+            panic!("vfree of {start:#x} which was not returned by vmalloc");
+        };
+        vmm::unmap_and_free_range(start, size);
+    }
+}
+
+/// Allocate `size` bytes, rounded to whole pages, of virtually contiguous memory
+pub fn vmalloc(size: usize) -> Option<NonNull<u8>> {
+    let size = page_align_up(size.max(1));
+    if let Some(area) = ARENA.lock().allocate(size) {
+        return Some(area);
+    }
+    vbrk((size + GUARD_SIZE).try_into().ok()?).ok()?;
+    ARENA.lock().allocate(size)
+}
+
+/// # Safety
+/// `pointer` must come from [vmalloc] and not be freed yet
+///
+/// # Panic
+/// When `pointer` is not the start of an area
+pub unsafe fn vfree(pointer: NonNull<u8>) {
+    let unused = {
+        let mut arena = ARENA.lock();
+        arena.deallocate(pointer.as_ptr() as VirtualAddress);
+        arena.brk - arena.areas_end()
+    };
+    // An area placed meanwhile past the last one keeps the break
+    if unused > 0 {
+        let _ = vbrk(-(unused as isize));
+    }
+}
+
+/// Usable size of the area starting at `pointer`
+#[allow(dead_code, reason = "part of the vmalloc interface, no caller yet")]
+pub fn vsize(pointer: NonNull<u8>) -> Option<usize> {
+    ARENA
+        .lock()
+        .areas
+        .get(&(pointer.as_ptr() as VirtualAddress))
+        .copied()
+}
+
+/// Move the end of the address range vmalloc picks areas from by `increment`
+/// bytes, rounded to whole pages, and return the previous end
+///
+/// The range cannot shrink below the last area.
+pub fn vbrk(increment: isize) -> Result<VirtualAddress, ()> {
+    let mut arena = ARENA.lock();
+    let previous = arena.brk;
+    let size = increment.unsigned_abs().next_multiple_of(PAGE_SIZE);
+    let new_brk = match increment < 0 {
+        false => previous.checked_add(size),
+        true => previous.checked_sub(size),
+    };
+    arena.move_break(new_brk.ok_or(())?)?;
+    Ok(previous)
+}
+
+pub fn statistics() -> Statistics {
+    let arena = ARENA.lock();
+    Statistics {
+        areas: arena.areas.len(),
+        used: arena.areas.values().sum(),
+        size: arena.brk - START,
+    }
+}
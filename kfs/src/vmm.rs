@@ -0,0 +1,805 @@
+//! Virtual memory manager
+//!
+//! Every address space shares the kernel layout:
+//! - `[0, LOW_MEMORY_END)` identity maps the kernel image and the VGA buffer,
+//!   the first page is left unmapped to catch null dereferences. The kernel
+//!   `.text` is read-only and the other sections are not executable
+//! - `[PHYSICAL_WINDOW_START, PHYSICAL_WINDOW_END)` maps physical memory
+//!   linearly, it is how paging structures and frames are edited
+//! - `[KERNEL_DYNAMIC_START, KERNEL_DYNAMIC_END)` is left for kernel
+//!   allocations, its page tables are allocated once at boot so that every
+//!   address space sees the same mappings. They are backed as soon as they are
+//!   allocated: resolving a fault there would need the kernel address space,
+//!   which the faulting code may hold
+//! - `[TEMPORARY_START, KERNEL_DYNAMIC_END)` maps the frames outside of the
+//!   physical window while the kernel accesses them, see [with_frame]
+//!
+//! User memory is usually reserved as a [Region] rather than mapped: frames
+//! are only committed when a page fault hits one of its pages. Pages can also be
+//! shared copy-on-write between address spaces, the first write then gives
+//! the writer its own copy.
+//!
+//! When the CPU supports it, three level PAE paging is used instead of 32 bit
+//! paging, which brings [Flags::NO_EXECUTE]. Entries are then 64 bits wide and
+//! a page directory entry covers 2 MiB instead of 4 MiB. Physical addresses
+//! are then 64 bits wide, so memory above 4 GiB can be used.
+//!
+//! Based of [OSDev.org](https://wiki.osdev.org/Paging)
+
+mod access;
+mod copy_on_write;
+mod fault;
+mod program_break;
+mod region;
+
+pub use fault::{Fault, FaultError, FaultPolicy};
+pub use region::Region;
+
+use core::{
+    ops::{BitOr, BitOrAssign},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use collections::ArrayVec;
+use sync::SpinLock;
+
+use crate::{frame, linker};
+
+pub type VirtualAddress = usize;
+pub type PhysicalAddress = u64;
+
+pub const PAGE_SIZE: usize = 0x1000;
+
+pub const LOW_MEMORY_END: VirtualAddress = 0x0040_0000;
+
+pub const USER_START: VirtualAddress = LOW_MEMORY_END;
+pub const USER_END: VirtualAddress = PHYSICAL_WINDOW_START;
+
+pub const PHYSICAL_WINDOW_START: VirtualAddress = 0xC000_0000;
+pub const PHYSICAL_WINDOW_SIZE: usize = 0x3000_0000;
+pub const PHYSICAL_WINDOW_END: VirtualAddress = PHYSICAL_WINDOW_START + PHYSICAL_WINDOW_SIZE;
+
+pub const KERNEL_DYNAMIC_START: VirtualAddress = PHYSICAL_WINDOW_END;
+pub const KERNEL_DYNAMIC_END: VirtualAddress = 0xFFC0_0000;
+
+/// Frames mapped at once by [copy_frame]
+const TEMPORARY_SLOTS: usize = 2;
+pub const TEMPORARY_START: VirtualAddress = KERNEL_DYNAMIC_END - TEMPORARY_SLOTS * PAGE_SIZE;
+
+pub const MAX_REGION_COUNT: usize = 128;
+
+/// Memory covered by one page directory pointer table entry
+const PAE_DIRECTORY_SPAN: usize = 0x4000_0000;
+const PAE_DIRECTORY_COUNT: usize = 4;
+
+pub static KERNEL: SpinLock<AddressSpace> = SpinLock::new(AddressSpace::UNINITIALIZED);
+
+static PAGING_ENABLED: AtomicBool = AtomicBool::new(false);
+static PAE_ENABLED: AtomicBool = AtomicBool::new(false);
+static NX_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Root of [KERNEL], readable without taking its lock
+static KERNEL_ROOT: AtomicUsize = AtomicUsize::new(0);
+/// Frames holding paging structures, of every address space
+static TABLE_FRAMES: AtomicUsize = AtomicUsize::new(0);
+/// Page table of the temporary mappings, in the physical window
+static TEMPORARY_TABLE: AtomicUsize = AtomicUsize::new(0);
+/// Taken while the temporary mappings are in use
+static TEMPORARY: SpinLock<()> = SpinLock::new(());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    OutOfMemory,
+    Unaligned,
+    AlreadyMapped,
+    NotMapped,
+    Overlapping,
+    TooManyRegions,
+    /// The operation only applies to user memory
+    KernelMemory,
+    /// The address is outside of the range the operation applies to
+    OutOfRange,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flags(u64);
+
+#[allow(dead_code, reason = "every page table entry bit is named")]
+impl Flags {
+    pub const NONE: Self = Self(0);
+    pub const PRESENT: Self = Self(1 << 0);
+    pub const WRITABLE: Self = Self(1 << 1);
+    pub const USER: Self = Self(1 << 2);
+    pub const WRITE_THROUGH: Self = Self(1 << 3);
+    pub const CACHE_DISABLED: Self = Self(1 << 4);
+    pub const ACCESSED: Self = Self(1 << 5);
+    pub const DIRTY: Self = Self(1 << 6);
+    /// Directory entry mapping a 4 MiB, or 2 MiB with PAE, page instead of a
+    /// table
+    pub const HUGE: Self = Self(1 << 7);
+    pub const GLOBAL: Self = Self(1 << 8);
+    /// Ignored by the CPU, the read-only page is shared until written
+    pub const COPY_ON_WRITE: Self = Self(1 << 9);
+    /// Only enforced with PAE on CPUs supporting it, ignored otherwise
+    pub const NO_EXECUTE: Self = Self(1 << 63);
+}
+
+impl Flags {
+    const MASK: u64 = 0xFFF | Self::NO_EXECUTE.0;
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub const fn from_bits_truncate(bits: u64) -> Self {
+        Self(bits & Self::MASK)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self.union(rhs)
+    }
+}
+
+impl BitOrAssign for Flags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = self.union(rhs);
+    }
+}
+
+/// Entry of any paging structure, in the PAE format
+///
+/// Without PAE the upper half is dropped when the entry is stored.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Entry(u64);
+
+impl Entry {
+    const EMPTY: Self = Self(0);
+    const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+    const fn new(address: PhysicalAddress, flags: Flags) -> Self {
+        Self(address & Self::ADDRESS_MASK | flags.bits())
+    }
+
+    const fn address(self) -> PhysicalAddress {
+        self.0 & Self::ADDRESS_MASK
+    }
+
+    const fn flags(self) -> Flags {
+        Flags::from_bits_truncate(self.0)
+    }
+
+    const fn is_present(self) -> bool {
+        self.flags().contains(Flags::PRESENT)
+    }
+
+    const fn is_huge(self) -> bool {
+        self.flags().contains(Flags::HUGE)
+    }
+}
+
+/// Paging structure held in a frame
+#[derive(Clone, Copy)]
+struct Table(PhysicalAddress);
+
+impl Table {
+    fn entry_count() -> usize {
+        match is_pae_enabled() {
+            false => 1024,
+            true => 512,
+        }
+    }
+
+    /// # Safety
+    /// `address` must be the physical address of a paging structure
+    unsafe fn at(address: PhysicalAddress) -> Self {
+        Self(address)
+    }
+
+    fn get(self, index: usize) -> Entry {
+        let base = physical_to_virtual(self.0);
+        match is_pae_enabled() {
+            false => Entry(unsafe { (base as *const u32).add(index).read() } as u64),
+            true => Entry(unsafe { (base as *const u64).add(index).read() }),
+        }
+    }
+
+    fn set(self, index: usize, entry: Entry) {
+        let base = physical_to_virtual(self.0);
+        match (is_pae_enabled(), is_nx_enabled()) {
+            (false, _) => unsafe { (base as *mut u32).add(index).write(entry.0 as u32) },
+            (true, true) => unsafe { (base as *mut u64).add(index).write(entry.0) },
+            // The bit is reserved when NX is not enabled
+            (true, false) => unsafe {
+                let bits = entry.0 & !Flags::NO_EXECUTE.bits();
+                (base as *mut u64).add(index).write(bits)
+            },
+        }
+    }
+}
+
+pub fn is_pae_enabled() -> bool {
+    PAE_ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn is_nx_enabled() -> bool {
+    NX_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Memory covered by one page directory entry
+pub fn table_span() -> usize {
+    PAGE_SIZE * Table::entry_count()
+}
+
+fn directory_index(address: VirtualAddress) -> usize {
+    address / table_span() % Table::entry_count()
+}
+
+fn table_index(address: VirtualAddress) -> usize {
+    address / PAGE_SIZE % Table::entry_count()
+}
+
+pub fn is_user_address(address: VirtualAddress) -> bool {
+    (USER_START..USER_END).contains(&address)
+}
+
+/// Start of every page table span mapping kernel memory
+fn kernel_table_spans() -> impl Iterator<Item = VirtualAddress> {
+    let span = table_span();
+    (0..USER_START)
+        .step_by(span)
+        .chain((USER_END..=usize::MAX).step_by(span))
+}
+
+pub const fn is_page_aligned(address: usize) -> bool {
+    address.is_multiple_of(PAGE_SIZE)
+}
+
+pub const fn page_align_down(address: usize) -> usize {
+    address - address % PAGE_SIZE
+}
+
+pub const fn page_align_up(address: usize) -> usize {
+    page_align_down(address + PAGE_SIZE - 1)
+}
+
+/// Whether `address` is reachable through the physical window
+pub fn is_in_window(address: PhysicalAddress) -> bool {
+    address < PHYSICAL_WINDOW_SIZE as PhysicalAddress
+}
+
+/// Where a physical address of the physical window can be accessed from
+///
+/// Before paging is enabled this is the address itself.
+pub fn physical_to_virtual(address: PhysicalAddress) -> VirtualAddress {
+    match PAGING_ENABLED.load(Ordering::Relaxed) {
+        false => address as VirtualAddress,
+        true => {
+            assert!(
+                is_in_window(address),
+                "Physical address {address:#x} is outside of the physical window"
+            );
+            PHYSICAL_WINDOW_START + address as VirtualAddress
+        }
+    }
+}
+
+/// Physical address behind an address of the physical window
+pub fn window_to_physical(address: VirtualAddress) -> PhysicalAddress {
+    assert!(
+        (PHYSICAL_WINDOW_START..PHYSICAL_WINDOW_END).contains(&address),
+        "Address {address:#x} is outside of the physical window"
+    );
+    (address - PHYSICAL_WINDOW_START) as PhysicalAddress
+}
+
+/// Highest physical address paging structures can point to, plus one
+pub fn physical_limit() -> PhysicalAddress {
+    match is_pae_enabled() {
+        false => 1 << 32,
+        true => Entry::ADDRESS_MASK + 1,
+    }
+}
+
+fn temporary_table() -> Table {
+    unsafe { Table::at(TEMPORARY_TABLE.load(Ordering::Relaxed) as PhysicalAddress) }
+}
+
+/// Where `frame` can be accessed from, mapping it at the temporary `slot` if
+/// it is outside of the physical window
+///
+/// The temporary mappings must be locked.
+fn map_temporary(slot: usize, frame: PhysicalAddress) -> VirtualAddress {
+    if is_in_window(frame) {
+        return physical_to_virtual(frame);
+    }
+    let address = TEMPORARY_START + slot * PAGE_SIZE;
+    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE;
+    temporary_table().set(table_index(address), Entry::new(frame, flags));
+    asm::invlpg(address);
+    address
+}
+
+fn unmap_temporary(address: VirtualAddress) {
+    if (TEMPORARY_START..KERNEL_DYNAMIC_END).contains(&address) {
+        temporary_table().set(table_index(address), Entry::EMPTY);
+        asm::invlpg(address);
+    }
+}
+
+/// Run `f` with the address the content of `frame` is reachable at
+///
+/// Frames above the physical window are mapped for the duration of `f`,
+/// which must not access any other frame that way.
+pub fn with_frame<R>(frame: PhysicalAddress, f: impl FnOnce(*mut u8) -> R) -> R {
+    let _slots = TEMPORARY.lock();
+    let address = map_temporary(0, frame);
+    let result = f(address as *mut u8);
+    unmap_temporary(address);
+    result
+}
+
+/// Copy the content of the frame `source` to the frame `destination`
+pub fn copy_frame(source: PhysicalAddress, destination: PhysicalAddress) {
+    let _slots = TEMPORARY.lock();
+    let source = map_temporary(0, source);
+    let destination = map_temporary(1, destination);
+    unsafe {
+        core::ptr::copy_nonoverlapping(source as *const u8, destination as *mut u8, PAGE_SIZE);
+    }
+    unmap_temporary(source);
+    unmap_temporary(destination);
+}
+
+/// Allocate a frame filled with zeroes, above the physical window if possible
+pub fn allocate_zeroed_frame() -> Result<PhysicalAddress, Error> {
+    let frame = frame::allocate_high().ok_or(Error::OutOfMemory)?;
+    with_frame(frame, |page| unsafe {
+        core::ptr::write_bytes(page, 0, PAGE_SIZE)
+    });
+    Ok(frame)
+}
+
+/// Allocate a zeroed frame for a paging structure, which is edited through
+/// the physical window
+fn allocate_table() -> Result<PhysicalAddress, Error> {
+    let frame = frame::allocate().ok_or(Error::OutOfMemory)?;
+    unsafe {
+        core::ptr::write_bytes(physical_to_virtual(frame) as *mut u8, 0, PAGE_SIZE);
+    }
+    TABLE_FRAMES.fetch_add(1, Ordering::Relaxed);
+    Ok(frame)
+}
+
+fn free_table(frame: PhysicalAddress) {
+    TABLE_FRAMES.fetch_sub(1, Ordering::Relaxed);
+    frame::free(frame);
+}
+
+/// Frames used by paging structures, the overhead of paging
+pub fn table_frames() -> usize {
+    TABLE_FRAMES.load(Ordering::Relaxed)
+}
+
+pub struct AddressSpace {
+    /// Page directory, or page directory pointer table with PAE
+    root: PhysicalAddress,
+    /// Sorted and disjoint
+    regions: ArrayVec<MAX_REGION_COUNT, Region>,
+    fault_policy: FaultPolicy,
+    /// Where the heap of the program starts, right after its segments
+    heap_start: VirtualAddress,
+    /// End of the heap of the program, as moved by `brk`
+    program_break: VirtualAddress,
+}
+
+impl AddressSpace {
+    const UNINITIALIZED: Self = Self {
+        root: 0,
+        regions: ArrayVec::new(),
+        fault_policy: FaultPolicy::Panic,
+        heap_start: 0,
+        program_break: 0,
+    };
+
+    /// Create an address space that only contains the kernel mappings
+    pub fn new() -> Result<Self, Error> {
+        let space = Self {
+            root: allocate_table()?,
+            regions: ArrayVec::new(),
+            fault_policy: FaultPolicy::Terminate,
+            heap_start: USER_START,
+            program_break: USER_START,
+        };
+        let kernel = KERNEL.lock();
+        if is_pae_enabled() {
+            let (source, destination) = unsafe { (Table::at(kernel.root), Table::at(space.root)) };
+            for index in 0..PAE_DIRECTORY_COUNT {
+                // The directories above user space are shared as a whole
+                let entry = match USER_END <= index * PAE_DIRECTORY_SPAN {
+                    true => source.get(index),
+                    false => Entry::new(allocate_table()?, Flags::PRESENT),
+                };
+                destination.set(index, entry);
+            }
+        }
+        for address in kernel_table_spans() {
+            let (Some(source), Some(destination)) =
+                (kernel.directory(address), space.directory(address))
+            else {
+                continue;
+            };
+            let index = directory_index(address);
+            destination.set(index, source.get(index));
+        }
+        Ok(space)
+    }
+
+    /// Physical address to load in cr3
+    pub fn root(&self) -> PhysicalAddress {
+        self.root
+    }
+
+    pub fn is_active(&self) -> bool {
+        asm::read_cr3() as PhysicalAddress == self.root
+    }
+
+    /// # Safety
+    /// The currently executing code and stack must be mapped in `self`
+    pub unsafe fn activate(&self) {
+        unsafe { asm::write_cr3(self.root as u32) };
+    }
+
+    fn invalidate(&self, address: VirtualAddress) {
+        // Kernel tables are shared, so the active address space might see them
+        if self.is_active() || !is_user_address(address) {
+            asm::invlpg(address);
+        }
+    }
+
+    /// Page directory covering `address`
+    fn directory(&self, address: VirtualAddress) -> Option<Table> {
+        let root = unsafe { Table::at(self.root) };
+        match is_pae_enabled() {
+            false => Some(root),
+            true => {
+                let entry = root.get(address / PAE_DIRECTORY_SPAN);
+                entry
+                    .is_present()
+                    .then(|| unsafe { Table::at(entry.address()) })
+            }
+        }
+    }
+
+    fn table(&self, address: VirtualAddress) -> Option<Table> {
+        let entry = self.directory(address)?.get(directory_index(address));
+        match entry.is_present() && !entry.is_huge() {
+            false => None,
+            true => Some(unsafe { Table::at(entry.address()) }),
+        }
+    }
+
+    fn table_or_create(&mut self, address: VirtualAddress) -> Result<Table, Error> {
+        let directory = self.directory(address).ok_or(Error::NotMapped)?;
+        let index = directory_index(address);
+        let mut entry = directory.get(index);
+        if entry.is_huge() {
+            return Err(Error::AlreadyMapped);
+        }
+        if !entry.is_present() {
+            let mut flags = Flags::PRESENT | Flags::WRITABLE;
+            if is_user_address(address) {
+                // Access is restricted at the table entry level
+                flags |= Flags::USER;
+            }
+            entry = Entry::new(allocate_table()?, flags);
+            directory.set(index, entry);
+        }
+        Ok(unsafe { Table::at(entry.address()) })
+    }
+
+    /// Map the page at `virtual_address` to the frame at `physical_address`
+    pub fn map(
+        &mut self,
+        virtual_address: VirtualAddress,
+        physical_address: PhysicalAddress,
+        flags: Flags,
+    ) -> Result<(), Error> {
+        if !is_page_aligned(virtual_address) || !physical_address.is_multiple_of(PAGE_SIZE as u64) {
+            return Err(Error::Unaligned);
+        }
+        let table = self.table_or_create(virtual_address)?;
+        let index = table_index(virtual_address);
+        if table.get(index).is_present() {
+            return Err(Error::AlreadyMapped);
+        }
+        table.set(index, Entry::new(physical_address, flags | Flags::PRESENT));
+        Ok(())
+    }
+
+    /// Remove the mapping of the page at `virtual_address` and return the
+    /// frame it was mapped to
+    pub fn unmap(&mut self, virtual_address: VirtualAddress) -> Result<PhysicalAddress, Error> {
+        if !is_page_aligned(virtual_address) {
+            return Err(Error::Unaligned);
+        }
+        let table = self.table(virtual_address).ok_or(Error::NotMapped)?;
+        let index = table_index(virtual_address);
+        let entry = table.get(index);
+        if !entry.is_present() {
+            return Err(Error::NotMapped);
+        }
+        table.set(index, Entry::EMPTY);
+        self.invalidate(virtual_address);
+        Ok(entry.address())
+    }
+
+    pub fn translate(&self, virtual_address: VirtualAddress) -> Option<PhysicalAddress> {
+        let directory_entry = self
+            .directory(virtual_address)?
+            .get(directory_index(virtual_address));
+        if !directory_entry.is_present() {
+            return None;
+        }
+        if directory_entry.is_huge() {
+            let offset = virtual_address % table_span();
+            return Some(directory_entry.address() + offset as PhysicalAddress);
+        }
+        let table = unsafe { Table::at(directory_entry.address()) };
+        let entry = table.get(table_index(virtual_address));
+        match entry.is_present() {
+            false => None,
+            true => Some(entry.address() + (virtual_address % PAGE_SIZE) as PhysicalAddress),
+        }
+    }
+
+    /// Map `size` bytes starting at `virtual_address` to the frames starting
+    /// at `physical_address`
+    ///
+    /// Nothing stays mapped on failure.
+    pub fn map_range(
+        &mut self,
+        virtual_address: VirtualAddress,
+        physical_address: PhysicalAddress,
+        size: usize,
+        flags: Flags,
+    ) -> Result<(), Error> {
+        let size = page_align_up(size);
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            let physical_address = physical_address + offset as PhysicalAddress;
+            let result = self.map(virtual_address + offset, physical_address, flags);
+            if let Err(error) = result {
+                self.unmap_range(virtual_address, offset);
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove every mapping of the `size` bytes starting at `virtual_address`
+    pub fn unmap_range(&mut self, virtual_address: VirtualAddress, size: usize) {
+        for offset in (0..page_align_up(size)).step_by(PAGE_SIZE) {
+            let _ = self.unmap(virtual_address + offset);
+        }
+    }
+
+    /// Back the `size` bytes starting at `virtual_address` with newly
+    /// allocated frames, above the physical window if possible
+    ///
+    /// Nothing stays mapped on failure.
+    pub fn map_allocated_range(
+        &mut self,
+        virtual_address: VirtualAddress,
+        size: usize,
+        flags: Flags,
+    ) -> Result<(), Error> {
+        let size = page_align_up(size);
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            let mapped = frame::allocate_high()
+                .ok_or(Error::OutOfMemory)
+                .and_then(|frame| {
+                    self.map(virtual_address + offset, frame, flags)
+                        .inspect_err(|_| frame::free(frame))
+                });
+            if let Err(error) = mapped {
+                self.unmap_and_free_range(virtual_address, offset);
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove every mapping of the `size` bytes starting at `virtual_address`
+    /// and free the frames they were mapped to
+    pub fn unmap_and_free_range(&mut self, virtual_address: VirtualAddress, size: usize) {
+        for offset in (0..page_align_up(size)).step_by(PAGE_SIZE) {
+            if let Ok(frame) = self.unmap(virtual_address + offset) {
+                frame::free(frame);
+            }
+        }
+    }
+
+    /// Map the pages of `[start, end)` to the frames at the same address
+    pub fn identity_map_range(
+        &mut self,
+        start: usize,
+        end: usize,
+        flags: Flags,
+    ) -> Result<(), Error> {
+        let start = page_align_down(start);
+        self.map_range(start, start as PhysicalAddress, end - start, flags)
+    }
+}
+
+/// User pages hold a reference on their frame, which is dropped with them
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "Dropping the active address space");
+        for address in (USER_START..USER_END).step_by(table_span()) {
+            if let Some(table) = self.table(address) {
+                for index in 0..Table::entry_count() {
+                    let entry = table.get(index);
+                    if entry.is_present() {
+                        frame::free(entry.address());
+                    }
+                }
+                free_table(table.0);
+            }
+        }
+        if is_pae_enabled() {
+            let root = unsafe { Table::at(self.root) };
+            for index in 0..USER_END / PAE_DIRECTORY_SPAN {
+                let entry = root.get(index);
+                if entry.is_present() {
+                    free_table(entry.address());
+                }
+            }
+        }
+        free_table(self.root);
+    }
+}
+
+/// Build the kernel address space and enable paging, with PAE when the CPU
+/// supports it
+///
+/// Requires the frame allocator to be initialized.
+pub fn init() {
+    const CPUID_PSE_BIT: u32 = 1 << 3;
+    const CPUID_PAE_BIT: u32 = 1 << 6;
+    const CPUID_EXTENDED_FEATURES: u32 = 0x8000_0001;
+    const CPUID_NX_BIT: u32 = 1 << 20;
+    const CR4_PSE_BIT: u32 = 1 << 4;
+    const CR4_PAE_BIT: u32 = 1 << 5;
+    const CR0_WRITE_PROTECT_BIT: u32 = 1 << 16;
+    const CR0_PAGING_BIT: u32 = 1 << 31;
+    const EFER: asm::Msr = 0xC000_0080;
+    const EFER_NX_BIT: u64 = 1 << 11;
+
+    let features = asm::cpuid(1).edx;
+    let pae = features & CPUID_PAE_BIT != 0;
+    let nx = pae
+        && CPUID_EXTENDED_FEATURES <= asm::cpuid(0x8000_0000).eax
+        && asm::cpuid(CPUID_EXTENDED_FEATURES).edx & CPUID_NX_BIT != 0;
+    assert!(
+        pae || features & CPUID_PSE_BIT != 0,
+        "CPU supports neither PAE nor 4 MiB pages"
+    );
+    PAE_ENABLED.store(pae, Ordering::Relaxed);
+    NX_ENABLED.store(nx, Ordering::Relaxed);
+
+    let kernel_end = linker::kernel_end();
+    assert!(
+        kernel_end <= LOW_MEMORY_END,
+        "Kernel image ends at {kernel_end:#x}, after {LOW_MEMORY_END:#x}"
+    );
+
+    let mut kernel = KERNEL.lock();
+    kernel.root = allocate_table().expect("No frame left for the kernel directory");
+    if pae {
+        let root = unsafe { Table::at(kernel.root) };
+        for index in 0..PAE_DIRECTORY_COUNT {
+            let directory = allocate_table().expect("No frame left for the kernel directories");
+            root.set(index, Entry::new(directory, Flags::PRESENT));
+        }
+    }
+
+    // W^X: code is never writable, everything else is never executable. Gaps
+    // between sections get the permissions of the following one.
+    let (text, rodata, data) = (linker::text(), linker::rodata(), linker::data());
+    assert!(
+        text.end <= rodata.start && rodata.end <= data.start,
+        "Kernel sections are not laid out as text, rodata and data"
+    );
+    let low_memory = PAGE_SIZE..text.start;
+    let sections = [
+        (low_memory, Flags::WRITABLE | Flags::NO_EXECUTE),
+        (text.clone(), Flags::NONE),
+        (text.end..rodata.end, Flags::NO_EXECUTE),
+        (rodata.end..kernel_end, Flags::WRITABLE | Flags::NO_EXECUTE),
+    ];
+    for (range, flags) in sections {
+        kernel
+            .identity_map_range(range.start, range.end, flags)
+            .expect("Could not identity map the kernel");
+    }
+
+    for offset in (0..PHYSICAL_WINDOW_SIZE).step_by(table_span()) {
+        let address = PHYSICAL_WINDOW_START + offset;
+        let flags = Flags::PRESENT | Flags::WRITABLE | Flags::HUGE | Flags::NO_EXECUTE;
+        let directory = kernel.directory(address).expect("Kernel directory missing");
+        let entry = Entry::new(offset as PhysicalAddress, flags);
+        directory.set(directory_index(address), entry);
+    }
+
+    for address in (KERNEL_DYNAMIC_START..KERNEL_DYNAMIC_END).step_by(table_span()) {
+        kernel
+            .table_or_create(address)
+            .expect("No frame left for kernel page tables");
+    }
+    let temporary = kernel.table(TEMPORARY_START).expect("Kernel table missing");
+    TEMPORARY_TABLE.store(temporary.0 as usize, Ordering::Relaxed);
+
+    unsafe {
+        if nx {
+            asm::write_msr(EFER, asm::read_msr(EFER) | EFER_NX_BIT);
+        }
+        let cr4_bit = match pae {
+            true => CR4_PAE_BIT,
+            false => CR4_PSE_BIT,
+        };
+        asm::write_cr4(asm::read_cr4() | cr4_bit);
+        kernel.activate();
+        KERNEL_ROOT.store(kernel.root() as usize, Ordering::Relaxed);
+        // Read-only pages are enforced in ring 0 too
+        asm::write_cr0(asm::read_cr0() | CR0_WRITE_PROTECT_BIT | CR0_PAGING_BIT);
+    }
+    PAGING_ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn map_allocated_range(
+    virtual_address: VirtualAddress,
+    size: usize,
+    flags: Flags,
+) -> Result<(), Error> {
+    KERNEL
+        .lock()
+        .map_allocated_range(virtual_address, size, flags)
+}
+
+pub fn unmap_and_free_range(virtual_address: VirtualAddress, size: usize) {
+    KERNEL.lock().unmap_and_free_range(virtual_address, size)
+}
+
+/// Root of the kernel address space, for the contexts without user space
+pub fn kernel_root() -> PhysicalAddress {
+    KERNEL_ROOT.load(Ordering::Relaxed) as PhysicalAddress
+}
+
+/// Resolve `fault` in the address space owning the faulting address,
+/// `user_space` being the active one if it is not [KERNEL]
+pub fn handle_page_fault(
+    fault: &Fault,
+    user_space: Option<&SpinLock<AddressSpace>>,
+) -> Result<(), FaultError> {
+    match (is_user_address(fault.address), user_space) {
+        (true, Some(space)) => space.lock().handle_fault(fault),
+        (true, None) => Err(FaultError::InvalidAddress),
+        // Kernel pages are never accessible from ring 3
+        (false, _) if fault.user => Err(FaultError::ProtectionViolation),
+        (false, _) => KERNEL.lock().handle_fault(fault),
+    }
+}
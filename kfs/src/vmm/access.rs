@@ -0,0 +1,56 @@
+use super::{
+    allocate_zeroed_frame, page_align_down, table_index, AddressSpace, Error, Flags,
+    PhysicalAddress, VirtualAddress, PAGE_SIZE,
+};
+use crate::frame;
+
+impl AddressSpace {
+    /// Whether ring 3 may read, or write if `write` is set, the page at
+    /// `address`, once page faults committed it
+    pub fn is_user_accessible(&self, address: VirtualAddress, write: bool) -> bool {
+        let mapped = self
+            .table(address)
+            .map(|table| table.get(table_index(address)));
+        let flags = match (mapped, self.region(address)) {
+            (Some(entry), _) if entry.is_present() => entry.flags(),
+            (_, Some(region)) => region.flags,
+            _ => return false,
+        };
+        flags.contains(Flags::USER)
+            && (!write || flags.contains(Flags::WRITABLE) || flags.contains(Flags::COPY_ON_WRITE))
+    }
+
+    /// Frame of the page at `address`, committing it if it is reserved and
+    /// giving this address space its own copy if it is copy-on-write
+    fn commit(&mut self, address: VirtualAddress) -> Result<PhysicalAddress, Error> {
+        let page = page_align_down(address);
+        if self.translate(page).is_none() {
+            let flags = self.region(page).ok_or(Error::NotMapped)?.flags;
+            let frame = allocate_zeroed_frame()?;
+            self.map(page, frame, flags)
+                .inspect_err(|_| frame::free(frame))?;
+        }
+        self.break_copy_on_write(page, false)?;
+        self.translate(page).ok_or(Error::NotMapped)
+    }
+
+    /// Copy `data` to `address`, through the physical window or a temporary
+    /// mapping so that the address space does not need to be active
+    ///
+    /// Page protections are ignored, which is how read-only pages get their
+    /// content.
+    pub fn write(&mut self, address: VirtualAddress, data: &[u8]) -> Result<(), Error> {
+        let mut written = 0;
+        while written < data.len() {
+            let current = address + written;
+            let offset = current % PAGE_SIZE;
+            let size = (PAGE_SIZE - offset).min(data.len() - written);
+            let frame = self.commit(current)?;
+            super::with_frame(frame, |page| unsafe {
+                core::ptr::copy_nonoverlapping(data[written..].as_ptr(), page.add(offset), size);
+            });
+            written += size;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use super::{
+    is_user_address, page_align_down, page_align_up, table_index, AddressSpace, Entry, Error,
+    Flags, VirtualAddress, PAGE_SIZE,
+};
+use crate::frame;
+
+impl AddressSpace {
+    /// Share the pages mapped in the `size` bytes starting at `start` with
+    /// `other`, at the same addresses
+    ///
+    /// Writable pages become read-only in both address spaces and are copied
+    /// by the first one to write them. Pages shared before a failure stay
+    /// shared.
+    pub fn share_copy_on_write(
+        &mut self,
+        other: &mut AddressSpace,
+        start: VirtualAddress,
+        size: usize,
+    ) -> Result<(), Error> {
+        if !super::is_page_aligned(start) {
+            return Err(Error::Unaligned);
+        }
+        let end = start + page_align_up(size);
+        if start != end && !(is_user_address(start) && is_user_address(end - 1)) {
+            // Kernel tables are already shared by every address space
+            return Err(Error::KernelMemory);
+        }
+
+        for address in (start..end).step_by(PAGE_SIZE) {
+            let Some(table) = self.table(address) else {
+                continue;
+            };
+            let index = table_index(address);
+            let entry = table.get(index);
+            if !entry.is_present() {
+                continue;
+            }
+
+            let mut flags = entry.flags();
+            if flags.contains(Flags::WRITABLE) {
+                flags = flags.difference(Flags::WRITABLE) | Flags::COPY_ON_WRITE;
+                table.set(index, Entry::new(entry.address(), flags));
+                self.invalidate(address);
+            }
+            other.map(address, entry.address(), flags)?;
+            frame::add_reference(entry.address());
+        }
+        Ok(())
+    }
+
+    /// New address space with the same regions and pages, the pages being
+    /// shared copy-on-write
+    pub fn duplicate(&mut self) -> Result<AddressSpace, Error> {
+        let mut copy = AddressSpace::new()?;
+        copy.fault_policy = self.fault_policy;
+        copy.heap_start = self.heap_start;
+        copy.program_break = self.program_break;
+        for index in 0..self.regions.len() {
+            let region = self.regions[index];
+            copy.reserve(region.start, region.size(), region.flags)?;
+            self.share_copy_on_write(&mut copy, region.start, region.size())?;
+        }
+        Ok(copy)
+    }
+
+    /// Give the copy-on-write page at `address` to this address space alone,
+    /// copying it if it is still shared
+    ///
+    /// Returns `Ok(false)` when the page is not copy-on-write, or is not
+    /// accessible from ring 3 while `user` is set.
+    pub(super) fn break_copy_on_write(
+        &mut self,
+        address: VirtualAddress,
+        user: bool,
+    ) -> Result<bool, Error> {
+        let address = page_align_down(address);
+        let table = self.table(address).ok_or(Error::NotMapped)?;
+        let index = table_index(address);
+        let entry = table.get(index);
+        let flags = entry.flags();
+        if !entry.is_present()
+            || !flags.contains(Flags::COPY_ON_WRITE)
+            || (user && !flags.contains(Flags::USER))
+        {
+            return Ok(false);
+        }
+
+        let shared = entry.address();
+        let frame = match frame::references(shared) {
+            1 => shared,
+            _ => {
+                let copy = frame::allocate_high().ok_or(Error::OutOfMemory)?;
+                super::copy_frame(shared, copy);
+                frame::free(shared);
+                copy
+            }
+        };
+        let flags = flags.difference(Flags::COPY_ON_WRITE) | Flags::WRITABLE;
+        table.set(index, Entry::new(frame, flags));
+        self.invalidate(address);
+        Ok(true)
+    }
+}
@@ -0,0 +1,92 @@
+use super::{allocate_zeroed_frame, page_align_down, AddressSpace, Error, Flags, VirtualAddress};
+
+/// Page fault as reported by the CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub address: VirtualAddress,
+    /// The page was present, so the access broke its protection
+    pub present: bool,
+    pub write: bool,
+    /// The access happened in ring 3
+    pub user: bool,
+    pub instruction_fetch: bool,
+}
+
+impl Fault {
+    pub const fn from_error_code(address: VirtualAddress, error_code: u32) -> Self {
+        Self {
+            address,
+            present: error_code & (1 << 0) != 0,
+            write: error_code & (1 << 1) != 0,
+            user: error_code & (1 << 2) != 0,
+            instruction_fetch: error_code & (1 << 4) != 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultError {
+    /// The address is not part of any region
+    InvalidAddress,
+    /// The access is not allowed by the page or region flags
+    ProtectionViolation,
+    OutOfMemory,
+}
+
+/// What to do with faults an address space cannot resolve
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultPolicy {
+    /// Stop the kernel
+    Panic,
+    /// Report the fault so that the faulting context gets terminated
+    Terminate,
+}
+
+impl AddressSpace {
+    /// Commit a zeroed frame when `fault` hits a page that was reserved but
+    /// never accessed, otherwise apply the fault policy
+    ///
+    /// Faults of ring 3 are always reported, only the faulting task pays for
+    /// them.
+    pub fn handle_fault(&mut self, fault: &Fault) -> Result<(), FaultError> {
+        let result = self.resolve_fault(fault);
+        if let (Err(error), FaultPolicy::Panic, false) = (result, self.fault_policy, fault.user) {
+            panic!("Page fault at {:#x}: {error:?} ({fault:?})", fault.address);
+        }
+        result
+    }
+
+    fn resolve_fault(&mut self, fault: &Fault) -> Result<(), FaultError> {
+        if fault.present {
+            return self.resolve_protection_fault(fault);
+        }
+        let region = *self
+            .region(fault.address)
+            .ok_or(FaultError::InvalidAddress)?;
+        let allowed = (!fault.write || region.flags.contains(Flags::WRITABLE))
+            && (!fault.user || region.flags.contains(Flags::USER))
+            && (!fault.instruction_fetch || !region.flags.contains(Flags::NO_EXECUTE));
+        if !allowed {
+            return Err(FaultError::ProtectionViolation);
+        }
+
+        let frame = allocate_zeroed_frame().map_err(|_| FaultError::OutOfMemory)?;
+        self.map(page_align_down(fault.address), frame, region.flags)
+            .map_err(|_| {
+                crate::frame::free(frame);
+                FaultError::OutOfMemory
+            })
+    }
+
+    /// Only writes to copy-on-write pages are legitimate
+    fn resolve_protection_fault(&mut self, fault: &Fault) -> Result<(), FaultError> {
+        if !fault.write || fault.instruction_fetch {
+            return Err(FaultError::ProtectionViolation);
+        }
+        match self.break_copy_on_write(fault.address, fault.user) {
+            Ok(true) => Ok(()),
+            Ok(false) | Err(Error::NotMapped) => Err(FaultError::ProtectionViolation),
+            Err(_) => Err(FaultError::OutOfMemory),
+        }
+    }
+}
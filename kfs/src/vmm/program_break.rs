@@ -0,0 +1,32 @@
+use super::{is_user_address, page_align_up, AddressSpace, Error, Flags, VirtualAddress};
+
+impl AddressSpace {
+    /// Start the heap of the program at the page aligned `start`, empty
+    pub fn set_heap_start(&mut self, start: VirtualAddress) {
+        self.heap_start = start;
+        self.program_break = start;
+    }
+
+    pub fn program_break(&self) -> VirtualAddress {
+        self.program_break
+    }
+
+    /// Move the end of the heap to `end`, reserving or releasing the pages
+    /// in between
+    pub fn set_program_break(&mut self, end: VirtualAddress) -> Result<(), Error> {
+        if end < self.heap_start || !is_user_address(end) {
+            return Err(Error::OutOfRange);
+        }
+        let (previous_top, top) = (page_align_up(self.program_break), page_align_up(end));
+        match previous_top <= top {
+            true => self.reserve(
+                previous_top,
+                top - previous_top,
+                Flags::USER | Flags::WRITABLE | Flags::NO_EXECUTE,
+            )?,
+            false => self.unreserve(top, previous_top - top)?,
+        }
+        self.program_break = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+use super::{
+    page_align_up, table_index, AddressSpace, Entry, Error, Flags, PhysicalAddress, VirtualAddress,
+    MAX_REGION_COUNT, PAGE_SIZE,
+};
+use crate::frame;
+
+/// Range of virtual memory the owner of an address space may use, its pages
+/// are backed by zeroed frames the first time they are accessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: VirtualAddress,
+    pub end: VirtualAddress,
+    pub flags: Flags,
+}
+
+impl Region {
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+        (self.start..self.end).contains(&address)
+    }
+
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+impl AddressSpace {
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn region(&self, address: VirtualAddress) -> Option<&Region> {
+        self.regions.iter().find(|region| region.contains(address))
+    }
+
+    /// Highest `size` bytes of `[start, end)` that no region covers
+    pub fn find_free_range(
+        &self,
+        size: usize,
+        start: VirtualAddress,
+        end: VirtualAddress,
+    ) -> Option<VirtualAddress> {
+        let size = page_align_up(size);
+        let mut top = end;
+        for region in self.regions().iter().rev() {
+            if region.end <= start {
+                break;
+            }
+            if region.end < top && size <= top - region.end {
+                return Some(top - size);
+            }
+            top = top.min(region.start);
+        }
+        (start <= top && size <= top - start).then(|| top - size)
+    }
+
+    /// Declare the `size` bytes starting at `start` usable without
+    /// committing any frame
+    pub fn reserve(
+        &mut self,
+        start: VirtualAddress,
+        size: usize,
+        flags: Flags,
+    ) -> Result<(), Error> {
+        if !super::is_page_aligned(start) {
+            return Err(Error::Unaligned);
+        }
+        let end = start + page_align_up(size);
+        if start == end {
+            return Ok(());
+        }
+        if self
+            .regions
+            .iter()
+            .any(|region| region.start < end && start < region.end)
+        {
+            return Err(Error::Overlapping);
+        }
+
+        // Regions are kept sorted, neighbours with the same flags are merged
+        let index = self.regions.partition_point(|region| region.end <= start);
+        let merges_previous = index > 0 && {
+            let previous = &self.regions[index - 1];
+            previous.end == start && previous.flags == flags
+        };
+        let merges_next = self
+            .regions
+            .get(index)
+            .is_some_and(|next| next.start == end && next.flags == flags);
+
+        match (merges_previous, merges_next) {
+            (true, true) => {
+                let next_end = self.regions[index].end;
+                self.regions[index - 1].end = next_end;
+                self.regions.remove(index);
+            }
+            (true, false) => self.regions[index - 1].end = end,
+            (false, true) => self.regions[index].start = start,
+            (false, false) => self
+                .regions
+                .insert(index, Region { start, end, flags })
+                .map_err(|_| Error::TooManyRegions)?,
+        }
+        Ok(())
+    }
+
+    /// Withdraw the `size` bytes starting at `start` from the regions and
+    /// free the frames that were committed there
+    pub fn unreserve(&mut self, start: VirtualAddress, size: usize) -> Result<(), Error> {
+        if !super::is_page_aligned(start) {
+            return Err(Error::Unaligned);
+        }
+        let end = start + page_align_up(size);
+        let splits_region = self
+            .regions
+            .iter()
+            .any(|region| region.start < start && end < region.end);
+        if splits_region && self.regions.is_full() {
+            return Err(Error::TooManyRegions);
+        }
+
+        let mut index = 0;
+        while let Some(&region) = self.regions.get(index) {
+            if end <= region.start || region.end <= start {
+                index += 1;
+                continue;
+            }
+            let released_start = region.start.max(start);
+            let released_end = region.end.min(end);
+            self.unmap_and_free_range(released_start, released_end - released_start);
+
+            match (region.start < start, end < region.end) {
+                (false, false) => {
+                    self.regions.remove(index);
+                    continue;
+                }
+                (true, false) => self.regions[index].end = start,
+                (false, true) => self.regions[index].start = end,
+                (true, true) => {
+                    self.regions[index].end = start;
+                    let _ = self.regions.insert(
+                        index + 1,
+                        Region {
+                            start: end,
+                            end: region.end,
+                            flags: region.flags,
+                        },
+                    );
+                }
+            }
+            index += 1;
+        }
+        Ok(())
+    }
+
+    /// Reserve the `size` bytes starting at `start` in place of whatever was
+    /// reserved there, its first pages mapped to `frames`
+    ///
+    /// Nothing changes on failure, the frames are then left to the caller.
+    pub fn replace(
+        &mut self,
+        start: VirtualAddress,
+        size: usize,
+        flags: Flags,
+        frames: &[PhysicalAddress],
+    ) -> Result<(), Error> {
+        if !super::is_page_aligned(start) {
+            return Err(Error::Unaligned);
+        }
+        let end = start + page_align_up(size);
+        if end - start < frames.len() * PAGE_SIZE {
+            return Err(Error::OutOfRange);
+        }
+        if MAX_REGION_COUNT < self.region_count_after_replace(start, end, flags) {
+            return Err(Error::TooManyRegions);
+        }
+        // Mapping the frames can not fail once their tables exist
+        for index in 0..frames.len() {
+            self.table_or_create(start + index * PAGE_SIZE)?;
+        }
+
+        self.unreserve(start, size)?;
+        self.reserve(start, size, flags)?;
+        for (index, &frame) in frames.iter().enumerate() {
+            self.map(start + index * PAGE_SIZE, frame, flags)?;
+        }
+        Ok(())
+    }
+
+    /// Regions there would be once `[start, end)` is reserved with `flags` in
+    /// place of what it held
+    fn region_count_after_replace(
+        &self,
+        start: VirtualAddress,
+        end: VirtualAddress,
+        flags: Flags,
+    ) -> usize {
+        let mut count = 1;
+        for region in self.regions.iter() {
+            let merges = region.flags == flags;
+            if end <= region.start || region.end <= start {
+                let touches = region.end == start || region.start == end;
+                count += match touches && merges {
+                    true => 0,
+                    false => 1,
+                };
+                continue;
+            }
+            // What is left of the region on each side of the range
+            for remains in [region.start < start, end < region.end] {
+                if remains && !merges {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    /// Give the `size` bytes starting at `start` new flags, they must all be
+    /// reserved
+    ///
+    /// Committed pages follow, those still shared only become writable
+    /// through copy-on-write.
+    pub fn protect(
+        &mut self,
+        start: VirtualAddress,
+        size: usize,
+        flags: Flags,
+    ) -> Result<(), Error> {
+        if !super::is_page_aligned(start) {
+            return Err(Error::Unaligned);
+        }
+        let end = start + page_align_up(size);
+        let mut covered = start;
+        for region in self.regions.iter() {
+            if region.start <= covered && covered < region.end {
+                covered = region.end;
+            }
+        }
+        if covered < end {
+            return Err(Error::NotMapped);
+        }
+        let splits = self
+            .regions
+            .iter()
+            .filter(|region| {
+                (region.start < start && start < region.end)
+                    || (region.start < end && end < region.end)
+            })
+            .count();
+        if MAX_REGION_COUNT < self.regions.len() + splits {
+            return Err(Error::TooManyRegions);
+        }
+
+        let mut index = 0;
+        while let Some(&region) = self.regions.get(index) {
+            if end <= region.start || region.end <= start {
+                index += 1;
+                continue;
+            }
+            if region.start < start {
+                self.regions[index].end = start;
+                let _ = self.regions.insert(index + 1, Region { start, ..region });
+                index += 1;
+                continue;
+            }
+            if end < region.end {
+                self.regions[index].end = end;
+                let _ = self.regions.insert(
+                    index + 1,
+                    Region {
+                        start: end,
+                        ..region
+                    },
+                );
+            }
+            self.regions[index].flags = flags;
+            index += 1;
+        }
+        self.merge_regions();
+
+        for address in (start..end).step_by(PAGE_SIZE) {
+            let Some(table) = self.table(address) else {
+                continue;
+            };
+            let index = table_index(address);
+            let entry = table.get(index);
+            if !entry.is_present() {
+                continue;
+            }
+            let shared = frame::references(entry.address()) != 1;
+            let flags = match flags.contains(Flags::WRITABLE) && shared {
+                true => flags.difference(Flags::WRITABLE) | Flags::COPY_ON_WRITE,
+                false => flags,
+            };
+            table.set(index, Entry::new(entry.address(), flags | Flags::PRESENT));
+            self.invalidate(address);
+        }
+        Ok(())
+    }
+
+    /// Merge the neighbouring regions that have the same flags
+    fn merge_regions(&mut self) {
+        let mut index = 1;
+        while let Some(&region) = self.regions.get(index) {
+            let previous = self.regions[index - 1];
+            match previous.end == region.start && previous.flags == region.flags {
+                true => {
+                    self.regions[index - 1].end = region.end;
+                    self.regions.remove(index);
+                }
+                false => index += 1,
+            }
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Tasks blocked until a condition holds, rather than spinning on it
+//!
+//! A waiting task joins the queue, checks its condition then blocks. Whoever
+//! may have made the condition true wakes the queue, interrupt handlers
+//! included, and the woken tasks check their condition again. A wake coming
+//! between the check and the block is not lost, see [task::block].
+
+use collections::ArrayVec;
+use sync::SpinLock;
+
+use crate::{
+    interrupts, pit,
+    task::{self, TaskId},
+};
+
+pub struct WaitQueue {
+    /// Only locked with interrupts disabled, since handlers wake queues
+    waiters: SpinLock<ArrayVec<{ task::MAX_TASKS }, TaskId>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: SpinLock::new(ArrayVec::new()),
+        }
+    }
+
+    fn add(&self, id: TaskId) {
+        let _interrupts = interrupts::disable();
+        let mut waiters = self.waiters.lock();
+        if !waiters.contains(&id) {
+            // Every task fits
+            let _ = waiters.push(id);
+        }
+    }
+
+    fn remove(&self, id: TaskId) {
+        let _interrupts = interrupts::disable();
+        let mut waiters = self.waiters.lock();
+        let index = waiters.iter().position(|&waiter| waiter == id);
+        if let Some(index) = index {
+            waiters.remove(index);
+        }
+    }
+
+    /// Block until `condition` gives a value, it runs with interrupts enabled
+    /// each time the task is woken
+    pub fn wait_until<T>(&self, condition: impl FnMut() -> Option<T>) -> T {
+        self.wait(None, condition)
+            .expect("Waits without a deadline never time out")
+    }
+
+    /// Like [WaitQueue::wait_until], giving up after `ticks` timer ticks
+    pub fn wait_timeout<T>(&self, ticks: u64, condition: impl FnMut() -> Option<T>) -> Option<T> {
+        self.wait(Some(pit::ticks() + ticks), condition)
+    }
+
+    fn wait<T>(
+        &self,
+        deadline: Option<u64>,
+        mut condition: impl FnMut() -> Option<T>,
+    ) -> Option<T> {
+        let current = task::current();
+        loop {
+            self.add(current);
+            let value = condition();
+            let timed_out = deadline.is_some_and(|deadline| deadline <= pit::ticks());
+            if value.is_some() || timed_out {
+                self.remove(current);
+                return value;
+            }
+            match deadline {
+                Some(deadline) => task::block_until(deadline),
+                None => task::block(),
+            }
+        }
+    }
+
+    /// Wake the task waiting the longest, if any
+    pub fn wake_one(&self) {
+        let _interrupts = interrupts::disable();
+        let mut waiters = self.waiters.lock();
+        if let Some(id) = waiters.remove(0) {
+            task::wake(id);
+        }
+    }
+
+    pub fn wake_all(&self) {
+        let _interrupts = interrupts::disable();
+        let mut waiters = self.waiters.lock();
+        while let Some(id) = waiters.pop() {
+            task::wake(id);
+        }
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
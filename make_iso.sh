@@ -5,13 +5,23 @@ set -o errexit    # Used to exit upon error, avoiding cascading errors
 set -o pipefail   # Unveils hidden failures
 set -o nounset    # Exposes unset variables
 
-cargo build --release --target ./conf/i686-elf/i686-elf.json --bin kfs
+cargo build --release --target ./conf/i686-elf/i686-elf.json --bin kfs --features "${KFS_FEATURES:-}"
 
 export ISO_DIR=isofs/boot/
 mkdir -p $ISO_DIR/grub
 
 # Populate
 cp grub.cfg $ISO_DIR/grub/
+
+# Every file of programs/ becomes a boot module, run from the shell with
+# `run <name>`
+mkdir -p $ISO_DIR/programs
+for program in programs/*; do
+  [ -f "$program" ] || continue
+  name=$(basename "$program")
+  cp "$program" "$ISO_DIR/programs/$name"
+  sed -i "/multiboot2/a\\  module2 /boot/programs/$name $name" $ISO_DIR/grub/grub.cfg
+done
 rm -f $ISO_DIR/kfs
 ln ./target/i686-elf/release/kfs $ISO_DIR
 
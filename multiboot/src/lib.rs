@@ -0,0 +1,171 @@
+#![no_std]
+
+//! Boot information handed over by a multiboot2 compliant boot loader
+//!
+//! Based of the [specification](https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html#Boot-information-format)
+
+mod memory_map;
+
+pub use memory_map::{MemoryKind, MemoryMap, MemoryRegion};
+
+/// Value found in `eax` when the kernel is started by a multiboot2 boot loader
+pub const BOOTLOADER_MAGIC: u32 = 0x36D7_6289;
+
+#[derive(Clone, Copy)]
+pub struct Info<'a> {
+    bytes: &'a [u8],
+}
+
+impl Info<'static> {
+    /// # Safety
+    /// `address` must point to a boot information structure that stays
+    /// readable for the rest of the execution
+    pub unsafe fn from_address(address: usize) -> Self {
+        let total_size = unsafe { (address as *const u32).read() } as usize;
+        let bytes = unsafe { core::slice::from_raw_parts(address as *const u8, total_size) };
+        Self { bytes }
+    }
+}
+
+impl<'a> Info<'a> {
+    const HEADER_SIZE: usize = 8;
+
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn tags(&self) -> Tags<'a> {
+        Tags {
+            bytes: self.bytes.get(Self::HEADER_SIZE..).unwrap_or(&[]),
+        }
+    }
+
+    pub fn find(&self, kind: TagKind) -> Option<Tag<'a>> {
+        self.tags().find(|tag| tag.kind == kind)
+    }
+
+    pub fn command_line(&self) -> Option<&'a str> {
+        self.find(TagKind::CommandLine)
+            .and_then(|tag| c_str(tag.data))
+    }
+
+    pub fn boot_loader_name(&self) -> Option<&'a str> {
+        self.find(TagKind::BootLoaderName)
+            .and_then(|tag| c_str(tag.data))
+    }
+
+    pub fn memory_map(&self) -> Option<MemoryMap<'a>> {
+        self.find(TagKind::MemoryMap).and_then(MemoryMap::from_tag)
+    }
+
+    pub fn modules(&self) -> impl Iterator<Item = Module<'a>> {
+        self.tags()
+            .filter(|tag| tag.kind == TagKind::Module)
+            .filter_map(Module::from_tag)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagKind {
+    End,
+    CommandLine,
+    BootLoaderName,
+    Module,
+    BasicMemoryInfo,
+    BiosBootDevice,
+    MemoryMap,
+    Unknown(u32),
+}
+
+impl From<u32> for TagKind {
+    fn from(value: u32) -> Self {
+        use TagKind::*;
+        match value {
+            0 => End,
+            1 => CommandLine,
+            2 => BootLoaderName,
+            3 => Module,
+            4 => BasicMemoryInfo,
+            5 => BiosBootDevice,
+            6 => MemoryMap,
+            _ => Unknown(value),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Tag<'a> {
+    pub kind: TagKind,
+    /// Content following the tag type and size fields
+    pub data: &'a [u8],
+}
+
+pub struct Tags<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = Tag<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        const TAG_HEADER_SIZE: usize = 8;
+        const TAG_ALIGN: usize = 8;
+
+        let kind = TagKind::from(read_u32(self.bytes, 0)?);
+        let size = read_u32(self.bytes, 4)? as usize;
+        if kind == TagKind::End || size < TAG_HEADER_SIZE {
+            return None;
+        }
+        let data = self.bytes.get(TAG_HEADER_SIZE..size)?;
+
+        let next = size.next_multiple_of(TAG_ALIGN);
+        self.bytes = self.bytes.get(next..).unwrap_or(&[]);
+        Some(Tag { kind, data })
+    }
+}
+
+/// A file loaded in memory by the boot loader next to the kernel
+#[derive(Clone, Copy)]
+pub struct Module<'a> {
+    /// Physical address of the first byte
+    pub start: u32,
+    /// Physical address of the first byte after the module
+    pub end: u32,
+    pub command_line: &'a str,
+}
+
+impl<'a> Module<'a> {
+    fn from_tag(tag: Tag<'a>) -> Option<Self> {
+        Some(Self {
+            start: read_u32(tag.data, 0)?,
+            end: read_u32(tag.data, 4)?,
+            command_line: c_str(tag.data.get(8..)?).unwrap_or(""),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        (self.end - self.start) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+pub(crate) fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset + 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn c_str(bytes: &[u8]) -> Option<&str> {
+    let len = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).ok()
+}
@@ -0,0 +1,74 @@
+use crate::{read_u32, read_u64, Tag};
+
+#[derive(Clone, Copy)]
+pub struct MemoryMap<'a> {
+    entry_size: usize,
+    entries: &'a [u8],
+}
+
+impl<'a> MemoryMap<'a> {
+    const HEADER_SIZE: usize = 8;
+    const MIN_ENTRY_SIZE: usize = 24;
+
+    pub(crate) fn from_tag(tag: Tag<'a>) -> Option<Self> {
+        let entry_size = read_u32(tag.data, 0)? as usize;
+        if entry_size < Self::MIN_ENTRY_SIZE {
+            return None;
+        }
+        Some(Self {
+            entry_size,
+            entries: tag.data.get(Self::HEADER_SIZE..)?,
+        })
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = MemoryRegion> + 'a {
+        self.entries
+            .chunks_exact(self.entry_size)
+            .filter_map(MemoryRegion::from_bytes)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub length: u64,
+    pub kind: MemoryKind,
+}
+
+impl MemoryRegion {
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self {
+            base: read_u64(bytes, 0)?,
+            length: read_u64(bytes, 8)?,
+            kind: MemoryKind::from(read_u32(bytes, 16)?),
+        })
+    }
+
+    pub fn end(&self) -> u64 {
+        self.base.saturating_add(self.length)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    Available,
+    Reserved,
+    AcpiReclaimable,
+    Nvs,
+    Defective,
+    Unknown(u32),
+}
+
+impl From<u32> for MemoryKind {
+    fn from(value: u32) -> Self {
+        use MemoryKind::*;
+        match value {
+            1 => Available,
+            2 => Reserved,
+            3 => AcpiReclaimable,
+            4 => Nvs,
+            5 => Defective,
+            _ => Unknown(value),
+        }
+    }
+}
@@ -64,7 +64,7 @@ impl ControllerStatus {
     }
 
     pub fn bit(self, offset: usize) -> bool {
-        (self.0 << offset) & 1 != 0
+        (self.0 >> offset) & 1 != 0
     }
 
     pub fn system_reset(self) -> bool {
@@ -132,11 +132,11 @@ impl Configuration {
     }
 
     pub const fn bit(self, offset: usize) -> bool {
-        (self.0 << offset) & 1 != 0
+        (self.0 >> offset) & 1 != 0
     }
 
     pub const fn with_bit(self, offset: usize, value: bool) -> Self {
-        Self(self.0 & (1 << offset) | (value as u8) << offset)
+        Self(self.0 & !(1 << offset) | (value as u8) << offset)
     }
 
     pub const fn first_port_interrupt_is_enabled(&self) -> bool {
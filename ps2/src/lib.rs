@@ -53,6 +53,14 @@ impl Controller {
         );
     }
 
+    /// Raise IRQ 1 whenever the first port has a byte to read
+    pub fn enable_first_port_interrupt(&mut self) {
+        let configuration = self
+            .configuration()
+            .with_bit(Configuration::FIRST_PORT_INTERRUPT_ENABLED_BIT, true);
+        self.set_configuration(configuration);
+    }
+
     fn ready_for_write(&mut self) -> bool {
         !self.status().output_buffer_is_full() 
     }
@@ -93,6 +101,11 @@ impl Controller {
         }
     }
 
+    /// Next byte from any port, if one is available
+    pub fn try_read_without_origin(&mut self) -> Option<u8> {
+        self.direct_read_without_origin().ok()
+    }
+
     pub fn read_without_origin(&mut self) -> u8 {
         loop {
             match self.direct_read_without_origin() {
@@ -2,16 +2,27 @@
 
 use core::{
     cell::UnsafeCell,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
+/// Spin locks held by the code running, there is a single CPU
+static HELD: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the code running holds a spin lock
+///
+/// Such code must not be preempted, a task spinning on the lock would never
+/// let it run again.
+pub fn holds_spin_lock() -> bool {
+    HELD.load(Ordering::Relaxed) != 0
+}
+
 pub struct SpinLock<T: ?Sized> {
     locked: AtomicBool,
     data: UnsafeCell<T>,
 }
 
-unsafe impl<T: Sync> Sync for SpinLock<T> {}
-unsafe impl<T: Send> Send for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Sync for SpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for SpinLock<T> {}
 
 impl<T: ?Sized> SpinLock<T> {
     pub const fn new(data: T) -> Self
@@ -32,6 +43,7 @@ impl<T: ?Sized> SpinLock<T> {
         {
             core::hint::spin_loop();
         }
+        HELD.fetch_add(1, Ordering::Relaxed);
 
         SpinLockGuard(&self)
     }
@@ -74,5 +86,6 @@ impl<'a, T: ?Sized> Drop for SpinLockGuard<'a, T> {
         unsafe {
             self.0.unlock_unchecked();
         }
+        HELD.fetch_sub(1, Ordering::Relaxed);
     }
 }
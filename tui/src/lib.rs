@@ -1,12 +1,19 @@
 #![no_std]
+
+extern crate alloc;
+
 use vga::{Char, Color};
 
 mod logger;
 mod multi_screen;
+mod prompt;
+mod report;
 mod text_buffer;
 
 pub use logger::Logger;
 pub use multi_screen::MultiScreen;
+pub use prompt::Prompt;
+pub use report::Report;
 pub use text_buffer::TextBuffer;
 
 pub struct Screen {
@@ -0,0 +1,88 @@
+use alloc::string::String;
+
+use keyboard::ScanCode;
+
+use crate::{Logger, Rectangle, Screen, Widget};
+
+const PROMPT: &str = "> ";
+
+/// Command line below the log, submitting a line when Enter is pressed and
+/// calling `interrupt` on Ctrl+C
+pub struct Prompt {
+    keyboard: keyboard::Keyboard,
+    line: String,
+    submit: fn(&str),
+    interrupt: fn(),
+}
+
+impl Prompt {
+    pub fn new(keyboard: keyboard::Keyboard, submit: fn(&str), interrupt: fn()) -> Self {
+        Self {
+            keyboard,
+            line: String::new(),
+            submit,
+            interrupt,
+        }
+    }
+
+    fn is_control_pressed(&self) -> bool {
+        self.keyboard.is_pressed(ScanCode::LeftControl)
+            || self.keyboard.is_pressed(ScanCode::RightControl)
+    }
+}
+
+impl Widget for Prompt {
+    type Event = keyboard::Event;
+
+    fn render(&self, screen: &mut Screen, area: Rectangle) {
+        if area.height == 0 {
+            return;
+        }
+        let log_area = Rectangle {
+            height: area.height - 1,
+            ..area
+        };
+        Logger.render(screen, log_area);
+
+        let line = (area.y + area.height - 1) as usize;
+        // Keep the end of the line visible
+        let width = area.width as usize;
+        let text = PROMPT.bytes().chain(self.line.bytes());
+        let length = PROMPT.len() + self.line.len();
+        let skipped = (length + 1).saturating_sub(width);
+        let mut column = area.x as usize;
+        for byte in text.skip(skipped) {
+            screen.chars[line][column] = vga::Char::new(byte);
+            column += 1;
+        }
+        screen.cursor_pos = (line as u16, column as u16);
+    }
+
+    fn update(&mut self, event: Self::Event) {
+        if event.key_status.is_pressed() {
+            match event.scan_code {
+                ScanCode::Enter | ScanCode::KeypadEnter => {
+                    (self.submit)(&self.line);
+                    self.line.clear();
+                    return;
+                }
+                ScanCode::Backspace => {
+                    self.line.pop();
+                    return;
+                }
+                _ => (),
+            }
+        }
+        if let Some(text) = self.keyboard.feed(event) {
+            if self.is_control_pressed() && text.eq_ignore_ascii_case("c") {
+                (self.interrupt)();
+                self.line.clear();
+                return;
+            }
+            self.line.extend(
+                text.chars()
+                    .filter(|c| c.is_ascii() && !c.is_ascii_control()),
+            );
+        }
+    }
+}
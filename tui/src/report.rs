@@ -0,0 +1,55 @@
+use core::fmt::{self, Write};
+
+use crate::{Rectangle, Screen, Widget};
+
+/// Text generated anew on every render, such as live statistics
+pub struct Report {
+    generate: fn(&mut dyn Write) -> fmt::Result,
+}
+
+impl Report {
+    pub const fn new(generate: fn(&mut dyn Write) -> fmt::Result) -> Self {
+        Self { generate }
+    }
+}
+
+/// Writes text in an area of the screen, cutting what does not fit
+struct AreaWriter<'a> {
+    screen: &'a mut Screen,
+    area: Rectangle,
+    line: u16,
+    column: u16,
+}
+
+impl Write for AreaWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.line += 1;
+                self.column = 0;
+                continue;
+            }
+            if self.line < self.area.height && self.column < self.area.width {
+                let line = (self.area.y + self.line) as usize;
+                let column = (self.area.x + self.column) as usize;
+                self.screen.chars[line][column] = vga::Char::new(byte);
+            }
+            self.column += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Widget for Report {
+    type Event = keyboard::Event;
+
+    fn render(&self, screen: &mut Screen, area: Rectangle) {
+        let mut writer = AreaWriter {
+            screen,
+            area,
+            line: 0,
+            column: 0,
+        };
+        let _ = (self.generate)(&mut writer);
+    }
+}
@@ -1,19 +1,17 @@
-use collections::ArrayStr;
+use alloc::string::String;
 
 use crate::{Screen, Widget};
 
 pub struct TextBuffer {
     keyboard: keyboard::Keyboard,
-    content: ArrayStr<{Self::MAX_LEN}>,
+    content: String,
 }
 
 impl TextBuffer {
-    pub const MAX_LEN: usize = 0x100;
-
     pub fn new(keyboard: keyboard::Keyboard) -> Self {
         Self {
             keyboard,
-            content: ArrayStr::new(),
+            content: String::new(),
         }
     }
 }
@@ -50,8 +48,7 @@ impl Widget for TextBuffer {
 
     fn update(&mut self, event: Self::Event) {
         if let Some(text) = self.keyboard.feed(event) {
-            // Stop writting when out of bounds
-            let _ = self.content.push_str(text);
+            self.content.push_str(text);
         }
     }
 }